@@ -8,14 +8,15 @@ use std::io;
 
 use failure::Error;
 
+// every box in the file, as `heif-info -d` would show them
 fn main() -> Result<(), Error> {
-    println!(
-        "{:?}",
-        heifers::Heif::new(io::BufReader::new(fs::File::open(
-            env::args_os()
-                .nth(1)
-                .ok_or_else(|| format_err!("usage: filename"))?
-        )?))?
-    );
+    let file = fs::File::open(
+        env::args_os()
+            .nth(1)
+            .ok_or_else(|| format_err!("usage: filename"))?,
+    )?;
+    let boxes =
+        heifers::mpeg::tree::read_tree(io::BufReader::new(file), &heifers::Limits::default())?;
+    print!("{}", heifers::mpeg::tree::dump(&boxes)?);
     Ok(())
 }
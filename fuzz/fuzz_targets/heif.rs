@@ -0,0 +1,34 @@
+#![no_main]
+extern crate heifers;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use std::io::Cursor;
+
+// keep in step with `heif` in tests/fuzz.rs
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(boxes) = heifers::mpeg::tree::read_tree(bytes, &heifers::Limits::default()) {
+        let _ = heifers::mpeg::tree::dump(&boxes);
+    }
+
+    let _ = heifers::validate(Cursor::new(bytes));
+    let _ = heifers::Heif::new_lenient(Cursor::new(bytes), &mut heifers::Report::default());
+    let _ = heifers::Heif::from_source(&mut &bytes[..]);
+    if let Ok(editor) = heifers::Editor::new(Cursor::new(bytes)) {
+        let _ = editor.write(Cursor::new(bytes), Vec::new());
+    }
+
+    let heif = match heifers::Heif::new(Cursor::new(bytes)) {
+        Ok(heif) => heif,
+        Err(_) => return,
+    };
+
+    for item in heif.items() {
+        let _ = heif.bit_stream(item.id, Cursor::new(bytes), Vec::new());
+        let _ = heif.decode_uncompressed(Cursor::new(bytes), item.id);
+        let _ = heif.read_item_data_from(&mut &bytes[..], item.id);
+        let _ = heif.find_vps(item.id);
+        let _ = heif.find_sps(item.id);
+        let _ = heif.find_pps(item.id);
+    }
+});
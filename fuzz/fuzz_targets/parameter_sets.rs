@@ -0,0 +1,23 @@
+#![no_main]
+extern crate bitreader;
+extern crate heifers;
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use bitreader::BitReader;
+use heifers::hevc::pps;
+use heifers::hevc::sps;
+use heifers::hevc::vps;
+
+// keep in step with `parameter_sets` in tests/fuzz.rs
+fuzz_target!(|rbsp: &[u8]| {
+    if let Ok(parsed) = vps::video_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+    if let Ok(parsed) = sps::seq_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+    if let Ok(parsed) = pps::picture_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+});
@@ -0,0 +1,171 @@
+use cast::u64;
+use cast::u8;
+use cast::usize;
+use failure::Error;
+
+pub const OBU_SEQUENCE_HEADER: u8 = 1;
+pub const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+const OBU_EXTENSION_FLAG: u8 = 0b0000_0100;
+const OBU_HAS_SIZE_FIELD: u8 = 0b0000_0010;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Obu<'d> {
+    pub obu_type: u8,
+    // the one or two byte header, as found
+    pub header: &'d [u8],
+    pub payload: &'d [u8],
+}
+
+// Split data in the low overhead bitstream format into OBUs. Inside ISOBMFF, the last OBU
+// of a sample may leave out its size, and run to the end of the data.
+pub fn parse_obus(mut data: &[u8]) -> Result<Vec<Obu<'_>>, Error> {
+    let mut obus = Vec::new();
+    while !data.is_empty() {
+        let first = data[0];
+        ensure!(0 == first & 0x80, "OBU forbidden bit is set");
+
+        let header_len = if 0 != first & OBU_EXTENSION_FLAG {
+            2
+        } else {
+            1
+        };
+        ensure!(data.len() >= header_len, "truncated OBU header");
+        let (header, rest) = data.split_at(header_len);
+        data = rest;
+
+        let payload_len = if 0 != first & OBU_HAS_SIZE_FIELD {
+            usize(read_leb128(&mut data)?)
+        } else {
+            data.len()
+        };
+        ensure!(
+            data.len() >= payload_len,
+            "OBU claims {} bytes, but only {} remain",
+            payload_len,
+            data.len()
+        );
+        let (payload, rest) = data.split_at(payload_len);
+        data = rest;
+
+        obus.push(Obu {
+            obu_type: (first >> 3) & 0x0f,
+            header,
+            payload,
+        });
+    }
+
+    Ok(obus)
+}
+
+// always with a size field, as a bare stream needs one on every unit
+pub fn write_obu(into: &mut Vec<u8>, obu: &Obu) {
+    into.push(obu.header[0] | OBU_HAS_SIZE_FIELD);
+    into.extend_from_slice(&obu.header[1..]);
+    write_leb128(into, u64(obu.payload.len()));
+    into.extend_from_slice(obu.payload);
+}
+
+// A temporal unit that can be handed to a decoder on its own: a temporal delimiter, the
+// configuration's sequence header if the item data doesn't repeat it, then the item data.
+pub fn temporal_unit(config_obus: &[u8], item_data: &[u8]) -> Result<Vec<u8>, Error> {
+    let config = parse_obus(config_obus)?;
+    let data = parse_obus(item_data)?;
+
+    let mut unit = Vec::with_capacity(2 + config_obus.len() + item_data.len());
+    write_obu(
+        &mut unit,
+        &Obu {
+            obu_type: OBU_TEMPORAL_DELIMITER,
+            header: &[OBU_TEMPORAL_DELIMITER << 3],
+            payload: &[],
+        },
+    );
+
+    if !data.iter().any(|obu| OBU_SEQUENCE_HEADER == obu.obu_type) {
+        for obu in &config {
+            write_obu(&mut unit, obu);
+        }
+    }
+
+    // temporal delimiters aren't allowed in samples, but don't repeat one if it's there
+    for obu in data
+        .iter()
+        .filter(|obu| OBU_TEMPORAL_DELIMITER != obu.obu_type)
+    {
+        write_obu(&mut unit, obu);
+    }
+
+    Ok(unit)
+}
+
+pub fn read_leb128(from: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for i in 0..8 {
+        let (&byte, rest) = from
+            .split_first()
+            .ok_or_else(|| format_err!("truncated leb128"))?;
+        *from = rest;
+        value |= u64(byte & 0x7f) << (i * 7);
+        if 0 == byte & 0x80 {
+            return Ok(value);
+        }
+    }
+
+    bail!("leb128 longer than eight bytes")
+}
+
+pub fn write_leb128(into: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = u8(value & 0x7f).expect("seven bits fit in a u8");
+        value >>= 7;
+        if 0 == value {
+            into.push(byte);
+            return;
+        }
+        into.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_obus;
+    use super::read_leb128;
+    use super::temporal_unit;
+    use super::write_leb128;
+
+    #[test]
+    fn leb128() {
+        for &value in &[0u64, 1, 127, 128, 300, 0xffff_ffff] {
+            let mut buf = Vec::new();
+            write_leb128(&mut buf, value);
+            let mut from = buf.as_slice();
+            assert_eq!(value, read_leb128(&mut from).unwrap());
+            assert!(from.is_empty());
+        }
+
+        assert_eq!(2, read_leb128(&mut &[0x82, 0x00][..]).unwrap());
+        assert!(read_leb128(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn unit() {
+        let sequence_header = [0x0a, 0x02, 0xaa, 0xbb];
+        // a frame, without a size field, and with an extension byte
+        let frame = [0x34, 0x00, 0xcc, 0xdd, 0xee];
+
+        let unit = temporal_unit(&sequence_header, &frame).unwrap();
+        assert_eq!(
+            &[0x12, 0x00, 0x0a, 0x02, 0xaa, 0xbb, 0x36, 0x00, 0x03, 0xcc, 0xdd, 0xee][..],
+            unit.as_slice()
+        );
+
+        // the sequence header is already in the data, so isn't repeated
+        let mut data = sequence_header.to_vec();
+        data.extend_from_slice(&frame);
+        assert_eq!(unit, temporal_unit(&sequence_header, &data).unwrap());
+
+        assert_eq!(2, parse_obus(&data).unwrap().len());
+        assert!(parse_obus(&[0x0a, 0x05, 0x00]).is_err());
+    }
+}
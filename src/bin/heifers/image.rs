@@ -0,0 +1,324 @@
+use std::io::Write;
+use std::path::Path;
+
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+use byteorder::LE;
+use cast::u16;
+use cast::u32;
+use cast::u8;
+use cast::usize;
+use failure::Error;
+use heifers::mpeg::unci;
+use heifers::Picture;
+use heifers::Plane;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    // P5 or P6, by whether the picture is in colour; alpha is dropped
+    Pnm,
+}
+
+// interleaved samples, each of 8 or 16 bits
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub colour: bool,
+    pub alpha: bool,
+    pub depth: u8,
+    pub samples: Vec<u16>,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Format, Error> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        Ok(
+            match extension.as_ref().map(|extension| extension.as_str()) {
+                Some("png") => Format::Png,
+                Some("ppm") | Some("pgm") | Some("pnm") => Format::Pnm,
+                _ => bail!("can only convert to .png or .ppm: {:?}", path),
+            },
+        )
+    }
+}
+
+impl Image {
+    // greyscale or RGB, with or without alpha, and with every plane full size
+    pub fn from_picture(picture: &Picture) -> Result<Image, Error> {
+        let grey = picture
+            .plane(unci::COMPONENT_MONOCHROME)
+            .or_else(|| picture.plane(unci::COMPONENT_Y));
+        let colour = (
+            picture.plane(unci::COMPONENT_RED),
+            picture.plane(unci::COMPONENT_GREEN),
+            picture.plane(unci::COMPONENT_BLUE),
+        );
+        let mut planes = match (grey, colour) {
+            (_, (Some(red), Some(green), Some(blue))) => vec![red, green, blue],
+            (Some(grey), (None, None, None)) if picture.plane(unci::COMPONENT_CB).is_none() => {
+                vec![grey]
+            }
+            _ => bail!("only greyscale and RGB pictures can be converted"),
+        };
+        let colour = planes.len() > 1;
+        let alpha = picture.plane(unci::COMPONENT_ALPHA);
+        planes.extend(alpha);
+
+        for plane in &planes {
+            ensure!(
+                (picture.width, picture.height) == (plane.width, plane.height),
+                "subsampled planes can't be converted"
+            );
+            ensure!(
+                plane.bit_depth >= 1 && plane.bit_depth <= 16,
+                "can't convert {} bit samples",
+                plane.bit_depth
+            );
+        }
+
+        let depth = if planes.iter().all(|plane| plane.bit_depth <= 8) {
+            8
+        } else {
+            16
+        };
+
+        let count = usize(picture.width) * usize(picture.height);
+        let mut samples = Vec::with_capacity(count * planes.len());
+        for i in 0..count {
+            for plane in &planes {
+                samples.push(scale(plane, plane.samples[i], depth));
+            }
+        }
+
+        Ok(Image {
+            width: picture.width,
+            height: picture.height,
+            colour,
+            alpha: alpha.is_some(),
+            depth,
+            samples,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        (if self.colour { 3 } else { 1 }) + (if self.alpha { 1 } else { 0 })
+    }
+
+    pub fn write(&self, format: Format, into: &mut dyn Write) -> Result<(), Error> {
+        match format {
+            Format::Png => self.write_png(into),
+            Format::Pnm => self.write_pnm(into),
+        }
+    }
+
+    fn write_pnm(&self, into: &mut dyn Write) -> Result<(), Error> {
+        let magic = if self.colour { "P6" } else { "P5" };
+        let max = (1u32 << self.depth) - 1;
+        write!(into, "{}\n{} {}\n{}\n", magic, self.width, self.height, max)?;
+
+        let channels = self.channels();
+        let mut data = Vec::with_capacity(self.samples.len() * usize(self.depth / 8));
+        for pixel in self.samples.chunks(channels) {
+            let without_alpha = if self.alpha {
+                &pixel[..channels - 1]
+            } else {
+                pixel
+            };
+            self.push_samples(&mut data, without_alpha)?;
+        }
+        into.write_all(&data)?;
+        Ok(())
+    }
+
+    fn write_png(&self, into: &mut dyn Write) -> Result<(), Error> {
+        into.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let colour_type = match (self.colour, self.alpha) {
+            (false, false) => 0,
+            (false, true) => 4,
+            (true, false) => 2,
+            (true, true) => 6,
+        };
+        let mut header = Vec::new();
+        header.write_u32::<BE>(self.width)?;
+        header.write_u32::<BE>(self.height)?;
+        // no compression to speak of, no filtering, and no interlacing
+        header.extend_from_slice(&[self.depth, colour_type, 0, 0, 0]);
+        write_chunk(into, b"IHDR", &header)?;
+
+        let row = usize(self.width) * self.channels();
+        let mut raw = Vec::with_capacity(self.samples.len() * 2 + usize(self.height));
+        if 0 != row {
+            for samples in self.samples.chunks(row) {
+                raw.push(0);
+                self.push_samples(&mut raw, samples)?;
+            }
+        }
+        write_chunk(into, b"IDAT", &zlib_stored(&raw)?)?;
+        write_chunk(into, b"IEND", &[])
+    }
+
+    // big-endian, as both formats want
+    fn push_samples(&self, into: &mut Vec<u8>, samples: &[u16]) -> Result<(), Error> {
+        for &sample in samples {
+            match self.depth {
+                8 => into.push(u8(sample)?),
+                _ => into.write_u16::<BE>(sample)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// stretched to the full range of the output depth
+fn scale(plane: &Plane, sample: u16, depth: u8) -> u16 {
+    let from = (1u32 << plane.bit_depth) - 1;
+    let to = (1u32 << depth) - 1;
+    let sample = u32::from(sample).min(from);
+    u16((sample * to + from / 2) / from).unwrap_or(u16::max_value())
+}
+
+fn write_chunk(into: &mut dyn Write, chunk_type: &[u8; 4], data: &[u8]) -> Result<(), Error> {
+    into.write_u32::<BE>(u32(data.len())?)?;
+    into.write_all(chunk_type)?;
+    into.write_all(data)?;
+    let crc = crc32(crc32(0, chunk_type), data);
+    into.write_u32::<BE>(crc)?;
+    Ok(())
+}
+
+// a zlib stream of uncompressed deflate blocks; big, but it needs no compressor
+fn zlib_stored(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xffff * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(if last { 1 } else { 0 });
+        let len = u16(block.len())?;
+        out.write_u16::<LE>(len)?;
+        out.write_u16::<LE>(!len)?;
+        out.extend_from_slice(block);
+    }
+
+    out.write_u32::<BE>(adler32(data))?;
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// continues from a previous crc, or 0 to start
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if 0 != crc & 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use heifers::mpeg::unci;
+    use heifers::Picture;
+    use heifers::Plane;
+
+    use super::Format;
+    use super::Image;
+
+    fn picture(planes: &[(u16, u8)]) -> Picture {
+        Picture {
+            width: 2,
+            height: 1,
+            planes: planes
+                .iter()
+                .map(|&(component_type, bit_depth)| {
+                    let mut plane = Plane::new(component_type, bit_depth, 2, 1);
+                    plane.set(1, 0, (1 << bit_depth) - 1);
+                    plane
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xcbf4_3926, super::crc32(0, b"123456789"));
+        assert_eq!(0xae42_6082, super::crc32(0, b"IEND"));
+        assert_eq!(0x11e6_0398, super::adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data = vec![7u8; 0x1_0001];
+        let zlib = super::zlib_stored(&data).unwrap();
+        assert_eq!(data.len() + 2 + 5 * 2 + 4, zlib.len());
+        assert_eq!(&[0, 0xff, 0xff, 0, 0], &zlib[2..7]);
+        assert_eq!(&[1, 2, 0, 0xfd, 0xff], &zlib[0xffff + 7..0xffff + 12]);
+    }
+
+    #[test]
+    fn rgb_ppm() {
+        let image = Image::from_picture(&picture(&[
+            (unci::COMPONENT_RED, 8),
+            (unci::COMPONENT_GREEN, 8),
+            (unci::COMPONENT_BLUE, 8),
+            (unci::COMPONENT_ALPHA, 8),
+        ]))
+        .unwrap();
+        assert!(image.colour && image.alpha);
+        let mut ppm = Vec::new();
+        image.write(Format::Pnm, &mut ppm).unwrap();
+        assert_eq!(&b"P6\n2 1\n255\n\0\0\0\xff\xff\xff"[..], ppm.as_slice());
+    }
+
+    #[test]
+    fn deep_grey_png() {
+        let image = Image::from_picture(&picture(&[(unci::COMPONENT_Y, 10)])).unwrap();
+        assert_eq!((false, 16), (image.colour, image.depth));
+        assert_eq!(vec![0, 0xffff], image.samples);
+
+        let mut png = Vec::new();
+        image.write(Format::Png, &mut png).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        // the bit depth and colour type in the IHDR
+        assert_eq!(&[16, 0], &png[24..26]);
+        assert_eq!(b"IEND\xae\x42\x60\x82", &png[png.len() - 8..]);
+    }
+
+    #[test]
+    fn chroma_refused() {
+        assert!(Image::from_picture(&picture(&[
+            (unci::COMPONENT_Y, 8),
+            (unci::COMPONENT_CB, 8),
+            (unci::COMPONENT_CR, 8),
+        ]))
+        .is_err());
+    }
+}
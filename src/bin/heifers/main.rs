@@ -0,0 +1,432 @@
+#[macro_use]
+extern crate failure;
+extern crate byteorder;
+extern crate cast;
+extern crate heifers;
+#[macro_use]
+extern crate serde_json;
+
+mod image;
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+use byteorder::ByteOrder;
+use byteorder::BE;
+use cast::usize;
+use failure::Error;
+use heifers::mpeg;
+use heifers::mpeg::iprp::Property;
+use heifers::Heif;
+
+const USAGE: &str = "usage:
+  heifers info [--json] FILE
+  heifers extract [--item ID] [--raw | --stream | --exif | --xmp] FILE OUTPUT
+  heifers convert [--item ID] FILE OUTPUT.png|OUTPUT.ppm    (unci items only)
+
+extract writes the item's data as stored (--raw, the default), as the stream a decoder
+wants (--stream: Annex B for HEVC, OBUs for AV1), or the Exif or XMP describing the item.
+
+convert only decodes uncompressed (unci) images. There's no HEVC, AV1 or JPEG decoder,
+so for .heic and .avif files, feed `extract --stream` to an external decoder instead.
+
+The item defaults to the primary item, and an OUTPUT of - is stdout.";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Extract {
+    Raw,
+    Stream,
+    Exif,
+    Xmp,
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    json: bool,
+    item: Option<u32>,
+    extract: Option<Extract>,
+    paths: Vec<PathBuf>,
+}
+
+fn main() {
+    let mut args = env::args_os().skip(1);
+    let command = args.next().and_then(|command| command.into_string().ok());
+    let result = match command.as_ref().map(|command| command.as_str()) {
+        Some("-h") | Some("--help") | Some("help") => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(command) => parse_args(args).and_then(|args| run(command, &args)),
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("heifers: {}", e);
+        for cause in e.iter_causes() {
+            eprintln!("  caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+}
+
+fn parse_args<I: Iterator<Item = OsString>>(mut from: I) -> Result<Args, Error> {
+    let mut args = Args::default();
+    while let Some(arg) = from.next() {
+        let flag = match arg.to_str() {
+            Some(flag) if flag.starts_with("--") => flag.to_string(),
+            _ => {
+                args.paths.push(PathBuf::from(arg));
+                continue;
+            }
+        };
+
+        let extract = match flag.as_str() {
+            "--json" => {
+                args.json = true;
+                continue;
+            }
+            "--item" => {
+                let id = from
+                    .next()
+                    .and_then(|id| id.into_string().ok())
+                    .ok_or_else(|| format_err!("--item needs an item id"))?;
+                args.item = Some(
+                    id.parse()
+                        .map_err(|_| format_err!("invalid item id: {:?}", id))?,
+                );
+                continue;
+            }
+            "--raw" => Extract::Raw,
+            "--stream" => Extract::Stream,
+            "--exif" => Extract::Exif,
+            "--xmp" => Extract::Xmp,
+            _ => bail!("unrecognised option: {}", flag),
+        };
+        ensure!(
+            args.extract.is_none(),
+            "only one of --raw, --stream, --exif and --xmp"
+        );
+        args.extract = Some(extract);
+    }
+    Ok(args)
+}
+
+fn run(command: &str, args: &Args) -> Result<(), Error> {
+    match command {
+        "info" => {
+            ensure!(args.item.is_none(), "info shows every item");
+            ensure!(args.extract.is_none(), "info doesn't extract");
+            let (input,) = paths1(args)?;
+            let heif = Heif::new(io::BufReader::new(fs::File::open(input)?))?;
+            let stdout = io::stdout();
+            if args.json {
+                let mut out = stdout.lock();
+                serde_json::to_writer_pretty(&mut out, &info_json(&heif)?)?;
+                writeln!(out)?;
+            } else {
+                write_info(&heif, stdout.lock())?;
+            }
+        }
+        "extract" => {
+            ensure!(!args.json, "--json is only for info");
+            let (input, output) = paths2(args)?;
+            let mut file = io::BufReader::new(fs::File::open(input)?);
+            let heif = Heif::new(&mut file)?;
+            let item = args.item.unwrap_or_else(|| heif.primary_item_id());
+            let what = args.extract.unwrap_or(Extract::Raw);
+            with_output(output, |into| extract(&heif, &mut file, item, what, into))?;
+        }
+        "convert" => {
+            ensure!(!args.json, "--json is only for info");
+            ensure!(args.extract.is_none(), "convert always decodes");
+            let (input, output) = paths2(args)?;
+            let format = image::Format::from_path(output)?;
+            let mut file = io::BufReader::new(fs::File::open(input)?);
+            let heif = Heif::new(&mut file)?;
+            let item = args.item.unwrap_or_else(|| heif.primary_item_id());
+            let picture = decode(&heif, &mut file, item)?;
+            let image = image::Image::from_picture(&picture)?;
+            with_output(output, |into| image.write(format, into))?;
+        }
+        _ => bail!("unrecognised command: {}; try --help", command),
+    }
+    Ok(())
+}
+
+fn paths1(args: &Args) -> Result<(&Path,), Error> {
+    match args.paths.as_slice() {
+        [input] => Ok((input,)),
+        _ => bail!("expected one file name"),
+    }
+}
+
+fn paths2(args: &Args) -> Result<(&Path, &Path), Error> {
+    match args.paths.as_slice() {
+        [input, output] => Ok((input, output)),
+        _ => bail!("expected an input and an output file name"),
+    }
+}
+
+// `-` is stdout; a file is removed again if writing it fails, so nothing half-written,
+// or empty, is left behind
+fn with_output<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Error>,
+{
+    if Path::new("-") == path {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        write(&mut out)?;
+        return Ok(out.flush()?);
+    }
+
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    let written = write(&mut out).and_then(|()| Ok(out.flush()?));
+    if written.is_err() {
+        drop(out);
+        let _ = fs::remove_file(path);
+    }
+    written
+}
+
+fn write_info<W: Write>(heif: &Heif, mut into: W) -> Result<(), Error> {
+    let file_type = heif.file_type();
+    writeln!(into, "major brand: {}", file_type.major_brand)?;
+    writeln!(into, "minor version: {}", file_type.minor_version)?;
+    writeln!(into, "compatible brands: {}", join(file_type.brands.iter()))?;
+    writeln!(into, "primary item: {}", heif.primary_item_id())?;
+
+    for item in heif.items() {
+        writeln!(into)?;
+        let mut flags = String::new();
+        if item.id == heif.primary_item_id() {
+            flags.push_str(", primary");
+        }
+        if item.info.hidden {
+            flags.push_str(", hidden");
+        }
+        writeln!(
+            into,
+            "item {}: {}, {} bytes{}",
+            item.id, item.info.item_type, item.data_size, flags
+        )?;
+        if !item.info.item_name.is_empty() {
+            writeln!(into, "  name: {}", item.info.item_name)?;
+        }
+        if let Some(ref content_type) = item.info.content_type {
+            writeln!(into, "  content type: {}", content_type)?;
+        }
+        if !item.properties.is_empty() {
+            let properties = item.properties.iter().map(|&property| describe(property));
+            writeln!(into, "  properties: {}", join(properties))?;
+        }
+        for reference in heif.references(item.id) {
+            writeln!(
+                into,
+                "  reference: {} to {}",
+                reference.reference_type,
+                join(reference.to_item_ids.iter())
+            )?;
+        }
+    }
+
+    for group in heif.entity_groups() {
+        writeln!(into)?;
+        writeln!(
+            into,
+            "group {}: {} of {}",
+            group.group_id,
+            group.grouping_type,
+            join(group.entity_ids.iter())
+        )?;
+    }
+    Ok(())
+}
+
+fn join<T: ToString, I: Iterator<Item = T>>(items: I) -> String {
+    items
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// the box type, and the values of the simple ones
+fn describe(property: &Property) -> String {
+    match property {
+        Property::HvcCodecSettings(_) => "hvcC".to_string(),
+        Property::Av1CodecSettings(_) => "av1C".to_string(),
+        Property::JpegPrefix(_) => "jpgC".to_string(),
+        Property::ComponentDefinition(_) => "cmpd".to_string(),
+        Property::UncompressedConfig(_) => "uncC".to_string(),
+        Property::Size(ispe) => format!("ispe {}x{}", ispe.width, ispe.height),
+        Property::Rotation(irot) => format!("irot {}", u32::from(irot.angle) * 90),
+        Property::Mirror(imir) => format!("imir {}", imir.axis),
+        Property::CleanAperture(clap) => format!(
+            "clap {}/{}x{}/{}",
+            clap.width_n, clap.width_d, clap.height_n, clap.height_d
+        ),
+        Property::Unknown(raw) => raw.box_type.to_string(),
+    }
+}
+
+fn info_json(heif: &Heif) -> Result<serde_json::Value, Error> {
+    let mut items = Vec::new();
+    for item in heif.items() {
+        let references: Vec<_> = heif
+            .references(item.id)
+            .into_iter()
+            .map(|reference| {
+                json!({
+                    "type": reference.reference_type,
+                    "to": reference.to_item_ids,
+                })
+            })
+            .collect();
+        items.push(json!({
+            "id": item.id,
+            "primary": item.id == heif.primary_item_id(),
+            "info": item.info,
+            "data_size": item.data_size,
+            "properties": item.properties,
+            "references": references,
+        }));
+    }
+
+    let groups: Vec<_> = heif
+        .entity_groups()
+        .iter()
+        .map(|group| {
+            json!({
+                "type": group.grouping_type,
+                "id": group.group_id,
+                "entities": group.entity_ids,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "file_type": heif.file_type(),
+        "primary_item": heif.primary_item_id(),
+        "items": items,
+        "groups": groups,
+    }))
+}
+
+fn extract<R: Read + Seek>(
+    heif: &Heif,
+    mut from: R,
+    item: u32,
+    what: Extract,
+    into: &mut dyn Write,
+) -> Result<(), Error> {
+    match what {
+        Extract::Raw => {
+            io::copy(&mut heif.open_item_data(from, item)?, into)?;
+        }
+        Extract::Stream => heif.bit_stream(item, from, into)?,
+        Extract::Exif => {
+            let exif = metadata_item(heif, item, what)?;
+            let data = read_item(heif, &mut from, exif)?;
+            // the offset of the tiff header, past whatever the writer put before it
+            ensure!(data.len() >= 4, "exif item {} is too short", exif);
+            let start = usize(BE::read_u32(&data)).saturating_add(4);
+            ensure!(
+                start < data.len(),
+                "exif item {} has its tiff header past its end",
+                exif
+            );
+            into.write_all(&data[start..])?;
+        }
+        Extract::Xmp => {
+            let xmp = metadata_item(heif, item, what)?;
+            into.write_all(&read_item(heif, &mut from, xmp)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_metadata(info: &mpeg::ItemInfo, what: Extract) -> bool {
+    match what {
+        Extract::Exif => mpeg::EXIF == info.item_type,
+        Extract::Xmp => {
+            mpeg::MIME == info.item_type
+                && Some("application/rdf+xml") == info.content_type.as_deref()
+        }
+        Extract::Raw | Extract::Stream => false,
+    }
+}
+
+// the metadata item itself, or the one describing (with a 'cdsc' reference) the image
+fn metadata_item(heif: &Heif, item: u32, what: Extract) -> Result<u32, Error> {
+    if is_metadata(heif.item_info(item)?, what) {
+        return Ok(item);
+    }
+
+    for reference in heif.all_references() {
+        if mpeg::CDSC == reference.reference_type
+            && reference.to_item_ids.contains(&item)
+            && is_metadata(heif.item_info(reference.from_item_id)?, what)
+        {
+            return Ok(reference.from_item_id);
+        }
+    }
+
+    let name = match what {
+        Extract::Xmp => "XMP",
+        _ => "Exif",
+    };
+    bail!("no {} metadata describes item {}", name, item)
+}
+
+fn read_item<R: Read + Seek>(heif: &Heif, from: R, item: u32) -> Result<Vec<u8>, Error> {
+    let allowed = heif.limits().max_total_allocation;
+    let mut data = Vec::new();
+    heif.open_item_data(from, item)?
+        .take(allowed.saturating_add(1))
+        .read_to_end(&mut data)?;
+    ensure!(
+        cast::u64(data.len()) <= allowed,
+        "item {} is too large to read",
+        item
+    );
+    Ok(data)
+}
+
+// only uncompressed items; the coded ones need a real decoder
+fn decode<R: Read + Seek>(heif: &Heif, from: R, item: u32) -> Result<heifers::Picture, Error> {
+    let item_type = heif.item_info(item)?.item_type;
+    if mpeg::UNCI != item_type {
+        let decodable = heif
+            .items()
+            .iter()
+            .filter(|summary| mpeg::UNCI == summary.info.item_type)
+            .map(|summary| summary.id.to_string())
+            .collect::<Vec<_>>();
+        ensure!(
+            !decodable.is_empty(),
+            "convert only decodes uncompressed (unci) items, and this file has none; \
+             item {} is {}, so try `extract --stream` with an external decoder",
+            item,
+            item_type
+        );
+        bail!(
+            "convert only decodes uncompressed (unci) items, and item {} is {}; \
+             try --item with one of: {}",
+            item,
+            item_type,
+            decodable.join(", ")
+        );
+    }
+    Ok(heif.decode_uncompressed(from, item)?)
+}
@@ -3,9 +3,7 @@ use std::io::Read;
 
 use cast::u16;
 use cast::u32;
-use cast::u64;
 use cast::u8;
-use cast::usize;
 pub use generic_array::typenum;
 use generic_array::ArrayLength;
 use generic_array::GenericArray;
@@ -22,48 +20,56 @@ impl<N: ArrayLength<u8>> Bits<N> {
         Ok(Bits { data, pos: 0 })
     }
 
-    pub fn read_bool(&mut self) -> bool {
+    pub fn read_bool(&mut self) -> Result<bool, io::Error> {
         let byte = self.pos / 8;
         let mask = 1 << (7 - (self.pos % 8));
+        let value = match self.data.get(byte) {
+            Some(value) => value,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "read past the end of a fixed-size field",
+                ))
+            }
+        };
         self.pos += 1;
-        self.data[byte] & mask == mask
+        Ok(value & mask == mask)
     }
 
-    pub fn read_bits(&mut self, bits: u8) -> u64 {
+    // widths come from the code, not the file, but are still checked rather than asserted
+    fn read_at_most(&mut self, bits: u8, max: u8) -> Result<u64, io::Error> {
+        if bits > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't read {} bits into {} bits", bits, max),
+            ));
+        }
+
         let mut ret = 0;
 
         for i in (0..bits).rev() {
-            if self.read_bool() {
+            if self.read_bool()? {
                 ret |= 1 << i;
             }
         }
 
-        ret
-    }
-
-    pub fn read_u8(&mut self, bits: u8) -> u8 {
-        assert_le!(bits, 8);
-        u8(self.read_bits(bits)).unwrap()
+        Ok(ret)
     }
 
-    pub fn read_u16(&mut self, bits: u8) -> u16 {
-        assert_le!(bits, 16);
-        u16(self.read_bits(bits)).unwrap()
+    pub fn read_u8(&mut self, bits: u8) -> Result<u8, io::Error> {
+        Ok(u8(self.read_at_most(bits, 8)?).unwrap())
     }
 
-    pub fn read_u32(&mut self, bits: u8) -> u32 {
-        assert_le!(bits, 32);
-        u32(self.read_bits(bits)).unwrap()
+    pub fn read_u16(&mut self, bits: u8) -> Result<u16, io::Error> {
+        Ok(u16(self.read_at_most(bits, 16)?).unwrap())
     }
 
-    pub fn read_u64(&mut self, bits: u8) -> u64 {
-        assert_le!(bits, 64);
-        u64(self.read_bits(bits))
+    pub fn read_u32(&mut self, bits: u8) -> Result<u32, io::Error> {
+        Ok(u32(self.read_at_most(bits, 32)?).unwrap())
     }
 
-    pub fn skip(&mut self, bits: u8) -> &mut Self {
-        self.pos += usize(bits);
-        self
+    pub fn read_u64(&mut self, bits: u8) -> Result<u64, io::Error> {
+        self.read_at_most(bits, 64)
     }
 
     pub fn done(&self) -> bool {
@@ -85,23 +91,23 @@ mod tests {
     fn one_byte() {
         let c = Cursor::new([0b1100_0100u8]);
         let mut bits = Bits::<typenum::U1>::read_exact(c).expect("reading from cursor");
-        assert!(bits.read_bool());
-        assert!(bits.read_bool());
-        assert!(!bits.read_bool());
-        assert!(!bits.read_bool());
-        assert!(!bits.read_bool());
-        assert!(bits.read_bool());
-        assert!(!bits.read_bool());
-        assert!(!bits.read_bool());
+        assert!(bits.read_bool().unwrap());
+        assert!(bits.read_bool().unwrap());
+        assert!(!bits.read_bool().unwrap());
+        assert!(!bits.read_bool().unwrap());
+        assert!(!bits.read_bool().unwrap());
+        assert!(bits.read_bool().unwrap());
+        assert!(!bits.read_bool().unwrap());
+        assert!(!bits.read_bool().unwrap());
     }
 
     #[test]
     fn sub_byte() {
         let c = Cursor::new([0b1100_0100u8]);
         let mut bits = Bits::<typenum::U1>::read_exact(c).expect("reading from cursor");
-        assert_eq!(0b1100, bits.read_u8(4));
-        assert_eq!(0b01, bits.read_u8(2));
-        assert_eq!(0b0, bits.read_u8(2));
+        assert_eq!(0b1100, bits.read_u8(4).unwrap());
+        assert_eq!(0b01, bits.read_u8(2).unwrap());
+        assert_eq!(0b0, bits.read_u8(2).unwrap());
     }
 
     #[test]
@@ -109,9 +115,9 @@ mod tests {
         let c = Cursor::new(b"abc");
         let mut bits = Bits::<typenum::U3>::read_exact(c).expect("reading from cursor");
 
-        assert_eq!(b'a', bits.read_u8(8));
-        assert_eq!(b'b', bits.read_u8(8));
-        assert_eq!(b'c', bits.read_u8(8));
+        assert_eq!(b'a', bits.read_u8(8).unwrap());
+        assert_eq!(b'b', bits.read_u8(8).unwrap());
+        assert_eq!(b'c', bits.read_u8(8).unwrap());
     }
 
     #[test]
@@ -120,6 +126,14 @@ mod tests {
         buf.write_u32::<BE>(987654321).unwrap();
         let mut bits =
             Bits::<typenum::U4>::read_exact(Cursor::new(buf)).expect("reading from cursor");
-        assert_eq!(987654321, bits.read_u32(32));
+        assert_eq!(987654321, bits.read_u32(32).unwrap());
+    }
+
+    #[test]
+    fn past_the_end() {
+        let c = Cursor::new([0xffu8]);
+        let mut bits = Bits::<typenum::U1>::read_exact(c).expect("reading from cursor");
+        assert!(bits.read_u16(12).is_err());
+        assert!(bits.read_u8(9).is_err());
     }
 }
@@ -0,0 +1,781 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use cast::u16;
+use cast::u64;
+use cast::usize;
+use failure::Error;
+
+use error;
+use error::ParseError;
+use file::get_only_element;
+use limits::Budget;
+use mpeg;
+use mpeg::iprp::Association;
+use mpeg::iprp::FromProperty;
+use mpeg::iprp::Ispe;
+use mpeg::iprp::ItemPropertyAssociation;
+use mpeg::iprp::Property;
+use mpeg::meta;
+use mpeg::parse_child;
+use mpeg::write;
+use mpeg::BoxHeader;
+use mpeg::EntityGroup;
+use mpeg::Extent;
+use mpeg::FileType;
+use mpeg::FourCc;
+use mpeg::ItemInfo;
+use mpeg::ItemLoc;
+use mpeg::ItemReference;
+use mpeg::RawBox;
+use transform::Orientation;
+use transform::Rect;
+use transform::Transform;
+
+// Rewrites a file's metadata without touching the coded data. The new file has the same
+// top-level boxes, but with a new 'meta', and one 'mdat' holding every item's data.
+pub struct Editor {
+    file_type: FileType,
+    handler: FourCc,
+    primary_item: u32,
+    infos: Vec<ItemInfo>,
+    locations: HashMap<u32, ItemLoc>,
+    properties: Vec<Property>,
+    associations: Vec<ItemPropertyAssociation>,
+    references: Vec<ItemReference>,
+    groups: Vec<EntityGroup>,
+    item_data: Vec<u8>,
+    unknown_boxes: Vec<RawBox>,
+    // other top-level boxes, which are copied through unchanged
+    others: Vec<(u64, BoxHeader)>,
+    replacements: HashMap<u32, Vec<u8>>,
+}
+
+impl Editor {
+    pub fn new<R: Read + Seek>(from: R) -> Result<Editor, ParseError> {
+        Ok(Editor::read(from)?)
+    }
+
+    fn read<R: Read + Seek>(mut from: R) -> Result<Editor, Error> {
+        from.seek(SeekFrom::Start(0))?;
+        let end = from.seek(SeekFrom::End(0))?;
+        from.seek(SeekFrom::Start(0))?;
+
+        let mut file_type = None;
+        let mut raw = None;
+        let mut others = Vec::new();
+
+        let mut budget = Budget::default();
+        let mut pos = 0;
+        while pos < end {
+            let header = mpeg::read_header(&mut from)?;
+            if header.size > end - pos {
+                return Err(error::truncated(format!(
+                    "{:?} box at {} runs past the end of the file",
+                    header.box_type, pos
+                )));
+            }
+
+            let data_offset = pos + u64(header.offset);
+            let mut data = (&mut from).take(header.data_size());
+            match header.box_type {
+                mpeg::FTYP => {
+                    let parsed =
+                        parse_child(&header, Some(data_offset), &mut data, mpeg::parse_ftyp);
+                    file_type = Some(parsed?);
+                }
+                mpeg::META => {
+                    ensure!(raw.is_none(), "multiple meta boxes");
+                    raw = Some(parse_child(
+                        &header,
+                        Some(data_offset),
+                        &mut data,
+                        |data| {
+                            budget.limits.check_box(&header)?;
+                            meta::parse(data, data_offset, &mut budget)
+                        },
+                    )?);
+                }
+                mpeg::MOOV => bail!("image sequences can't be edited"),
+                // we write our own 'mdat', and padding isn't worth keeping
+                mpeg::MDAT | mpeg::FREE | mpeg::SKIP => (),
+                _ => others.push((pos, header)),
+            }
+
+            pos += header.size;
+            from.seek(SeekFrom::Start(pos))?;
+        }
+
+        let file_type = file_type.ok_or_else(|| format_err!("no ftyp"))?;
+        let raw = raw.ok_or_else(|| format_err!("no meta"))?;
+
+        let props = get_only_element(raw.item_props)?;
+        let properties = get_only_element(props.containers)?;
+        let associations = get_only_element(props.associations)?;
+
+        let mut locations = HashMap::new();
+        for location in raw.item_locators.into_iter().flatten() {
+            ensure!(
+                0 == location.data_reference_index,
+                "item data in other files is not supported"
+            );
+            ensure!(
+                location.construction_method <= 1,
+                "unsupported construction method: {}",
+                location.construction_method
+            );
+            locations.insert(location.id, location);
+        }
+
+        let infos: Vec<ItemInfo> = raw.item_infos.into_iter().flatten().collect();
+        for info in &infos {
+            ensure!(
+                locations.contains_key(&info.id),
+                "no locator for item {}",
+                info.id
+            );
+        }
+
+        ensure!(raw.item_data.len() <= 1, "multiple idat boxes");
+
+        Ok(Editor {
+            file_type,
+            handler: *get_only_element(&raw.handler)?,
+            primary_item: *get_only_element(&raw.primary_item)?,
+            infos,
+            locations,
+            properties,
+            associations,
+            references: raw.item_refs.into_iter().flatten().collect(),
+            groups: raw.entity_groups.into_iter().flatten().collect(),
+            item_data: raw.item_data.into_iter().next().unwrap_or_default(),
+            unknown_boxes: raw.unknown,
+            others,
+            replacements: HashMap::new(),
+        })
+    }
+
+    pub fn items(&self) -> &[ItemInfo] {
+        &self.infos
+    }
+
+    fn check_item(&self, item: u32) -> Result<(), ParseError> {
+        if !self.locations.contains_key(&item) {
+            return Err(error::invalid_argument(format!(
+                "invalid item id: {}",
+                item
+            )));
+        }
+        Ok(())
+    }
+
+    // drop an item, and everything which mentions it; the inputs of a derived image, like
+    // the tiles of a grid, can't go while it's still there
+    pub fn remove_item(&mut self, item: u32) -> Result<(), ParseError> {
+        self.check_item(item)?;
+        if item == self.primary_item {
+            return Err(error::invalid_argument(
+                "can't remove the primary item".to_string(),
+            ));
+        }
+        let derived: Vec<u32> = self
+            .references
+            .iter()
+            .filter(|reference| {
+                [mpeg::DIMG, mpeg::BASE].contains(&reference.reference_type)
+                    && reference.to_item_ids.contains(&item)
+            })
+            .map(|reference| reference.from_item_id)
+            .collect();
+        if !derived.is_empty() {
+            return Err(error::invalid_argument(format!(
+                "item {} is an input of {:?}, which must be removed first",
+                item, derived
+            )));
+        }
+
+        self.infos.retain(|info| info.id != item);
+        self.locations.remove(&item);
+        self.replacements.remove(&item);
+        self.associations.retain(|assoc| assoc.item_id != item);
+
+        for reference in &mut self.references {
+            reference.to_item_ids.retain(|&id| id != item);
+        }
+        self.references.retain(|reference| {
+            reference.from_item_id != item && !reference.to_item_ids.is_empty()
+        });
+
+        for group in &mut self.groups {
+            group.entity_ids.retain(|&id| id != item);
+        }
+        self.groups.retain(|group| !group.entity_ids.is_empty());
+
+        Ok(())
+    }
+
+    // 'Exif' items, and XMP, which is a 'mime' item with this content type
+    pub fn remove_metadata(&mut self) -> Result<Vec<u32>, ParseError> {
+        let metadata: Vec<u32> = self
+            .infos
+            .iter()
+            .filter(|info| {
+                mpeg::EXIF == info.item_type
+                    || (mpeg::MIME == info.item_type
+                        && Some("application/rdf+xml") == info.content_type.as_deref())
+            })
+            .map(|info| info.id)
+            .collect();
+
+        for &item in &metadata {
+            self.remove_item(item)?;
+        }
+
+        Ok(metadata)
+    }
+
+    // the data is written to the 'mdat'
+    pub fn add_item(&mut self, info: ItemInfo, data: Vec<u8>) -> Result<(), ParseError> {
+        if self.locations.contains_key(&info.id) {
+            return Err(error::invalid_argument(format!(
+                "duplicate item id: {}",
+                info.id
+            )));
+        }
+        self.locations
+            .insert(info.id, single_extent(info.id, 0, 0, 0));
+        self.replacements.insert(info.id, data);
+        self.infos.push(info);
+        Ok(())
+    }
+
+    pub fn add_reference(&mut self, reference: ItemReference) -> Result<(), ParseError> {
+        for &id in Some(reference.from_item_id)
+            .iter()
+            .chain(reference.to_item_ids.iter())
+        {
+            self.check_item(id)?;
+        }
+        self.references.push(reference);
+        Ok(())
+    }
+
+    pub fn replace_item_data(&mut self, item: u32, data: Vec<u8>) -> Result<(), ParseError> {
+        self.check_item(item)?;
+        self.replacements.insert(item, data);
+        Ok(())
+    }
+
+    // the item's properties, in association order, with their essential flags
+    pub fn properties(&self, item: u32) -> Vec<(bool, &Property)> {
+        self.associations
+            .iter()
+            .filter(|assoc| assoc.item_id == item)
+            .flat_map(|assoc| assoc.associations.iter())
+            .filter(|assoc| 0 != assoc.property_index)
+            .filter_map(|assoc| {
+                self.properties
+                    .get(usize::from(assoc.property_index) - 1)
+                    .map(|prop| (assoc.essential, prop))
+            })
+            .collect()
+    }
+
+    // drop the item's associations with properties of this box type, e.g. vendor metadata
+    pub fn remove_properties(&mut self, item: u32, box_type: FourCc) -> usize {
+        let properties = &self.properties;
+        let mut removed = 0;
+        for item_assoc in &mut self.associations {
+            if item_assoc.item_id != item {
+                continue;
+            }
+
+            let before = item_assoc.associations.len();
+            item_assoc.associations.retain(|assoc| {
+                match properties.get(usize::from(assoc.property_index).wrapping_sub(1)) {
+                    Some(prop) => box_type != property_type(prop),
+                    None => true,
+                }
+            });
+            removed += before - item_assoc.associations.len();
+        }
+
+        removed
+    }
+
+    // associate a new property with the item, after its existing ones
+    pub fn add_property(
+        &mut self,
+        item: u32,
+        property: Property,
+        essential: bool,
+    ) -> Result<(), ParseError> {
+        self.check_item(item)?;
+        // the ipma's indexes are 15 bits
+        let property_index = match u16(self.properties.len() + 1) {
+            Ok(index) if index <= 0x7fff => index,
+            _ => return Err(error::unsupported("too many properties".to_string()).into()),
+        };
+
+        self.properties.push(property);
+        let assoc = Association {
+            essential,
+            property_index,
+        };
+
+        match self
+            .associations
+            .iter_mut()
+            .find(|assoc| assoc.item_id == item)
+        {
+            Some(item_assoc) => item_assoc.associations.push(assoc),
+            None => self.associations.push(ItemPropertyAssociation {
+                item_id: item,
+                associations: vec![assoc],
+            }),
+        }
+
+        Ok(())
+    }
+
+    // the crop and orientation described by the item's transformative properties
+    pub fn transform(&self, item: u32) -> Result<Transform, ParseError> {
+        let properties = self.properties(item);
+        let ispe = get_only_element(
+            properties
+                .iter()
+                .filter_map(|&(_, prop)| Ispe::from_property(prop)),
+        )
+        .map_err(|e| {
+            format_err!(
+                "item {} needs exactly one ispe to be transformed: {}",
+                item,
+                e
+            )
+        })?;
+
+        Ok(Transform::from_properties(
+            ispe.width,
+            ispe.height,
+            properties.into_iter().map(|(_, prop)| prop),
+        )?)
+    }
+
+    // replace the item's clap, irot and imir with ones describing this transform
+    pub fn set_transform(&mut self, item: u32, transform: &Transform) -> Result<(), ParseError> {
+        let properties = transform.to_properties()?;
+        for &box_type in &[mpeg::CLAP, mpeg::IROT, mpeg::IMIR] {
+            self.remove_properties(item, box_type);
+        }
+
+        // transformative properties are always essential
+        for property in properties {
+            self.add_property(item, property, true)?;
+        }
+        Ok(())
+    }
+
+    // anticlockwise, after any existing transforms
+    pub fn rotate(&mut self, item: u32, quarter_turns: u8) -> Result<(), ParseError> {
+        self.reorient(item, Orientation::rotation(quarter_turns))
+    }
+
+    pub fn flip_horizontal(&mut self, item: u32) -> Result<(), ParseError> {
+        self.reorient(item, Orientation::flip_horizontal())
+    }
+
+    pub fn flip_vertical(&mut self, item: u32) -> Result<(), ParseError> {
+        self.reorient(item, Orientation::flip_vertical())
+    }
+
+    pub fn reorient(&mut self, item: u32, orientation: Orientation) -> Result<(), ParseError> {
+        let mut transform = self.transform(item)?;
+        transform.reorient(orientation);
+        self.set_transform(item, &transform)
+    }
+
+    // crop to a rectangle of the image as it's displayed, i.e. after any existing transforms
+    pub fn crop(&mut self, item: u32, rect: Rect) -> Result<(), ParseError> {
+        let mut transform = self.transform(item)?;
+        transform
+            .crop(rect)
+            .map_err(|e| error::invalid_argument(e.to_string()))?;
+        self.set_transform(item, &transform)
+    }
+
+    pub fn write<R: Read + Seek, W: Write>(&self, from: R, into: W) -> Result<(), ParseError> {
+        Ok(self.rewrite(from, into)?)
+    }
+
+    fn rewrite<R: Read + Seek, W: Write>(&self, mut from: R, mut into: W) -> Result<(), Error> {
+        let end = from.seek(SeekFrom::End(0))?;
+
+        // work out where every item's data will be, relative to the start of the new data
+        let mut items: Vec<&ItemInfo> = self.infos.iter().collect();
+        items.sort_by_key(|info| info.id);
+
+        let mut file_items = Vec::new();
+        let mut idat_items = Vec::new();
+        let mut data_len = 0u64;
+        let mut idat_len = 0u64;
+        for info in &items {
+            let location = &self.locations[&info.id];
+            let len = match self.replacements.get(&info.id) {
+                Some(data) => u64(data.len()),
+                None => extents_len(location, end, u64(self.item_data.len()))?,
+            };
+
+            if 1 == location.construction_method && !self.replacements.contains_key(&info.id) {
+                idat_items.push((info.id, idat_len, len));
+                idat_len += len;
+            } else {
+                file_items.push((info.id, data_len, len));
+                data_len += len;
+            }
+        }
+
+        let mut idat = Vec::with_capacity(usize(idat_len));
+        for &(id, _, _) in &idat_items {
+            let location = &self.locations[&id];
+            copy_extents(
+                &mut io::Cursor::new(&self.item_data),
+                location,
+                u64(self.item_data.len()),
+                &mut idat,
+            )?;
+        }
+
+        let mut others = Vec::new();
+        for &(pos, header) in &self.others {
+            from.seek(SeekFrom::Start(pos))?;
+            let mut raw = Vec::new();
+            (&mut from).take(header.size).read_to_end(&mut raw)?;
+            ensure!(u64(raw.len()) == header.size, "box truncated: {:?}", header);
+            others.extend_from_slice(&raw);
+        }
+
+        let mut head = Vec::new();
+        write::write_ftyp(&mut head, &self.file_type)?;
+
+        // the offsets in the iloc can change its size, so repeat until it settles
+        let mut data_start = 0;
+        let mut meta = Vec::new();
+        for _ in 0..4 {
+            meta = self.build_meta(data_start, &file_items, &idat_items, &idat)?;
+            let start =
+                u64(head.len() + meta.len() + others.len()) + write::box_header_size(data_len);
+            if start == data_start {
+                break;
+            }
+            data_start = start;
+        }
+        ensure!(
+            u64(head.len() + meta.len() + others.len()) + write::box_header_size(data_len)
+                == data_start,
+            "meta size didn't settle"
+        );
+
+        into.write_all(&head)?;
+        into.write_all(&meta)?;
+        into.write_all(&others)?;
+        write::write_box_header(&mut into, mpeg::MDAT, data_len)?;
+
+        for &(id, _, _) in &file_items {
+            match self.replacements.get(&id) {
+                Some(data) => into.write_all(data)?,
+                None => {
+                    let location = &self.locations[&id];
+                    if 1 == location.construction_method {
+                        copy_extents(
+                            &mut io::Cursor::new(&self.item_data),
+                            location,
+                            u64(self.item_data.len()),
+                            &mut into,
+                        )?;
+                    } else {
+                        copy_extents(&mut from, location, end, &mut into)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_meta(
+        &self,
+        data_start: u64,
+        file_items: &[(u32, u64, u64)],
+        idat_items: &[(u32, u64, u64)],
+        idat: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut locations = Vec::with_capacity(file_items.len() + idat_items.len());
+        for &(id, offset, length) in file_items {
+            locations.push(single_extent(id, 0, data_start + offset, length));
+        }
+        for &(id, offset, length) in idat_items {
+            locations.push(single_extent(id, 1, offset, length));
+        }
+        locations.sort_by_key(|location| location.id);
+
+        let (properties, associations) = self.used_properties()?;
+
+        let mut meta = Vec::new();
+        write::write_full_box(&mut meta, mpeg::META, 0, 0, |into| {
+            write::write_hdlr(into, self.handler)?;
+            write::write_pitm(into, self.primary_item)?;
+            write::write_iloc(into, &locations)?;
+            write::write_iinf(into, &self.infos)?;
+            if !self.references.is_empty() {
+                write::write_iref(into, &self.references)?;
+            }
+            write::write_iprp(into, &properties, &associations)?;
+            if !self.groups.is_empty() {
+                write::write_grpl(into, &self.groups)?;
+            }
+            if !idat.is_empty() {
+                write::write_box(into, mpeg::IDAT, |into| Ok(into.write_all(idat)?))?;
+            }
+            for raw in &self.unknown_boxes {
+                write::write_raw(into, raw)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(meta)
+    }
+
+    // drop properties nothing refers to any more, renumbering the associations
+    fn used_properties(&self) -> Result<(Vec<Property>, Vec<ItemPropertyAssociation>), Error> {
+        let mut renumbered = HashMap::new();
+        let mut properties = Vec::new();
+        let mut associations = Vec::new();
+
+        for item_assoc in &self.associations {
+            let mut kept = Vec::new();
+            for assoc in &item_assoc.associations {
+                let old = usize::from(assoc.property_index);
+                if 0 == old || old > self.properties.len() {
+                    continue;
+                }
+
+                let new = match renumbered.get(&old) {
+                    Some(&new) => new,
+                    None => {
+                        properties.push(self.properties[old - 1].clone());
+                        let new = u16(properties.len())?;
+                        renumbered.insert(old, new);
+                        new
+                    }
+                };
+
+                kept.push(Association {
+                    essential: assoc.essential,
+                    property_index: new,
+                });
+            }
+
+            if !kept.is_empty() {
+                associations.push(ItemPropertyAssociation {
+                    item_id: item_assoc.item_id,
+                    associations: kept,
+                });
+            }
+        }
+
+        Ok((properties, associations))
+    }
+}
+
+pub fn property_type(property: &Property) -> FourCc {
+    match property {
+        Property::HvcCodecSettings(_) => mpeg::HVCC,
+        Property::Av1CodecSettings(_) => mpeg::AV1C,
+        Property::JpegPrefix(_) => mpeg::JPGC,
+        Property::ComponentDefinition(_) => mpeg::CMPD,
+        Property::UncompressedConfig(_) => mpeg::UNCC,
+        Property::Size(_) => mpeg::ISPE,
+        Property::Rotation(_) => mpeg::IROT,
+        Property::Mirror(_) => mpeg::IMIR,
+        Property::CleanAperture(_) => mpeg::CLAP,
+        Property::Unknown(raw) => raw.box_type,
+    }
+}
+
+fn single_extent(id: u32, construction_method: u8, offset: u64, length: u64) -> ItemLoc {
+    ItemLoc {
+        id,
+        construction_method,
+        data_reference_index: 0,
+        base_offset: 0,
+        extents: vec![Extent {
+            index: 0,
+            offset,
+            length,
+        }],
+    }
+}
+
+// a zero length means the rest of the file, or idat
+fn extent_len(location: &ItemLoc, extent: &Extent, end: u64) -> Result<u64, Error> {
+    let start = location
+        .base_offset
+        .checked_add(extent.offset)
+        .ok_or_else(|| format_err!("extent offset out of range"))?;
+    ensure!(start <= end, "extent starts past the end of the data");
+    Ok(if 0 == extent.length {
+        end - start
+    } else {
+        ensure!(
+            extent.length <= end - start,
+            "extent runs past the end of the data"
+        );
+        extent.length
+    })
+}
+
+fn extents_len(location: &ItemLoc, file_end: u64, idat_end: u64) -> Result<u64, Error> {
+    let end = if 1 == location.construction_method {
+        idat_end
+    } else {
+        file_end
+    };
+
+    let mut total = 0;
+    for extent in &location.extents {
+        total += extent_len(location, extent, end)?;
+    }
+    Ok(total)
+}
+
+fn copy_extents<R: Read + Seek, W: Write>(
+    mut from: R,
+    location: &ItemLoc,
+    end: u64,
+    mut into: W,
+) -> Result<(), Error> {
+    for extent in &location.extents {
+        ensure!(0 == extent.index, "extent indexes are not supported");
+        let len = extent_len(location, extent, end)?;
+        from.seek(SeekFrom::Start(location.base_offset + extent.offset))?;
+        let copied = io::copy(&mut (&mut from).take(len), &mut into)?;
+        ensure!(copied == len, "item data truncated");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::io::Read;
+
+    use super::single_extent;
+    use super::Editor;
+    use mpeg;
+    use mpeg::ItemInfo;
+    use Heif;
+
+    fn info(id: u32, item_type: mpeg::FourCc) -> ItemInfo {
+        ItemInfo {
+            id,
+            protection_index: 0,
+            item_type,
+            item_name: String::new(),
+            content_type: None,
+            content_encoding: None,
+            item_uri_type: None,
+            hidden: true,
+        }
+    }
+
+    #[test]
+    fn idat_offsets() {
+        let road = &include_bytes!("../tests/data/road.heic")[..];
+        let mut editor = Editor::new(Cursor::new(road)).expect("loading");
+
+        // two items packed into the idat, the first of which we'll remove
+        editor.item_data = b"firstsecond".to_vec();
+        for &(id, offset, length) in &[(2, 0, 5), (3, 5, 6)] {
+            editor.infos.push(info(id, mpeg::EXIF));
+            editor
+                .locations
+                .insert(id, single_extent(id, 1, offset, length));
+        }
+
+        let mut with_both = Vec::new();
+        editor
+            .write(Cursor::new(road), &mut with_both)
+            .expect("writing");
+
+        editor.remove_item(2).expect("removing");
+        let mut without = Vec::new();
+        editor
+            .write(Cursor::new(road), &mut without)
+            .expect("writing");
+
+        for file in &[with_both, without] {
+            let heif = Heif::new(Cursor::new(file)).expect("parsing");
+            let mut data = Vec::new();
+            heif.open_item_data(Cursor::new(file), 3)
+                .expect("opening")
+                .read_to_end(&mut data)
+                .expect("reading");
+            assert_eq!(b"second", data.as_slice());
+        }
+    }
+
+    #[test]
+    fn derivation_inputs() {
+        let road = &include_bytes!("../tests/data/road.heic")[..];
+        let mut editor = Editor::new(Cursor::new(road)).expect("loading");
+
+        // a grid, 3, of one tile, 2
+        editor.item_data = b"tilegrid".to_vec();
+        for &(id, item_type, offset, length) in &[(2, mpeg::HVC1, 0, 4), (3, mpeg::GRID, 4, 4)] {
+            editor.infos.push(info(id, item_type));
+            editor
+                .locations
+                .insert(id, single_extent(id, 1, offset, length));
+        }
+        editor
+            .add_reference(mpeg::ItemReference {
+                reference_type: mpeg::DIMG,
+                from_item_id: 3,
+                to_item_ids: vec![2],
+            })
+            .expect("referencing");
+
+        assert!(editor.remove_item(2).is_err());
+        assert_eq!(1, editor.references.len());
+        editor.remove_item(3).expect("removing the grid");
+        editor.remove_item(2).expect("removing the tile");
+        assert!(editor.references.is_empty());
+    }
+
+    #[test]
+    fn wide_primary_item() {
+        let road = &include_bytes!("../tests/data/road.heic")[..];
+        let mut editor = Editor::new(Cursor::new(road)).expect("loading");
+
+        // too big for a version 0 pitm
+        let id = 0x1_2345;
+        editor.item_data = b"wide".to_vec();
+        editor.infos.push(info(id, mpeg::MIME));
+        editor.locations.insert(id, single_extent(id, 1, 0, 4));
+        editor.primary_item = id;
+
+        let mut written = Vec::new();
+        editor
+            .write(Cursor::new(road), &mut written)
+            .expect("writing");
+
+        let heif = Heif::new(Cursor::new(&written)).expect("parsing");
+        assert_eq!(id, heif.primary_item_id());
+        let reread = Editor::new(Cursor::new(&written)).expect("loading again");
+        assert_eq!(id, reread.primary_item);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::io;
+
+use bitreader::BitReaderError;
+use failure::Backtrace;
+use failure::Error;
+use failure::Fail;
+
+use limits::LimitExceeded;
+use mpeg::FourCc;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    // the file, or a box, ended before what it claimed to hold
+    Truncated,
+    // valid, as far as we can tell, but using something we can't read
+    Unsupported,
+    Malformed,
+    LimitExceeded,
+    Io,
+    // the caller asked for something the file doesn't have, like an item id that isn't in it
+    InvalidArgument,
+}
+
+// Why reading a file failed, and where. Parsing is still written in terms of
+// `failure::Error`; this is built on the way out, with each box adding itself to the path.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    // outermost first, e.g. meta/iprp/ipco/hvcC; empty for the top level
+    pub path: Vec<FourCc>,
+    // absolute, in the file, of where parsing stopped, when we know it
+    pub offset: Option<u64>,
+    inner: Error,
+}
+
+impl ParseError {
+    pub fn inner(&self) -> &Error {
+        &self.inner
+    }
+
+    pub fn path_string(&self) -> String {
+        self.path
+            .iter()
+            .map(|box_type| box_type.to_string())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl From<Error> for ParseError {
+    fn from(inner: Error) -> ParseError {
+        let inner = match inner.downcast::<ParseError>() {
+            Ok(error) => return error,
+            Err(inner) => inner,
+        };
+
+        let kind = if inner.downcast_ref::<LimitExceeded>().is_some() {
+            ErrorKind::LimitExceeded
+        } else if let Some(e) = inner.downcast_ref::<io::Error>() {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => ErrorKind::Truncated,
+                _ => ErrorKind::Io,
+            }
+        } else if let Some(BitReaderError::NotEnoughData { .. }) = inner.downcast_ref() {
+            ErrorKind::Truncated
+        } else {
+            ErrorKind::Malformed
+        };
+
+        ParseError {
+            kind,
+            path: Vec::new(),
+            offset: None,
+            inner,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(inner: io::Error) -> ParseError {
+        Error::from(inner).into()
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Truncated => "truncated input",
+            ErrorKind::Unsupported => "unsupported feature",
+            ErrorKind::Malformed => "malformed file",
+            ErrorKind::LimitExceeded => "limit exceeded",
+            ErrorKind::Io => "I/O error",
+            ErrorKind::InvalidArgument => "invalid argument",
+        })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !self.path.is_empty() {
+            write!(f, " in {}", self.path_string())?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        write!(f, ": {}", self.inner)
+    }
+}
+
+impl Fail for ParseError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(self.inner.as_fail())
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        Some(self.inner.backtrace())
+    }
+}
+
+fn new(kind: ErrorKind, message: String) -> ParseError {
+    ParseError {
+        kind,
+        path: Vec::new(),
+        offset: None,
+        inner: failure::err_msg(message),
+    }
+}
+
+// for versions, and other parts of the spec, we don't implement
+pub fn unsupported(message: String) -> Error {
+    new(ErrorKind::Unsupported, message).into()
+}
+
+pub fn truncated(message: String) -> Error {
+    new(ErrorKind::Truncated, message).into()
+}
+
+// for the item and editing methods, which return a `ParseError` directly
+pub fn invalid_argument(message: String) -> ParseError {
+    new(ErrorKind::InvalidArgument, message)
+}
+
+// Note that a failure came from inside a box. Only the innermost box that knows where it
+// is sets the offset; the rest only add to the path.
+pub fn in_box<T>(
+    result: Result<T, Error>,
+    box_type: FourCc,
+    offset: Option<u64>,
+) -> Result<T, Error> {
+    result.map_err(|e| {
+        let mut error = ParseError::from(e);
+        error.path.insert(0, box_type);
+        if error.offset.is_none() {
+            error.offset = offset;
+        }
+        error.into()
+    })
+}
+
+// as `in_box`, for failures between the top-level boxes
+pub fn at<T>(result: Result<T, Error>, offset: u64) -> Result<T, Error> {
+    result.map_err(|e| {
+        let mut error = ParseError::from(e);
+        if error.offset.is_none() {
+            error.offset = Some(offset);
+        }
+        error.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use failure::Error;
+
+    use limits::Limits;
+    use mpeg;
+
+    use super::at;
+    use super::in_box;
+    use super::unsupported;
+    use super::ErrorKind;
+    use super::ParseError;
+
+    #[test]
+    fn kinds_and_paths() {
+        let eof: Error = io::Error::new(io::ErrorKind::UnexpectedEof, "eof").into();
+        let error = ParseError::from(eof);
+        assert_eq!(ErrorKind::Truncated, error.kind);
+
+        let limit = Limits::default().check_items(u64::MAX).unwrap_err();
+        assert_eq!(ErrorKind::LimitExceeded, ParseError::from(limit).kind);
+
+        let failed: Result<(), Error> = Err(unsupported("version 7".to_string()));
+        let failed = in_box(failed, mpeg::HVCC, Some(120));
+        let failed = in_box(failed, mpeg::IPCO, Some(100));
+        let failed = at(in_box(failed, mpeg::META, None), 40);
+        let error = ParseError::from(failed.unwrap_err());
+        assert_eq!(ErrorKind::Unsupported, error.kind);
+        assert_eq!("meta/ipco/hvcC", error.path_string());
+        assert_eq!(Some(120), error.offset);
+        assert_eq!(
+            "unsupported feature in meta/ipco/hvcC at offset 120: version 7",
+            error.to_string()
+        );
+
+        let error = ParseError::from(format_err!("no items"));
+        assert_eq!(ErrorKind::Malformed, error.kind);
+        assert_eq!("malformed file: no items", error.to_string());
+    }
+}
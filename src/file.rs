@@ -1,234 +1,903 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::io;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
+use std::sync::Mutex;
 
 use bitreader::BitReader;
-use cast::u32;
+use byteorder::ByteOrder;
+use byteorder::BE;
 use cast::u64;
 use cast::usize;
 use failure::Error;
 
+use av1;
+use error;
+use error::ParseError;
 use hevc;
-use hevc::nal::NalReader;
+use hevc::nal;
 use hevc::pps;
 use hevc::sps;
+use hevc::vps;
+use limits::Limits;
 use mpeg;
+use mpeg::iprp::Av1c;
+use mpeg::iprp::FromProperty;
+use mpeg::iprp::Hvcc;
+use mpeg::iprp::Ispe;
+use mpeg::iprp::Jpgc;
 use mpeg::iprp::Property;
+use mpeg::meta::RawMeta;
+use mpeg::unci::Cmpd;
+use mpeg::unci::Uncc;
+use mpeg::EntityGroup;
 use mpeg::Extent;
+use mpeg::FileType;
 use mpeg::FourCc;
 use mpeg::ItemInfo;
 use mpeg::ItemLoc;
+use mpeg::ItemReference;
+use mpeg::RawBox;
+use picture::Picture;
+use uncompressed;
+use validate::Category;
+use validate::Problems;
+use validate::Report;
 
 // TODO: not Debug
 #[derive(Debug)]
 pub struct Heif {
+    file_type: FileType,
     handler: FourCc,
     primary_item: u32,
     items: HashMap<u32, Item>,
-    props: Vec<(HashSet<u32>, Property)>,
+    props: Vec<Property>,
+    groups: Vec<EntityGroup>,
+    item_data: Vec<u8>,
+    references: Vec<ItemReference>,
+    unknown_boxes: Vec<RawBox>,
+    essential_policy: EssentialPolicy,
+    // the items read despite unsupported essential properties, under `Warn`
+    ignored_essential: Mutex<Vec<(u32, Vec<FourCc>)>>,
+    limits: Limits,
 }
 
 #[derive(Clone, Debug)]
 struct Item {
     info: ItemInfo,
     location: ItemLoc,
+    properties: Vec<PropertyRef>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PropertyRef {
+    essential: bool,
+    index: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct ItemSummary<'h> {
+    pub id: u32,
+    pub info: &'h ItemInfo,
+    pub data_size: u64,
+    pub properties: Vec<&'h Property>,
+}
+
+// What to do when asked for an item with an essential property we don't understand.
+// The spec says readers must not display such items, so refusing is the default.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EssentialPolicy {
+    Refuse,
+    // read the item anyway, and note it for `ignored_essential_properties`
+    Warn,
 }
 
 impl Heif {
-    pub fn new<R: Read>(from: R) -> Result<Heif, Error> {
-        let raw = mpeg::load_meta(from)?;
+    pub fn new<R: Read>(from: R) -> Result<Heif, ParseError> {
+        Heif::with_limits(from, Limits::default())
+    }
+
+    // as `new`, with the limits kept for reading the item data too
+    pub fn with_limits<R: Read>(from: R, limits: Limits) -> Result<Heif, ParseError> {
+        let (file_type, raw) = mpeg::load_meta(from, &limits)?;
+        Heif::from_raw(file_type, raw, limits)
+    }
+
+    // from boxes that have already been read, e.g. by a `MetaLoader`
+    pub fn from_raw(file_type: FileType, raw: RawMeta, limits: Limits) -> Result<Heif, ParseError> {
+        let built = Heif::build(file_type, raw, limits, &mut Problems::Strict);
+        Ok(error::in_box(built, mpeg::META, None)?)
+    }
+
+    // as `new`, but carrying on past whatever problems it can, and noting them in the report
+    pub fn new_lenient<R: Read>(from: R, report: &mut Report) -> Result<Heif, ParseError> {
+        let limits = Limits::default();
+        let (file_type, raw) = mpeg::load_meta(from, &limits)?;
+        let built = Heif::build(file_type, raw, limits, &mut Problems::Collect(report));
+        Ok(error::in_box(built, mpeg::META, None)?)
+    }
+
+    fn build(
+        file_type: FileType,
+        raw: RawMeta,
+        limits: Limits,
+        problems: &mut Problems,
+    ) -> Result<Heif, Error> {
+        let handler = match get_only_element(&raw.handler) {
+            Ok(&handler) => handler,
+            Err(e) => {
+                problems.error(Category::Missing, None, format!("hdlr: {}", e))?;
+                raw.handler.first().cloned().unwrap_or(mpeg::PICT)
+            }
+        };
 
-        let handler = *get_only_element(&raw.handler)?;
-        let primary_item = u32(*get_only_element(&raw.primary_item)?);
+        let primary_item = match get_only_element(&raw.primary_item) {
+            Ok(&primary_item) => primary_item,
+            Err(e) => {
+                problems.error(Category::Missing, None, format!("pitm: {}", e))?;
+                raw.primary_item.first().cloned().unwrap_or(0)
+            }
+        };
 
         let mut locators = HashMap::with_capacity(4 * raw.item_locators.len());
         for item_locators in raw.item_locators {
             for locator in item_locators {
-                match locators.entry(locator.id) {
-                    Entry::Occupied(_) => bail!("duplicate locator for {}", locator.id),
-                    Entry::Vacant(vacant) => vacant.insert(locator),
+                let id = locator.id;
+                match locators.entry(id) {
+                    Entry::Occupied(_) => problems.error(
+                        Category::Duplicate,
+                        Some(id),
+                        format!("duplicate locator for {}", id),
+                    )?,
+                    Entry::Vacant(vacant) => {
+                        vacant.insert(locator);
+                    }
                 };
             }
         }
 
-        let mut props = Vec::with_capacity(8 * locators.len());
-        for prop_containers in raw.item_props {
-            let container = get_only_element(prop_containers.containers)?;
-            let assocs: Vec<mpeg::iprp::ItemPropertyAssociation> =
-                get_only_element(prop_containers.associations)?;
-
-            for (index, prop) in container.into_iter().enumerate() {
-                let mut associated_items = HashSet::with_capacity(assocs.len());
-                for item_assoc in &assocs {
-                    if item_assoc
-                        .associations
-                        .iter()
-                        .find(|x| usize(x.property_index) == index + 1)
-                        .is_some()
-                    {
-                        associated_items.insert(item_assoc.item_id);
-                    }
+        let mut items = HashMap::new();
+        for item_infos in raw.item_infos {
+            for info in item_infos {
+                let id = info.id;
+                if items.contains_key(&id) {
+                    problems.error(
+                        Category::Duplicate,
+                        Some(id),
+                        format!("duplicate item id: {}", id),
+                    )?;
+                    continue;
                 }
 
-                // TODO: we're losing the essential status here
-                props.push((associated_items, prop));
+                let location = match locators.remove(&id) {
+                    Some(location) => location,
+                    None => {
+                        problems.error(
+                            Category::Missing,
+                            Some(id),
+                            format!("no locator for item {}", id),
+                        )?;
+                        continue;
+                    }
+                };
+
+                items.insert(
+                    id,
+                    Item {
+                        info,
+                        location,
+                        properties: Vec::new(),
+                    },
+                );
             }
         }
 
-        let mut items = HashMap::new();
-        for item_infos in raw.item_infos {
-            for info in item_infos {
-                let id = u32(info.id);
-                match items.entry(id) {
-                    Entry::Occupied(_) => bail!("duplicate item id: {}", id),
-                    Entry::Vacant(vacancy) => vacancy.insert(Item {
-                        info,
-                        location: locators
-                            .remove(&id)
-                            .ok_or_else(|| format_err!("no locator for item {}", id))?,
-                    }),
+        for id in locators.keys() {
+            problems.warning(
+                Category::Reference,
+                Some(*id),
+                format!("iloc locates item {}, which isn't in the iinf", id),
+            );
+        }
+
+        let mut props = Vec::with_capacity(8 * items.len());
+        for prop_containers in raw.item_props {
+            let mut containers = prop_containers.containers;
+            let mut associations = prop_containers.associations;
+            for &(name, count) in &[("ipco", containers.len()), ("ipma", associations.len())] {
+                if 1 != count {
+                    problems.error(
+                        Category::Structure,
+                        None,
+                        format!("expected one {} in the iprp, not {}", name, count),
+                    )?;
+                }
+            }
+
+            if containers.is_empty() {
+                continue;
+            }
+            let container = containers.swap_remove(0);
+            let assocs = if associations.is_empty() {
+                Vec::new()
+            } else {
+                associations.swap_remove(0)
+            };
+
+            // property indexes are one-based, relative to this container
+            let base = props.len();
+            let count = container.len();
+            props.extend(container);
+
+            for item_assoc in assocs {
+                // associations for items we don't know about can't affect anything
+                let item = match items.get_mut(&item_assoc.item_id) {
+                    Some(item) => item,
+                    None => {
+                        problems.warning(
+                            Category::Reference,
+                            Some(item_assoc.item_id),
+                            format!(
+                                "ipma has properties for item {}, which doesn't exist",
+                                item_assoc.item_id
+                            ),
+                        );
+                        continue;
+                    }
                 };
+
+                for assoc in item_assoc.associations {
+                    if 0 == assoc.property_index {
+                        continue;
+                    }
+
+                    let index = usize(assoc.property_index) - 1;
+                    if index >= count {
+                        problems.error(
+                            Category::Association,
+                            Some(item_assoc.item_id),
+                            format!(
+                                "item {} has association with invalid property index {}",
+                                item_assoc.item_id, assoc.property_index
+                            ),
+                        )?;
+                        continue;
+                    }
+
+                    item.properties.push(PropertyRef {
+                        essential: assoc.essential,
+                        index: base + index,
+                    });
+                }
             }
         }
 
-        ensure!(
-            items.contains_key(&primary_item),
-            "primary item has no data"
-        );
+        if !items.contains_key(&primary_item) {
+            problems.error(
+                Category::Missing,
+                Some(primary_item),
+                "primary item has no data".to_string(),
+            )?;
+        }
+
+        if raw.item_data.len() > 1 {
+            problems.error(Category::Duplicate, None, "multiple idat boxes".to_string())?;
+        }
+        let item_data = raw.item_data.into_iter().next().unwrap_or_default();
+
+        let groups: Vec<EntityGroup> = raw.entity_groups.into_iter().flatten().collect();
+
+        for (i, group) in groups.iter().enumerate() {
+            if groups[..i]
+                .iter()
+                .any(|other| other.group_id == group.group_id)
+            {
+                problems.error(
+                    Category::Duplicate,
+                    None,
+                    format!("duplicate entity group id: {}", group.group_id),
+                )?;
+            }
+        }
 
         Ok(Heif {
+            file_type,
             handler,
             primary_item,
             items,
             props,
+            groups,
+            item_data,
+            references: raw.item_refs.into_iter().flatten().collect(),
+            unknown_boxes: raw.unknown,
+            essential_policy: EssentialPolicy::Refuse,
+            ignored_essential: Mutex::new(Vec::new()),
+            limits,
         })
     }
 
+    pub fn set_essential_policy(&mut self, policy: EssentialPolicy) {
+        self.essential_policy = policy;
+    }
+
+    // each item read under `EssentialPolicy::Warn` despite essential properties we don't
+    // understand, with those properties, in the order they were first read
+    pub fn ignored_essential_properties(&self) -> Vec<(u32, Vec<FourCc>)> {
+        match self.ignored_essential.lock() {
+            Ok(ignored) => ignored.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
+    }
+
     pub fn primary_item_id(&self) -> u32 {
         self.primary_item
     }
 
-    pub fn open_item_data<R: Read + Seek>(
+    pub fn items(&self) -> Vec<ItemSummary<'_>> {
+        let mut ids: Vec<u32> = self.items.keys().cloned().collect();
+        ids.sort();
+
+        ids.into_iter()
+            .map(|id| {
+                let item = &self.items[&id];
+                ItemSummary {
+                    id,
+                    info: &item.info,
+                    data_size: item.location.extents.iter().map(|e| e.length).sum(),
+                    properties: item
+                        .properties
+                        .iter()
+                        .map(|prop| &self.props[prop.index])
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn item_location(&self, item: u32) -> Result<&ItemLoc, Error> {
+        Ok(&self
+            .items
+            .get(&item)
+            .ok_or_else(|| error::invalid_argument(format!("invalid item id: {}", item)))?
+            .location)
+    }
+
+    // the contents of the idat, which items with construction method 1 point into
+    pub fn inline_item_data(&self) -> &[u8] {
+        &self.item_data
+    }
+
+    pub fn all_references(&self) -> &[ItemReference] {
+        &self.references
+    }
+
+    pub fn item_info(&self, item: u32) -> Result<&ItemInfo, Error> {
+        Ok(&self
+            .items
+            .get(&item)
+            .ok_or_else(|| error::invalid_argument(format!("invalid item id: {}", item)))?
+            .info)
+    }
+
+    // references made by this item, e.g. a thumbnail's 'thmb' to its master
+    pub fn references(&self, item: u32) -> Vec<&ItemReference> {
+        self.references
+            .iter()
+            .filter(|reference| reference.from_item_id == item)
+            .collect()
+    }
+
+    // smallest first; thumbnails with no 'ispe' sort first, as we can't tell how big they are
+    pub fn thumbnails(&self, item: u32) -> Result<Vec<u32>, Error> {
+        let mut thumbnails = Vec::new();
+        for reference in &self.references {
+            if mpeg::THMB == reference.reference_type
+                && reference.to_item_ids.contains(&item)
+                && self.items.contains_key(&reference.from_item_id)
+            {
+                let area = match self.property::<Ispe>(reference.from_item_id)? {
+                    Some(ispe) => u64(ispe.width) * u64(ispe.height),
+                    None => 0,
+                };
+                thumbnails.push((area, reference.from_item_id));
+            }
+        }
+
+        thumbnails.sort();
+        thumbnails.dedup();
+        Ok(thumbnails.into_iter().map(|(_, id)| id).collect())
+    }
+
+    // the smallest thumbnail that's at least this big, or the item itself if there isn't one
+    pub fn preview_item(&self, item: u32, width: u32, height: u32) -> Result<u32, Error> {
+        for thumbnail in self.thumbnails(item)? {
+            if let Some(ispe) = self.property::<Ispe>(thumbnail)? {
+                if ispe.width >= width
+                    && ispe.height >= height
+                    && self.unsupported_essential_properties(thumbnail)?.is_empty()
+                {
+                    return Ok(thumbnail);
+                }
+            }
+        }
+
+        Ok(item)
+    }
+
+    // the coded bit stream for `preview_item`, returning which item it chose; this doesn't
+    // decode the thumbnail, as there's no decoder here, so it's ready for one elsewhere
+    pub fn preview_bit_stream<R: Read + Seek, W: Write>(
         &self,
-        mut from: R,
         item: u32,
-    ) -> Result<Extents<R>, Error> {
+        width: u32,
+        height: u32,
+        from: R,
+        into: W,
+    ) -> Result<u32, ParseError> {
+        let chosen = self.preview_item(item, width, height)?;
+        self.bit_stream(chosen, from, into)?;
+        Ok(chosen)
+    }
+
+    pub fn entity_groups(&self) -> &[EntityGroup] {
+        &self.groups
+    }
+
+    pub fn groups_containing(&self, entity: u32) -> Vec<&EntityGroup> {
+        self.groups
+            .iter()
+            .filter(|group| group.entity_ids.contains(&entity))
+            .collect()
+    }
+
+    // 'altr' lists alternatives in order of preference; pick the first one we can extract,
+    // falling back to the item we were asked about
+    pub fn preferred_alternative(&self, item: u32) -> u32 {
+        self.groups_containing(item)
+            .into_iter()
+            .filter(|group| mpeg::ALTR == group.grouping_type)
+            .flat_map(|group| group.entity_ids.iter())
+            .cloned()
+            .find(|&id| self.is_supported_image(id))
+            .unwrap_or(item)
+    }
+
+    // the first entity in a 'ster' group is the left view, the second the right
+    pub fn stereo_pair(&self, item: u32) -> Result<Option<(u32, u32)>, Error> {
+        let group = match self
+            .groups_containing(item)
+            .into_iter()
+            .find(|group| mpeg::STER == group.grouping_type)
+        {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+
+        ensure!(
+            2 == group.entity_ids.len(),
+            "stereo pair group {} has {} entities",
+            group.group_id,
+            group.entity_ids.len()
+        );
+
+        Ok(Some((group.entity_ids[0], group.entity_ids[1])))
+    }
+
+    fn is_supported_image(&self, item: u32) -> bool {
+        match self.items.get(&item) {
+            Some(item) => {
+                [mpeg::HVC1, mpeg::AV01, mpeg::JPEG, mpeg::UNCI].contains(&item.info.item_type)
+            }
+            None => false,
+        }
+    }
+
+    pub fn essential_properties(&self, item: u32) -> Result<Vec<&Property>, Error> {
+        Ok(self
+            .associations(item)?
+            .into_iter()
+            .filter(|&(essential, _)| essential)
+            .map(|(_, prop)| prop)
+            .collect())
+    }
+
+    pub fn unsupported_essential_properties(&self, item: u32) -> Result<Vec<FourCc>, Error> {
+        Ok(self
+            .essential_properties(item)?
+            .into_iter()
+            .filter_map(|prop| match prop {
+                Property::Unknown(raw) => Some(raw.box_type),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn check_essential(&self, item: u32) -> Result<(), Error> {
+        let unsupported = self.unsupported_essential_properties(item)?;
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+
+        match self.essential_policy {
+            EssentialPolicy::Refuse => bail!(
+                "item {} has unsupported essential properties: {:?}",
+                item,
+                unsupported
+            ),
+            EssentialPolicy::Warn => {
+                let mut ignored = match self.ignored_essential.lock() {
+                    Ok(ignored) => ignored,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                if !ignored.iter().any(|&(id, _)| id == item) {
+                    ignored.push((item, unsupported));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // properties in the order they're associated with the item, which is the order
+    // transformative properties must be applied in
+    pub fn properties(&self, item: u32) -> Result<Vec<&Property>, Error> {
+        Ok(self
+            .associations(item)?
+            .into_iter()
+            .map(|(_, prop)| prop)
+            .collect())
+    }
+
+    pub fn unknown_properties(&self, item: u32) -> Result<Vec<&RawBox>, Error> {
+        Ok(self
+            .properties(item)?
+            .into_iter()
+            .filter_map(|prop| match prop {
+                Property::Unknown(raw) => Some(raw),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // children of 'meta' that we didn't understand, e.g. vendor extensions
+    pub fn unknown_boxes(&self) -> &[RawBox] {
+        &self.unknown_boxes
+    }
+
+    pub fn property<T: FromProperty>(&self, item: u32) -> Result<Option<&T>, Error> {
+        Ok(self
+            .properties(item)?
+            .into_iter()
+            .filter_map(T::from_property)
+            .next())
+    }
+
+    fn associations(&self, item: u32) -> Result<Vec<(bool, &Property)>, Error> {
         let item = self
             .items
             .get(&item)
-            .ok_or_else(|| format_err!("invalid item id"))?;
+            .ok_or_else(|| error::invalid_argument(format!("invalid item id: {}", item)))?;
 
-        let first_extent = &item
-            .location
-            .extents
-            .get(0)
-            .ok_or_else(|| format_err!("empty extents"))?;
+        Ok(item
+            .properties
+            .iter()
+            .map(|prop| (prop.essential, &self.props[prop.index]))
+            .collect())
+    }
+
+    // where the item's data is, once we know we can read it: its essential properties are
+    // understood, and its extents are ones we support
+    pub fn readable_location(&self, item: u32) -> Result<&ItemLoc, Error> {
+        self.check_essential(item)?;
+        self.location_unchecked(item)
+    }
+
+    fn location_unchecked(&self, item: u32) -> Result<&ItemLoc, Error> {
+        let location = self.item_location(item)?;
+
+        ensure!(!location.extents.is_empty(), "empty extents");
+        ensure!(
+            0 == location.data_reference_index,
+            "item data in other files is not supported"
+        );
+        ensure!(
+            location.construction_method <= 1,
+            "unsupported construction method: {}",
+            location.construction_method
+        );
+
+        let base = location.base_offset;
+        for extent in &location.extents {
+            // a zero length means "to the end", which we only know for the 'idat'
+            ensure!(0 != extent.length, "empty extent");
+            ensure!(0 == extent.index, "extent indexes are not supported");
+            ensure!(
+                base.checked_add(extent.offset).is_some(),
+                "extent offset out of range: {} + {}",
+                base,
+                extent.offset
+            );
+        }
 
-        ensure!(0 != first_extent.length, "empty first extent");
-        ensure!(0 == first_extent.index, "empty first extent");
+        Ok(location)
+    }
 
-        from.seek(SeekFrom::Start(
-            item.location.base_offset + first_extent.offset,
-        ))?;
+    // the bytes as stored, and nothing else: a 'jpeg' item's jpgC prefix isn't included, so
+    // use `open_jpeg`, or `bit_stream`, for something a decoder can read
+    pub fn open_item_data<R: Read + Seek>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<Extents<'_, R>, ParseError> {
+        self.check_essential(item)?;
+        Ok(self.open_item_data_unchecked(from, item)?)
+    }
+
+    fn open_item_data_unchecked<R: Read + Seek>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<Extents<'_, R>, Error> {
+        let location = self.location_unchecked(item)?;
+
+        // only 0 and 1 are let through
+        let mut inner = match location.construction_method {
+            0 => Source::File(from),
+            _ => Source::ItemData(io::Cursor::new(&self.item_data)),
+        };
+
+        let base = location.base_offset;
+        inner.seek(base + location.extents[0].offset)?;
 
         Ok(Extents {
-            inner: from,
-            base: item.location.base_offset,
-            extents: &item.location.extents,
+            inner,
+            base,
+            extents: &location.extents,
             current_extent: 0,
             current_pos: 0,
         })
     }
 
+    // the whole of the item's data, which must fit in the total allocation limit
+    fn read_item_data_unchecked<R: Read + Seek>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let size = self
+            .item_location(item)?
+            .extents
+            .iter()
+            .fold(0u64, |total, extent| total.saturating_add(extent.length));
+        self.limits.check_allocation(size)?;
+
+        let mut data = Vec::new();
+        self.open_item_data_unchecked(from, item)?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    // the stream a decoder for the item's codec wants: Annex B for HEVC, bare OBUs for AV1
     pub fn bit_stream<R: Read + Seek, W: Write>(
         &self,
         item: u32,
-        mut from: R,
+        from: R,
+        mut into: W,
+    ) -> Result<(), ParseError> {
+        match self.item_type(item) {
+            Some(mpeg::AV01) => self.obu_stream(item, from, into),
+            Some(mpeg::JPEG) => {
+                io::copy(&mut self.open_jpeg(from, item)?, &mut into)?;
+                Ok(())
+            }
+            _ => Ok(self.hevc_stream(item, from, into)?),
+        }
+    }
+
+    // Annex B: the hvcC's parameter sets, then each NAL unit, all after start codes
+    fn hevc_stream<R: Read + Seek, W: Write>(
+        &self,
+        item: u32,
+        from: R,
         mut into: W,
     ) -> Result<(), Error> {
-        for (ids, prop) in &self.props {
-            if !ids.contains(&item) {
+        self.check_essential(item)?;
+
+        let length_size = self
+            .property::<Hvcc>(item)?
+            .ok_or_else(|| format_err!("item {} has no hvcC", item))?
+            .length_size();
+        ensure!(3 != length_size, "invalid NAL length size: {}", length_size);
+
+        for (_, unit) in self.parameter_sets(item)? {
+            into.write_all(&[0, 0, 0, 1])?;
+            into.write_all(&nal::escape_start_codes(unit))?;
+        }
+
+        let mut data_item = self.open_item_data_unchecked(from, item)?;
+        while let Some(length) = read_nal_length(&mut data_item, length_size)? {
+            self.limits.check_nal_size(length)?;
+            let mut unit = Vec::new();
+            (&mut data_item).take(length).read_to_end(&mut unit)?;
+            ensure!(
+                u64(unit.len()) == length,
+                "item data ended inside a NAL unit: {} < {}",
+                unit.len(),
+                length
+            );
+
+            if unit.is_empty() {
                 continue;
             }
 
-            if let Property::HvcCodecSettings(hvcc) = prop {
-                for nal in &hvcc.nals {
-                    // still not confident on this nested array thing
-                    debug_assert_eq!(1, nal.units.len());
-                    for unit in &nal.units {
-                        into.write_all(&[0, 0, 0, 1])?;
-                        into.write_all(unit)?;
-                    }
-                }
-            }
+            into.write_all(&[0, 0, 0, 1])?;
+            into.write_all(&nal::escape_start_codes(&unit))?;
         }
 
-        into.write_all(&[0, 0, 0, 1])?;
-        let mut data_item = self.open_item_data(from, item)?;
-        // length prefix?
-        data_item.read_exact(&mut [0u8; 4])?;
-        io::copy(&mut data_item, &mut into)?;
+        Ok(())
+    }
+
+    // a temporal unit in the low overhead bitstream format, with the av1C sequence header
+    pub fn obu_stream<R: Read + Seek, W: Write>(
+        &self,
+        item: u32,
+        from: R,
+        mut into: W,
+    ) -> Result<(), ParseError> {
+        self.check_essential(item)?;
+
+        let av1c = self
+            .property::<Av1c>(item)?
+            .ok_or_else(|| format_err!("item {} has no av1C", item))?;
 
+        let data = self.read_item_data_unchecked(from, item)?;
+        into.write_all(&av1::temporal_unit(&av1c.config_obus, &data)?)?;
         Ok(())
     }
 
-    pub fn find_pps(&self, item: u32) -> Result<pps::PicParamSet, Error> {
-        for (ids, prop) in &self.props {
-            if !ids.contains(&item) {
-                continue;
-            }
+    // a standalone JPEG stream: the shared headers from any jpgC, then the item data
+    pub fn open_jpeg<R: Read + Seek>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<io::Chain<&[u8], Extents<'_, R>>, ParseError> {
+        self.check_essential(item)?;
+        if Some(mpeg::JPEG) != self.item_type(item) {
+            return Err(error::invalid_argument(format!(
+                "item {} is not a jpeg item",
+                item
+            )));
+        }
 
-            if let Property::HvcCodecSettings(hvcc) = prop {
-                for nal in &hvcc.nals {
-                    if hevc::NAL_PPS_NUT == nal.completeness_and_nal_unit_type {
-                        ensure!(1 == nal.units.len(), "expecting only one unit");
-                        let bytes = &nal.units[0];
-                        // TODO: validate NAL unit header, 2..
-                        return Ok(pps::picture_parameter_set(&mut BitReader::new(
-                            &bytes[2..],
-                        ))?);
-                    }
-                }
+        let prefix = self.jpeg_prefix(item)?;
+        Ok(prefix.chain(self.open_item_data_unchecked(from, item)?))
+    }
+
+    // the headers shared between jpeg items, if the item has any
+    fn jpeg_prefix(&self, item: u32) -> Result<&[u8], Error> {
+        Ok(match self.property::<Jpgc>(item)? {
+            Some(jpgc) => {
+                ensure!(
+                    jpgc.prefix.starts_with(&[0xff, 0xd8]),
+                    "jpgC of item {} doesn't start with a start of image marker",
+                    item
+                );
+                &jpgc.prefix
             }
+            None => &[],
+        })
+    }
+
+    // the samples of an 'unci' item, as laid out by its uncC and cmpd
+    pub fn decode_uncompressed<R: Read + Seek>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<Picture, ParseError> {
+        self.check_essential(item)?;
+        if Some(mpeg::UNCI) != self.item_type(item) {
+            return Err(error::invalid_argument(format!(
+                "item {} is not an uncompressed item",
+                item
+            )));
         }
 
-        bail!("not found");
+        let uncc = self
+            .property::<Uncc>(item)?
+            .ok_or_else(|| format_err!("item {} has no uncC", item))?;
+        let implied;
+        let cmpd = match self.property::<Cmpd>(item)? {
+            Some(cmpd) => cmpd,
+            None => {
+                implied = uncc
+                    .implied_cmpd()
+                    .ok_or_else(|| format_err!("item {} has no cmpd", item))?;
+                &implied
+            }
+        };
+        let ispe = self
+            .property::<Ispe>(item)?
+            .ok_or_else(|| format_err!("item {} has no ispe", item))?;
+
+        self.limits.check_image(u64(ispe.width), u64(ispe.height))?;
+        let data = self.read_item_data_unchecked(from, item)?;
+
+        Ok(uncompressed::decode(
+            uncc,
+            cmpd,
+            ispe.width,
+            ispe.height,
+            &data,
+        )?)
+    }
+
+    fn item_type(&self, item: u32) -> Option<FourCc> {
+        self.items.get(&item).map(|item| item.info.item_type)
+    }
+
+    pub fn find_vps(&self, item: u32) -> Result<vps::VidParamSet, Error> {
+        let rbsp = self.find_parameter_set(item, hevc::NAL_VPS_NUT)?;
+        vps::video_parameter_set(&mut BitReader::new(&rbsp))
+    }
+
+    pub fn find_pps(&self, item: u32) -> Result<pps::PicParamSet, Error> {
+        let rbsp = self.find_parameter_set(item, hevc::NAL_PPS_NUT)?;
+        pps::picture_parameter_set(&mut BitReader::new(&rbsp))
     }
 
-    // TODO: generic?
     pub fn find_sps(&self, item: u32) -> Result<sps::SeqParamSet, Error> {
-        for (ids, prop) in &self.props {
-            if !ids.contains(&item) {
-                continue;
-            }
+        let rbsp = self.find_parameter_set(item, hevc::NAL_SPS_NUT)?;
+        let sps = sps::seq_parameter_set(&mut BitReader::new(&rbsp))?;
+        self.limits.check_image(
+            sps.pic_width_in_luma_samples,
+            sps.pic_height_in_luma_samples,
+        )?;
+        Ok(sps)
+    }
 
-            if let Property::HvcCodecSettings(hvcc) = prop {
-                for nal in &hvcc.nals {
-                    if hevc::NAL_SPS_NUT == nal.completeness_and_nal_unit_type {
-                        ensure!(1 == nal.units.len(), "expecting only one unit");
-                        let bytes = &nal.units[0];
-                        let bytes = NalReader::new(io::Cursor::new(bytes))
-                            .read_nal()?
-                            .ok_or_else(|| format_err!("NalReader didn't"))?;
-                        // TODO: validate NAL unit header, 2..
-                        // TODO: validate no more NALs
-                        return Ok(sps::seq_parameter_set(&mut BitReader::new(&bytes[2..]))?);
-                    }
-                }
-            }
-        }
+    // every NAL unit in every array of the item's hvcC, with its type, in file order
+    pub fn parameter_sets(&self, item: u32) -> Result<Vec<(u8, &[u8])>, Error> {
+        let hvcc = self
+            .property::<Hvcc>(item)?
+            .ok_or_else(|| format_err!("item {} has no hvcC", item))?;
+
+        Ok(hvcc
+            .nals
+            .iter()
+            .flat_map(|nal| {
+                nal.units
+                    .iter()
+                    .map(move |unit| (nal.completeness_and_nal_unit_type & 0x3f, unit.as_slice()))
+            })
+            .collect())
+    }
+
+    // the payload of the first NAL unit of this type, with the header and emulation
+    // prevention removed
+    fn find_parameter_set(&self, item: u32, nal_unit_type: u8) -> Result<Vec<u8>, Error> {
+        let (_, unit) = self
+            .parameter_sets(item)?
+            .into_iter()
+            .find(|&(unit_type, _)| nal_unit_type == unit_type)
+            .ok_or_else(|| format_err!("no NAL unit of type {} for {}", nal_unit_type, item))?;
 
-        bail!("not found");
+        nal::payload(unit, nal_unit_type)
     }
 }
 
 pub struct Extents<'h, R> {
-    inner: R,
+    inner: Source<'h, R>,
     base: u64,
     extents: &'h [Extent],
     current_extent: usize,
@@ -245,9 +914,8 @@ impl<'h, R: Read + Seek> Read for Extents<'h, R> {
             return Ok(0);
         }
 
+        // checked when the extents were opened: every length is non-zero, and every offset fits
         let current = &self.extents[self.current_extent];
-
-        assert_lt!(self.current_pos, current.length);
         let to_read = usize((current.length - self.current_pos).min(u64(buf.len())));
 
         let actually_read = self.inner.read(&mut buf[..to_read])?;
@@ -256,17 +924,11 @@ impl<'h, R: Read + Seek> Read for Extents<'h, R> {
 
         if self.current_pos == current.length {
             self.current_extent += 1;
+            self.current_pos = 0;
 
             if self.current_extent != self.extents.len() {
                 let new_extent = &self.extents[self.current_extent];
-                assert_eq!(
-                    new_extent.index,
-                    u64(self.current_extent),
-                    "extent index out of sequence",
-                );
-
-                self.inner
-                    .seek(SeekFrom::Start(self.base + new_extent.offset))?;
+                self.inner.seek(self.base + new_extent.offset)?;
             }
         }
 
@@ -274,9 +936,44 @@ impl<'h, R: Read + Seek> Read for Extents<'h, R> {
     }
 }
 
-fn get_only_element<T, I: IntoIterator<Item = T>>(from: I) -> Result<T, Error> {
+// where an item's extents point: the file itself, or the 'idat' box
+enum Source<'h, R> {
+    File(R),
+    ItemData(io::Cursor<&'h [u8]>),
+}
+
+impl<'h, R: Read + Seek> Source<'h, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+        match self {
+            Source::File(from) => from.read(buf),
+            Source::ItemData(from) => from.read(buf),
+        }
+    }
+
+    fn seek(&mut self, pos: u64) -> Result<(), io::Error> {
+        match self {
+            Source::File(from) => from.seek(SeekFrom::Start(pos))?,
+            Source::ItemData(from) => from.seek(SeekFrom::Start(pos))?,
+        };
+        Ok(())
+    }
+}
+
+pub fn get_only_element<T, I: IntoIterator<Item = T>>(from: I) -> Result<T, Error> {
     let mut from = from.into_iter();
     let val = from.next().ok_or_else(|| format_err!("no items"))?;
     ensure!(from.next().is_none(), "unexpected second item");
     Ok(val)
 }
+
+// None at a clean end of the data, an error if it stops part way through a length
+fn read_nal_length<R: Read>(mut from: R, length_size: u8) -> Result<Option<u64>, Error> {
+    let mut buf = [0u8; 4];
+    let buf = &mut buf[..usize(length_size)];
+    if 0 == from.read(&mut buf[..1])? {
+        return Ok(None);
+    }
+
+    from.read_exact(&mut buf[1..])?;
+    Ok(Some(BE::read_uint(buf, buf.len())))
+}
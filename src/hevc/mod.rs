@@ -1,8 +1,10 @@
+use std::fmt;
 use std::io::Read;
 
 use bitreader::BitReader;
 use byteorder::ReadBytesExt;
 use byteorder::BE;
+use cast::i64;
 use failure::Error;
 
 mod cabac;
@@ -11,7 +13,7 @@ pub mod nal;
 pub mod pps;
 pub mod sps;
 mod ss;
-mod vps;
+pub mod vps;
 
 use bit::typenum;
 use bit::Bits;
@@ -34,6 +36,7 @@ const NAL_CRA_NUT: u8 = 21;
 const NAL_RSV_IRAP_VCL22: u8 = 22;
 const NAL_RSV_IRAP_VCL23: u8 = 23;
 
+pub const NAL_VPS_NUT: u8 = 32;
 pub const NAL_SPS_NUT: u8 = 33;
 pub const NAL_PPS_NUT: u8 = 34;
 
@@ -52,7 +55,11 @@ pub fn dump<R: Read>(mut from: R, pps: &PicParamSet, sps: &SeqParamSet) -> Resul
 
     ss::slice_segment_header(nal_unit_header.unit_type, &mut read, pps, sps)?;
 
-    assert_eq!(56, read.position());
+    ensure!(
+        56 == read.position(),
+        "slice segment header was {} bits, not 56",
+        read.position()
+    );
 
     println!("{:?}", nal_unit_header);
     Ok(())
@@ -63,11 +70,11 @@ fn nal_unit_header<R: Read>(mut from: R) -> Result<NalUnitHeader, Error> {
 
     let mut bits = Bits::<typenum::U2>::read_exact(from)?;
 
-    ensure!(!bits.read_bool(), "invalid bit prefix for nal unit header");
-    let unit_type = bits.read_u8(6);
-    let nuh_layer_id = bits.read_u8(6);
-    let nuh_temporal_id_plus_1 = bits.read_u8(3);
-    assert!(bits.done());
+    ensure!(!bits.read_bool()?, "invalid bit prefix for nal unit header");
+    let unit_type = bits.read_u8(6)?;
+    let nuh_layer_id = bits.read_u8(6)?;
+    let nuh_temporal_id_plus_1 = bits.read_u8(3)?;
+    ensure!(bits.done(), "nal unit header not fully read");
 
     Ok(NalUnitHeader {
         unit_type,
@@ -87,6 +94,16 @@ fn read_uvlc(from: &mut BitReader) -> Result<u64, Error> {
     Ok(from.read_u64(leading_zeros)? + (1 << leading_zeros) - 1)
 }
 
+// se(v): 0, 1, -1, 2, -2, ...
+fn read_svlc(from: &mut BitReader) -> Result<i64, Error> {
+    let code = read_uvlc(from)?;
+    Ok(if 0 == code % 2 {
+        -i64(code / 2)?
+    } else {
+        i64(code / 2 + 1)?
+    })
+}
+
 fn rbsp_trailing_bits(from: &mut BitReader) -> Result<(), Error> {
     ensure!(from.read_bool()?, "rbsp_trailing_bits must start with one");
     while !from.is_aligned(1) {
@@ -94,3 +111,28 @@ fn rbsp_trailing_bits(from: &mut BitReader) -> Result<(), Error> {
     }
     Ok(())
 }
+
+// One line of a parameter set's dump: the name, with any indent, then the value in a column.
+fn field<T: fmt::Display>(
+    f: &mut fmt::Formatter,
+    width: usize,
+    name: &str,
+    value: T,
+) -> fmt::Result {
+    writeln!(f, "{:width$}{}", name, value, width = width)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitreader::BitReader;
+
+    #[test]
+    fn svlc() {
+        // 1, 010, 011, 00100, 00101: 0, 1, -1, 2, -2
+        let bytes = [0b1010_0110, 0b0100_0010, 0b1000_0000];
+        let mut reader = BitReader::new(&bytes);
+        for expected in &[0, 1, -1, 2, -2] {
+            assert_eq!(*expected, super::read_svlc(&mut reader).unwrap());
+        }
+    }
+}
@@ -1,17 +1,26 @@
 use std::io::Bytes;
 use std::io::Read;
 
+use cast::u64;
 use failure::Error;
 use twoway;
 
+use limits::Limits;
+
 pub struct NalReader<R: Read> {
     inner: Bytes<R>,
+    limits: Limits,
 }
 
 impl<R: Read> NalReader<R> {
     pub fn new(inner: R) -> Self {
+        NalReader::with_limits(inner, Limits::default())
+    }
+
+    pub fn with_limits(inner: R, limits: Limits) -> Self {
         NalReader {
             inner: inner.bytes(),
+            limits,
         }
     }
 
@@ -55,16 +64,95 @@ impl<R: Read> NalReader<R> {
             second = byte;
 
             nal.push(byte);
+            self.limits.check_nal_size(u64(nal.len()))?;
         }
 
         Ok(Some(nal))
     }
 }
 
+// strip the emulation prevention bytes, turning a NAL unit's payload back into an RBSP
+pub fn un_nal(bytes: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if i + 2 < bytes.len() && 0x00 == bytes[i] && 0x00 == bytes[i + 1] && 0x03 == bytes[i + 2] {
+            ret.push(0);
+            ret.push(0);
+            i += 3;
+        } else {
+            ret.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    ret
+}
+
+// NAL units stored in a file should already have emulation prevention, but not every writer
+// bothers. Only sequences which would look like a start code are escaped, so a unit which
+// is already correct is unchanged.
+pub fn escape_start_codes(bytes: &[u8]) -> Vec<u8> {
+    escape(bytes, 0x02)
+}
+
+// the inverse of `un_nal`: turn an RBSP, or anything else `NalReader` has unescaped, back
+// into the payload of a NAL unit
+pub fn add_emulation_prevention(bytes: &[u8]) -> Vec<u8> {
+    escape(bytes, 0x03)
+}
+
+fn escape(bytes: &[u8], max_escaped: u8) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(bytes.len() + bytes.len() / 64);
+    let mut zeros = 0;
+    for &byte in bytes {
+        if zeros >= 2 && byte <= max_escaped {
+            ret.push(0x03);
+            zeros = 0;
+        }
+
+        ret.push(byte);
+
+        if 0x00 == byte {
+            zeros += 1;
+        } else {
+            zeros = 0;
+        }
+    }
+
+    // a trailing zero would be taken as part of the next start code
+    if Some(&0x00) == ret.last() {
+        ret.push(0x03);
+    }
+
+    ret
+}
+
+// the RBSP of a single NAL unit: the two byte header is checked and removed, then the
+// emulation prevention
+pub fn payload(unit: &[u8], nal_unit_type: u8) -> Result<Vec<u8>, Error> {
+    ensure!(unit.len() > 2, "NAL unit is too short: {}", unit.len());
+    ensure!(
+        0 == unit[0] & 0x80,
+        "forbidden zero bit set in NAL unit header"
+    );
+    ensure!(
+        nal_unit_type == (unit[0] >> 1) & 0x3f,
+        "unexpected NAL unit type: {} != {}",
+        (unit[0] >> 1) & 0x3f,
+        nal_unit_type
+    );
+
+    Ok(un_nal(&unit[2..]))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
 
+    use super::add_emulation_prevention;
+    use super::escape_start_codes;
+    use super::un_nal;
     use super::NalReader;
 
     fn nal_read(input: &[u8]) -> Vec<Vec<u8>> {
@@ -89,4 +177,41 @@ mod tests {
         assert_eq!(vec![[0, 0].to_vec()], nal_read(&[0, 0, 3]));
         assert_eq!(vec![[0, 0, 7].to_vec()], nal_read(&[0, 0, 3, 7]));
     }
+
+    #[test]
+    fn un_nalling() {
+        assert_eq!(vec![0u8; 0], un_nal(&[]));
+        assert_eq!(vec![0, 0, 1], un_nal(&[0, 0, 3, 1]));
+        assert_eq!(vec![0, 0, 0, 0], un_nal(&[0, 0, 3, 0, 0, 3]));
+        assert_eq!(vec![7, 0, 0], un_nal(&[7, 0, 0]));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(vec![0u8; 0], escape_start_codes(&[]));
+        assert_eq!(vec![7, 0, 0, 3, 1], escape_start_codes(&[7, 0, 0, 1]));
+        assert_eq!(
+            vec![0, 0, 3, 0, 0, 3, 0, 3],
+            escape_start_codes(&[0, 0, 0, 0, 0])
+        );
+        assert_eq!(vec![0, 0, 3, 1], escape_start_codes(&[0, 0, 3, 1]));
+        assert_eq!(vec![5, 0, 3], escape_start_codes(&[5, 0]));
+        assert_eq!(
+            vec![0, 0, 1, 0, 0, 0, 2],
+            un_nal(&escape_start_codes(&[0, 0, 1, 0, 0, 0, 2]))
+        );
+    }
+
+    #[test]
+    fn emulation_prevention() {
+        assert_eq!(vec![0, 0, 3, 3, 1], add_emulation_prevention(&[0, 0, 3, 1]));
+        let nal = nal_read(&[0, 0, 1, 7, 0, 0, 3, 3, 0, 0, 3, 1])
+            .pop()
+            .expect("one nal");
+        assert_eq!(vec![7, 0, 0, 3, 0, 0, 1], nal);
+        assert_eq!(
+            vec![7, 0, 0, 3, 3, 0, 0, 3, 1],
+            add_emulation_prevention(&nal)
+        );
+    }
 }
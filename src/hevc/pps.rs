@@ -1,7 +1,11 @@
+use std::fmt;
+
 use bitreader::BitReader;
 use failure::Error;
 
+use hevc::field;
 use hevc::rbsp_trailing_bits;
+use hevc::read_svlc;
 use hevc::read_uvlc;
 
 bitflags! {
@@ -33,11 +37,34 @@ bitflags! {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+// as the raw bits, in the order of the syntax
+#[cfg(feature = "serde")]
+impl serde::Serialize for Flags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.bits())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PicParamSet {
     pub pps_pic_parameter_set_id: u64,
     pub pps_seq_parameter_set_id: u64,
     pub num_extra_slice_header_bits: u8,
+    pub num_ref_idx_l0_default_active_minus1: u64,
+    pub num_ref_idx_l1_default_active_minus1: u64,
+    pub init_qp_minus26: i64,
+    pub diff_cu_qp_delta_depth: u64,
+    pub pps_cb_qp_offset: i64,
+    pub pps_cr_qp_offset: i64,
+    pub num_tile_columns_minus1: u64,
+    pub num_tile_rows_minus1: u64,
+    // empty with uniform spacing
+    pub column_width_minus1: Vec<u64>,
+    pub row_height_minus1: Vec<u64>,
+    pub pps_beta_offset_div2: i64,
+    pub pps_tc_offset_div2: i64,
+    pub log2_parallel_merge_level_minus2: u64,
     pub flags: Flags,
 }
 
@@ -53,7 +80,7 @@ pub fn picture_parameter_set(from: &mut BitReader) -> Result<PicParamSet, Error>
     flags |= read_flag(from, Flags::CABAC_INIT_PRESENT)?;
     let num_ref_idx_l0_default_active_minus1 = read_uvlc(from)?;
     let num_ref_idx_l1_default_active_minus1 = read_uvlc(from)?;
-    let init_qp_minus26 = read_uvlc(from)?; // TODO: signed
+    let init_qp_minus26 = read_svlc(from)?;
     flags |= read_flag(from, Flags::CONSTRAINED_INTRA_PRED)?;
     flags |= read_flag(from, Flags::TRANSFORM_SKIP_ENABLED)?;
     let diff_cu_qp_delta_depth = if from.read_bool()? {
@@ -62,26 +89,37 @@ pub fn picture_parameter_set(from: &mut BitReader) -> Result<PicParamSet, Error>
     } else {
         0
     };
-    let pps_cb_qp_offset = read_uvlc(from)?; // TODO: signed
-    let pps_cr_qp_offset = read_uvlc(from)?; // TODO: signed
+    let pps_cb_qp_offset = read_svlc(from)?;
+    let pps_cr_qp_offset = read_svlc(from)?;
     flags |= read_flag(from, Flags::PPS_SLICE_CHROMA_QP_OFFSETS_PRESENT)?;
     flags |= read_flag(from, Flags::WEIGHTED_PRED)?;
     flags |= read_flag(from, Flags::WEIGHTED_BIPRED)?;
     flags |= read_flag(from, Flags::TRANSQUANT_BYPASS_ENABLED)?;
     flags |= read_flag(from, Flags::TILES_ENABLED)?;
     flags |= read_flag(from, Flags::ENTROPY_CODING_SYNC_ENABLED)?;
+    let mut num_tile_columns_minus1 = 0;
+    let mut num_tile_rows_minus1 = 0;
+    let mut column_width_minus1 = Vec::new();
+    let mut row_height_minus1 = Vec::new();
     if flags.contains(Flags::TILES_ENABLED) {
-        let num_tile_columns_minus1 = read_uvlc(from)?;
-        let num_tile_rows_minus1 = read_uvlc(from)?;
+        num_tile_columns_minus1 = read_uvlc(from)?;
+        num_tile_rows_minus1 = read_uvlc(from)?;
+        // at most 20 by 22, at the highest level
+        ensure!(
+            num_tile_columns_minus1 < 20 && num_tile_rows_minus1 < 22,
+            "too many tiles: {} by {}",
+            num_tile_columns_minus1 + 1,
+            num_tile_rows_minus1 + 1
+        );
         flags |= read_flag(from, Flags::UNIFORM_SPACING)?;
 
         if !flags.contains(Flags::UNIFORM_SPACING) {
             for _ in 0..num_tile_columns_minus1 {
-                read_uvlc(from)?;
+                column_width_minus1.push(read_uvlc(from)?);
             }
 
             for _ in 0..num_tile_rows_minus1 {
-                read_uvlc(from)?;
+                row_height_minus1.push(read_uvlc(from)?);
             }
         }
 
@@ -89,12 +127,14 @@ pub fn picture_parameter_set(from: &mut BitReader) -> Result<PicParamSet, Error>
     }
     flags |= read_flag(from, Flags::PPS_LOOP_FILTER_ACROSS_SLICES_ENABLED)?;
     flags |= read_flag(from, Flags::DEBLOCKING_FILTER_CONTROL_PRESENT)?;
+    let mut pps_beta_offset_div2 = 0;
+    let mut pps_tc_offset_div2 = 0;
     if flags.contains(Flags::DEBLOCKING_FILTER_CONTROL_PRESENT) {
         flags |= read_flag(from, Flags::DEBLOCKING_FILTER_OVERRIDE_ENABLED)?;
         flags |= read_flag(from, Flags::PPS_DEBLOCKING_FILTER_DISABLED)?;
         if !flags.contains(Flags::PPS_DEBLOCKING_FILTER_DISABLED) {
-            let pps_beta_offset_div2 = read_uvlc(from)?; // TODO: signed
-            let pps_tc_offset_div2 = read_uvlc(from)?; // TODO: signed
+            pps_beta_offset_div2 = read_svlc(from)?;
+            pps_tc_offset_div2 = read_svlc(from)?;
         }
     }
     flags |= read_flag(from, Flags::PPS_SCALING_LIST_DATA_PRESENT)?;
@@ -117,10 +157,243 @@ pub fn picture_parameter_set(from: &mut BitReader) -> Result<PicParamSet, Error>
         pps_pic_parameter_set_id,
         pps_seq_parameter_set_id,
         num_extra_slice_header_bits,
+        num_ref_idx_l0_default_active_minus1,
+        num_ref_idx_l1_default_active_minus1,
+        init_qp_minus26,
+        diff_cu_qp_delta_depth,
+        pps_cb_qp_offset,
+        pps_cr_qp_offset,
+        num_tile_columns_minus1,
+        num_tile_rows_minus1,
+        column_width_minus1,
+        row_height_minus1,
+        pps_beta_offset_div2,
+        pps_tc_offset_div2,
+        log2_parallel_merge_level_minus2,
         flags,
     })
 }
 
+// Field by field, in the order libde265 dumps them, which is mostly that of the syntax.
+impl fmt::Display for PicParamSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const WIDTH: usize = 45;
+        let flag = |flag| u8::from(self.flags.contains(flag));
+        let list = |values: &[u64]| {
+            values
+                .iter()
+                .map(|value| (value + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        field(
+            f,
+            WIDTH,
+            "pic_parameter_set_id",
+            self.pps_pic_parameter_set_id,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "seq_parameter_set_id",
+            self.pps_seq_parameter_set_id,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "dependent_slice_segments_enabled_flag",
+            flag(Flags::DEPENDENT_SLICE_SEGMENTS_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "sign_data_hiding_flag",
+            flag(Flags::SIGN_DATA_HIDING_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "cabac_init_present_flag",
+            flag(Flags::CABAC_INIT_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "num_ref_idx_l0_default_active",
+            self.num_ref_idx_l0_default_active_minus1.saturating_add(1),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "num_ref_idx_l1_default_active",
+            self.num_ref_idx_l1_default_active_minus1.saturating_add(1),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "pic_init_qp",
+            self.init_qp_minus26.saturating_add(26),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "constrained_intra_pred_flag",
+            flag(Flags::CONSTRAINED_INTRA_PRED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "transform_skip_enabled_flag",
+            flag(Flags::TRANSFORM_SKIP_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "cu_qp_delta_enabled_flag",
+            flag(Flags::CU_QP_DELTA_ENABLED),
+        )?;
+        if self.flags.contains(Flags::CU_QP_DELTA_ENABLED) {
+            field(
+                f,
+                WIDTH,
+                "  diff_cu_qp_delta_depth",
+                self.diff_cu_qp_delta_depth,
+            )?;
+        }
+        field(f, WIDTH, "pic_cb_qp_offset", self.pps_cb_qp_offset)?;
+        field(f, WIDTH, "pic_cr_qp_offset", self.pps_cr_qp_offset)?;
+        field(
+            f,
+            WIDTH,
+            "pps_slice_chroma_qp_offsets_present_flag",
+            flag(Flags::PPS_SLICE_CHROMA_QP_OFFSETS_PRESENT),
+        )?;
+        field(f, WIDTH, "weighted_pred_flag", flag(Flags::WEIGHTED_PRED))?;
+        field(
+            f,
+            WIDTH,
+            "weighted_bipred_flag",
+            flag(Flags::WEIGHTED_BIPRED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "output_flag_present_flag",
+            flag(Flags::OUTPUT_FLAG_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "transquant_bypass_enable_flag",
+            flag(Flags::TRANSQUANT_BYPASS_ENABLED),
+        )?;
+        field(f, WIDTH, "tiles_enabled_flag", flag(Flags::TILES_ENABLED))?;
+        field(
+            f,
+            WIDTH,
+            "entropy_coding_sync_enabled_flag",
+            flag(Flags::ENTROPY_CODING_SYNC_ENABLED),
+        )?;
+        if self.flags.contains(Flags::TILES_ENABLED) {
+            field(
+                f,
+                WIDTH,
+                "  num_tile_columns",
+                self.num_tile_columns_minus1 + 1,
+            )?;
+            field(f, WIDTH, "  num_tile_rows", self.num_tile_rows_minus1 + 1)?;
+            field(
+                f,
+                WIDTH,
+                "  uniform_spacing_flag",
+                flag(Flags::UNIFORM_SPACING),
+            )?;
+            if !self.flags.contains(Flags::UNIFORM_SPACING) {
+                field(f, WIDTH, "  column_widths", list(&self.column_width_minus1))?;
+                field(f, WIDTH, "  row_heights", list(&self.row_height_minus1))?;
+            }
+            field(
+                f,
+                WIDTH,
+                "  loop_filter_across_tiles_enabled_flag",
+                flag(Flags::LOOP_FILTER_ACROSS_TILES_ENABLED),
+            )?;
+        }
+        field(
+            f,
+            WIDTH,
+            "pps_loop_filter_across_slices_enabled_flag",
+            flag(Flags::PPS_LOOP_FILTER_ACROSS_SLICES_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "deblocking_filter_control_present_flag",
+            flag(Flags::DEBLOCKING_FILTER_CONTROL_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "deblocking_filter_override_enabled_flag",
+            flag(Flags::DEBLOCKING_FILTER_OVERRIDE_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "pic_disable_deblocking_filter_flag",
+            flag(Flags::PPS_DEBLOCKING_FILTER_DISABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "beta_offset",
+            self.pps_beta_offset_div2.saturating_mul(2),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "tc_offset",
+            self.pps_tc_offset_div2.saturating_mul(2),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "pic_scaling_list_data_present_flag",
+            flag(Flags::PPS_SCALING_LIST_DATA_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "lists_modification_present_flag",
+            flag(Flags::LISTS_MODIFICATION_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "log2_parallel_merge_level",
+            self.log2_parallel_merge_level_minus2.saturating_add(2),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "num_extra_slice_header_bits",
+            self.num_extra_slice_header_bits,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "slice_segment_header_extension_present_flag",
+            flag(Flags::SLICE_SEGMENT_HEADER_EXTENSION_PRESENT),
+        )?;
+        field(f, WIDTH, "pps_extension_flag", flag(Flags::PPS_EXTENSION))?;
+        // we don't read extensions, so these are always zero
+        field(f, WIDTH, "pps_range_extension_flag", 0)?;
+        field(f, WIDTH, "pps_multilayer_extension_flag", 0)?;
+        field(f, WIDTH, "pps_extension_6bits", 0)
+    }
+}
+
 #[inline]
 fn read_flag(from: &mut BitReader, flag: Flags) -> Result<Flags, Error> {
     Ok(if from.read_bool()? {
@@ -141,7 +414,12 @@ mod tests {
 
         let mut reader = BitReader::new(&bytes);
 
-        super::picture_parameter_set(&mut reader).unwrap();
+        let pps = super::picture_parameter_set(&mut reader).unwrap();
         assert_eq!(u64(bytes.len()) * 8, reader.position());
+        assert_eq!(1, pps.diff_cu_qp_delta_depth);
+        assert_eq!(
+            include_str!("../../tests/data/road.pps.txt"),
+            pps.to_string()
+        );
     }
 }
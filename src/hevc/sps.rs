@@ -1,10 +1,19 @@
+use std::fmt;
+
 use bitreader::BitReader;
 use cast::u8;
+use cast::usize;
 use failure::Error;
 
+use error::unsupported;
+use hevc::field;
 use hevc::rbsp_trailing_bits;
 use hevc::read_uvlc;
+use hevc::vps::dump_sub_layer_ordering;
 use hevc::vps::profile_tier_level;
+use hevc::vps::sub_layer_ordering_info;
+use hevc::vps::ProfileTierLevel;
+use hevc::vps::SubLayerOrdering;
 
 bitflags! {
     #[derive(Default)]
@@ -28,37 +37,161 @@ bitflags! {
     }
 }
 
+// as the raw bits, in the order of the syntax
+#[cfg(feature = "serde")]
+impl serde::Serialize for Flags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(self.bits())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SeqParamSet {
+    pub sps_video_parameter_set_id: u8,
+    pub sps_max_sub_layers_minus1: u8,
+    pub profile_tier_level: ProfileTierLevel,
+    pub sps_seq_parameter_set_id: u64,
+    pub chroma_format_idc: u8,
+    pub pic_width_in_luma_samples: u64,
+    pub pic_height_in_luma_samples: u64,
+    // left, right, top, bottom; in chroma sample units, all zero if there's no window
+    pub conf_win_offsets: [u64; 4],
+    pub bit_depth_luma_minus8: u8,
+    pub bit_depth_chroma_minus8: u8,
     pub log2_max_pic_order_cnt_lsb_minus4: u8,
+    pub sub_layer_ordering: Vec<SubLayerOrdering>,
+    pub log2_min_luma_coding_block_size_minus3: u64,
+    pub log2_diff_max_min_luma_coding_block_size: u64,
+    pub log2_min_luma_transform_block_size_minus2: u64,
+    pub log2_diff_max_min_luma_transform_block_size: u64,
+    pub max_transform_hierarchy_depth_inter: u64,
+    pub max_transform_hierarchy_depth_intra: u64,
+    pub pcm: Option<Pcm>,
     pub num_short_term_ref_pic_sets: u8,
     pub num_long_term_ref_pics_sps: u8,
+    pub vui: Option<Vui>,
     pub flags: Flags,
 }
 
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Pcm {
+    pub pcm_sample_bit_depth_luma_minus1: u8,
+    pub pcm_sample_bit_depth_chroma_minus1: u8,
+    pub log2_min_pcm_luma_coding_block_size_minus3: u64,
+    pub log2_diff_max_min_pcm_luma_coding_block_size: u64,
+}
+
+// Annex E; fields that aren't present hold the values the spec says to infer
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Vui {
+    pub aspect_ratio_info_present_flag: bool,
+    pub aspect_ratio_idc: u8,
+    // from the table for the idc, unless it's EXTENDED_SAR; 0:0 if unspecified
+    pub sar_width: u16,
+    pub sar_height: u16,
+    pub overscan_info_present_flag: bool,
+    pub overscan_appropriate_flag: bool,
+    pub video_signal_type_present_flag: bool,
+    pub video_format: u8,
+    pub video_full_range_flag: bool,
+    pub colour_description_present_flag: bool,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coeffs: u8,
+    pub chroma_loc_info_present_flag: bool,
+    pub chroma_sample_loc_type_top_field: u64,
+    pub chroma_sample_loc_type_bottom_field: u64,
+    pub neutral_chroma_indication_flag: bool,
+    pub field_seq_flag: bool,
+    pub frame_field_info_present_flag: bool,
+    pub default_display_window_flag: bool,
+    // left, right, top, bottom, as `conf_win_offsets`
+    pub def_disp_win_offsets: [u64; 4],
+    pub vui_timing_info_present_flag: bool,
+    pub vui_num_units_in_tick: u32,
+    pub vui_time_scale: u32,
+    pub vui_poc_proportional_to_timing_flag: bool,
+    pub vui_num_ticks_poc_diff_one_minus1: u64,
+    pub vui_hrd_parameters_present_flag: bool,
+    pub bitstream_restriction_flag: bool,
+    pub tiles_fixed_structure_flag: bool,
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    pub restricted_ref_pic_lists_flag: bool,
+    pub min_spatial_segmentation_idc: u64,
+    pub max_bytes_per_pic_denom: u64,
+    pub max_bits_per_min_cu_denom: u64,
+    pub log2_max_mv_length_horizontal: u64,
+    pub log2_max_mv_length_vertical: u64,
+}
+
+impl SeqParamSet {
+    // SubWidthC and SubHeightC: how many luma samples there are for each chroma sample
+    pub fn chroma_subsampling(&self) -> (u64, u64) {
+        match self.chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        }
+    }
+
+    pub fn ctb_size_y(&self) -> u64 {
+        1 << (self.log2_min_luma_coding_block_size_minus3
+            + 3
+            + self.log2_diff_max_min_luma_coding_block_size)
+    }
+
+    // the size of the decoded picture after the conformance window has been applied
+    pub fn cropped_size(&self) -> (u64, u64) {
+        let (sub_width_c, sub_height_c) = self.chroma_subsampling();
+
+        let [left, right, top, bottom] = self.conf_win_offsets;
+        (
+            self.pic_width_in_luma_samples
+                .saturating_sub(left.saturating_add(right).saturating_mul(sub_width_c)),
+            self.pic_height_in_luma_samples
+                .saturating_sub(top.saturating_add(bottom).saturating_mul(sub_height_c)),
+        )
+    }
+}
+
 pub fn seq_parameter_set(from: &mut BitReader) -> Result<SeqParamSet, Error> {
     let mut flags = Flags::default();
 
     let sps_video_parameter_set_id = from.read_u8(4)?;
     let sps_max_sub_layers_minus1 = from.read_u8(3)?;
     flags |= read_flag(from, Flags::SPS_TEMPORAL_ID_NESTING)?;
-    // minus1 here is complicated, it appears to actually want the minus1
-    profile_tier_level(from, sps_max_sub_layers_minus1)?;
+    let profile_tier_level = profile_tier_level(from, sps_max_sub_layers_minus1)?;
     let sps_seq_parameter_set_id = read_uvlc(from)?;
-    let chroma_format_idc = read_uvlc(from)?;
+    let chroma_format_idc = {
+        let val = read_uvlc(from)?;
+        ensure!(val <= 3, "chroma_format_idc out of range: {}", val);
+        u8(val).unwrap()
+    };
     if 3 == chroma_format_idc {
         flags |= read_flag(from, Flags::SEPARATE_COLOUR_PLANE)?;
     }
     let pic_width_in_luma_samples = read_uvlc(from)?;
     let pic_height_in_luma_samples = read_uvlc(from)?;
     flags |= read_flag(from, Flags::CONFORMANCE_WINDOW)?;
+    let mut conf_win_offsets = [0u64; 4];
     if flags.contains(Flags::CONFORMANCE_WINDOW) {
-        let conf_win_left_offset = read_uvlc(from)?;
-        let conf_win_right_offset = read_uvlc(from)?;
-        let conf_win_top_offset = read_uvlc(from)?;
-        let conf_win_bottom_offset = read_uvlc(from)?;
+        for offset in &mut conf_win_offsets {
+            *offset = read_uvlc(from)?;
+        }
     }
-    let bit_depth_luma_minus8 = read_uvlc(from)?;
-    let bit_depth_chroma_minus8 = read_uvlc(from)?;
+    let bit_depth_luma_minus8 = {
+        let val = read_uvlc(from)?;
+        ensure!(val <= 8, "bit_depth_luma_minus8 out of range: {}", val);
+        u8(val).unwrap()
+    };
+    let bit_depth_chroma_minus8 = {
+        let val = read_uvlc(from)?;
+        ensure!(val <= 8, "bit_depth_chroma_minus8 out of range: {}", val);
+        u8(val).unwrap()
+    };
     let log2_max_pic_order_cnt_lsb_minus4 = {
         let val = read_uvlc(from)?;
         ensure!(
@@ -69,18 +202,31 @@ pub fn seq_parameter_set(from: &mut BitReader) -> Result<SeqParamSet, Error> {
         u8(val).unwrap()
     };
     flags |= read_flag(from, Flags::SPS_SUB_LAYER_ORDERING_INFO_PRESENT)?;
-    if flags.contains(Flags::SPS_SUB_LAYER_ORDERING_INFO_PRESENT) {
-        for i in 0..(sps_max_sub_layers_minus1 + 1) {
-            let sps_max_dec_pic_buffering_minus1 = read_uvlc(from)?;
-            let sps_max_num_reorder_pics = read_uvlc(from)?;
-            let sps_max_latency_increase_plus1 = read_uvlc(from)?;
-        }
-    }
+    let sub_layer_ordering = sub_layer_ordering_info(
+        from,
+        flags.contains(Flags::SPS_SUB_LAYER_ORDERING_INFO_PRESENT),
+        sps_max_sub_layers_minus1,
+    )?;
 
     let log2_min_luma_coding_block_size_minus3 = read_uvlc(from)?;
     let log2_diff_max_min_luma_coding_block_size = read_uvlc(from)?;
-    let log2_min_transform_block_size_minus2 = read_uvlc(from)?;
-    let log2_diff_max_min_transform_block_size = read_uvlc(from)?;
+    // CtbLog2SizeY is at most 6, in every profile
+    ensure!(
+        log2_min_luma_coding_block_size_minus3 + log2_diff_max_min_luma_coding_block_size <= 3,
+        "coding block sizes out of range: {} + {}",
+        log2_min_luma_coding_block_size_minus3,
+        log2_diff_max_min_luma_coding_block_size
+    );
+    let log2_min_luma_transform_block_size_minus2 = read_uvlc(from)?;
+    let log2_diff_max_min_luma_transform_block_size = read_uvlc(from)?;
+    // MaxTbLog2SizeY is at most 5
+    ensure!(
+        log2_min_luma_transform_block_size_minus2 + log2_diff_max_min_luma_transform_block_size
+            <= 3,
+        "transform block sizes out of range: {} + {}",
+        log2_min_luma_transform_block_size_minus2,
+        log2_diff_max_min_luma_transform_block_size
+    );
     let max_transform_hierarchy_depth_inter = read_uvlc(from)?;
     let max_transform_hierarchy_depth_intra = read_uvlc(from)?;
     flags |= read_flag(from, Flags::SCALING_LIST_ENABLED)?;
@@ -95,11 +241,14 @@ pub fn seq_parameter_set(from: &mut BitReader) -> Result<SeqParamSet, Error> {
     flags |= read_flag(from, Flags::SAMPLE_ADAPTIVE_OFFSET_ENABLED)?;
     flags |= read_flag(from, Flags::PCM_ENABLED)?;
 
+    let mut pcm = None;
     if flags.contains(Flags::PCM_ENABLED) {
-        let pcm_sample_bit_depth_luma_minus1 = from.read_u8(4)?;
-        let pcm_sample_bit_depth_chroma_minus1 = from.read_u8(4)?;
-        let log2_min_pcm_luma_coding_block_size_minus3 = read_uvlc(from)?;
-        let log2_diff_max_min_pcm_luma_coding_block_size = read_uvlc(from)?;
+        pcm = Some(Pcm {
+            pcm_sample_bit_depth_luma_minus1: from.read_u8(4)?,
+            pcm_sample_bit_depth_chroma_minus1: from.read_u8(4)?,
+            log2_min_pcm_luma_coding_block_size_minus3: read_uvlc(from)?,
+            log2_diff_max_min_pcm_luma_coding_block_size: read_uvlc(from)?,
+        });
         flags |= read_flag(from, Flags::PCM_LOOP_FILTER_DISABLED)?;
     }
 
@@ -136,8 +285,9 @@ pub fn seq_parameter_set(from: &mut BitReader) -> Result<SeqParamSet, Error> {
     flags |= read_flag(from, Flags::SPS_TEMPORAL_MVP_ENABLED)?;
     flags |= read_flag(from, Flags::STRONG_INTRA_SMOOTHING_ENABLED)?;
     flags |= read_flag(from, Flags::VUI_PARAMETERS_PRESENT)?;
+    let mut vui = None;
     if flags.contains(Flags::VUI_PARAMETERS_PRESENT) {
-        vui_parameters(from)?;
+        vui = Some(vui_parameters(from)?);
     }
     flags |= read_flag(from, Flags::SPS_EXTENSION)?;
     ensure!(
@@ -146,79 +296,166 @@ pub fn seq_parameter_set(from: &mut BitReader) -> Result<SeqParamSet, Error> {
     );
     rbsp_trailing_bits(from)?;
     Ok(SeqParamSet {
+        sps_video_parameter_set_id,
+        sps_max_sub_layers_minus1,
+        profile_tier_level,
+        sps_seq_parameter_set_id,
+        chroma_format_idc,
+        pic_width_in_luma_samples,
+        pic_height_in_luma_samples,
+        conf_win_offsets,
+        bit_depth_luma_minus8,
+        bit_depth_chroma_minus8,
         log2_max_pic_order_cnt_lsb_minus4,
+        sub_layer_ordering,
+        log2_min_luma_coding_block_size_minus3,
+        log2_diff_max_min_luma_coding_block_size,
+        log2_min_luma_transform_block_size_minus2,
+        log2_diff_max_min_luma_transform_block_size,
+        max_transform_hierarchy_depth_inter,
+        max_transform_hierarchy_depth_intra,
+        pcm,
         num_short_term_ref_pic_sets,
         num_long_term_ref_pics_sps,
+        vui,
         flags,
     })
 }
 
-fn vui_parameters(from: &mut BitReader) -> Result<(), Error> {
+// Table E.1, from 1; 0 is unspecified, and the rest up to EXTENDED_SAR are reserved
+const SAMPLE_ASPECT_RATIOS: [(u16, u16); 16] = [
+    (1, 1),
+    (12, 11),
+    (10, 11),
+    (16, 11),
+    (40, 33),
+    (24, 11),
+    (20, 11),
+    (32, 11),
+    (80, 33),
+    (18, 11),
+    (15, 11),
+    (64, 33),
+    (160, 99),
+    (4, 3),
+    (3, 2),
+    (2, 1),
+];
+
+fn vui_parameters(from: &mut BitReader) -> Result<Vui, Error> {
     const EXTENDED_SAR: u8 = 255;
-    let aspect_ratio_info_present_flag = from.read_bool()?;
-    if aspect_ratio_info_present_flag {
-        let aspect_ratio_idc = from.read_u8(8)?;
-        if aspect_ratio_idc == EXTENDED_SAR {
-            let sar_width = from.read_u16(16)?;
-            let sar_height = from.read_u16(16)?;
+
+    let mut vui = Vui {
+        aspect_ratio_info_present_flag: from.read_bool()?,
+        aspect_ratio_idc: 0,
+        sar_width: 0,
+        sar_height: 0,
+        overscan_info_present_flag: false,
+        overscan_appropriate_flag: false,
+        video_signal_type_present_flag: false,
+        // unspecified
+        video_format: 5,
+        video_full_range_flag: false,
+        colour_description_present_flag: false,
+        colour_primaries: 2,
+        transfer_characteristics: 2,
+        matrix_coeffs: 2,
+        chroma_loc_info_present_flag: false,
+        chroma_sample_loc_type_top_field: 0,
+        chroma_sample_loc_type_bottom_field: 0,
+        neutral_chroma_indication_flag: false,
+        field_seq_flag: false,
+        frame_field_info_present_flag: false,
+        default_display_window_flag: false,
+        def_disp_win_offsets: [0; 4],
+        vui_timing_info_present_flag: false,
+        vui_num_units_in_tick: 0,
+        vui_time_scale: 0,
+        vui_poc_proportional_to_timing_flag: false,
+        vui_num_ticks_poc_diff_one_minus1: 0,
+        vui_hrd_parameters_present_flag: false,
+        bitstream_restriction_flag: false,
+        tiles_fixed_structure_flag: false,
+        motion_vectors_over_pic_boundaries_flag: true,
+        restricted_ref_pic_lists_flag: false,
+        min_spatial_segmentation_idc: 0,
+        max_bytes_per_pic_denom: 2,
+        max_bits_per_min_cu_denom: 1,
+        log2_max_mv_length_horizontal: 15,
+        log2_max_mv_length_vertical: 15,
+    };
+
+    if vui.aspect_ratio_info_present_flag {
+        vui.aspect_ratio_idc = from.read_u8(8)?;
+        match vui.aspect_ratio_idc {
+            EXTENDED_SAR => {
+                vui.sar_width = from.read_u16(16)?;
+                vui.sar_height = from.read_u16(16)?;
+            }
+            0 => (),
+            idc => {
+                if let Some(&(width, height)) = SAMPLE_ASPECT_RATIOS.get(usize(idc) - 1) {
+                    vui.sar_width = width;
+                    vui.sar_height = height;
+                }
+            }
         }
     }
-    let overscan_info_present_flag = from.read_bool()?;
-    if overscan_info_present_flag {
-        let overscan_appropriate_flag = from.read_bool()?;
+    vui.overscan_info_present_flag = from.read_bool()?;
+    if vui.overscan_info_present_flag {
+        vui.overscan_appropriate_flag = from.read_bool()?;
     }
-    let video_signal_type_present_flag = from.read_bool()?;
-    if video_signal_type_present_flag {
-        let video_format = from.read_u8(3)?;
-        let video_full_range_flag = from.read_bool()?;
-        let colour_description_present_flag = from.read_bool()?;
-        if colour_description_present_flag {
-            let colour_primaries = from.read_u8(8)?;
-            let transfer_characteristics = from.read_u8(8)?;
-            let matrix_coeffs = from.read_u8(8)?;
+    vui.video_signal_type_present_flag = from.read_bool()?;
+    if vui.video_signal_type_present_flag {
+        vui.video_format = from.read_u8(3)?;
+        vui.video_full_range_flag = from.read_bool()?;
+        vui.colour_description_present_flag = from.read_bool()?;
+        if vui.colour_description_present_flag {
+            vui.colour_primaries = from.read_u8(8)?;
+            vui.transfer_characteristics = from.read_u8(8)?;
+            vui.matrix_coeffs = from.read_u8(8)?;
         }
     }
-    let chroma_loc_info_present_flag = from.read_bool()?;
-    if chroma_loc_info_present_flag {
-        let chroma_sample_loc_type_top_field = read_uvlc(from)?;
-        let chroma_sample_loc_type_bottom_field = read_uvlc(from)?;
+    vui.chroma_loc_info_present_flag = from.read_bool()?;
+    if vui.chroma_loc_info_present_flag {
+        vui.chroma_sample_loc_type_top_field = read_uvlc(from)?;
+        vui.chroma_sample_loc_type_bottom_field = read_uvlc(from)?;
     }
-    let neutral_chroma_indication_flag = from.read_bool()?;
-    let field_seq_flag = from.read_bool()?;
-    let frame_field_info_present_flag = from.read_bool()?;
-    let default_display_window_flag = from.read_bool()?;
-    if default_display_window_flag {
-        let def_disp_win_left_offset = read_uvlc(from)?;
-        let def_disp_win_right_offset = read_uvlc(from)?;
-        let def_disp_win_top_offset = read_uvlc(from)?;
-        let def_disp_win_bottom_offset = read_uvlc(from)?;
+    vui.neutral_chroma_indication_flag = from.read_bool()?;
+    vui.field_seq_flag = from.read_bool()?;
+    vui.frame_field_info_present_flag = from.read_bool()?;
+    vui.default_display_window_flag = from.read_bool()?;
+    if vui.default_display_window_flag {
+        for offset in &mut vui.def_disp_win_offsets {
+            *offset = read_uvlc(from)?;
+        }
     }
-    let vui_timing_info_present_flag = from.read_bool()?;
-    if vui_timing_info_present_flag {
-        let vui_num_units_in_tick = from.read_u32(32)?;
-        let vui_time_scale = from.read_u32(32)?;
-        let vui_poc_proportional_to_timing_flag = from.read_bool()?;
-        if vui_poc_proportional_to_timing_flag {
-            let vui_num_ticks_poc_diff_one_minus1 = read_uvlc(from)?;
+    vui.vui_timing_info_present_flag = from.read_bool()?;
+    if vui.vui_timing_info_present_flag {
+        vui.vui_num_units_in_tick = from.read_u32(32)?;
+        vui.vui_time_scale = from.read_u32(32)?;
+        vui.vui_poc_proportional_to_timing_flag = from.read_bool()?;
+        if vui.vui_poc_proportional_to_timing_flag {
+            vui.vui_num_ticks_poc_diff_one_minus1 = read_uvlc(from)?;
         }
-        let vui_hrd_parameters_present_flag = from.read_bool()?;
-        if vui_hrd_parameters_present_flag {
+        vui.vui_hrd_parameters_present_flag = from.read_bool()?;
+        if vui.vui_hrd_parameters_present_flag {
             bail!("hrd_parameters(1, sps_max_sub_layers_minus1)");
         }
     }
-    let bitstream_restriction_flag = from.read_bool()?;
-    if bitstream_restriction_flag {
-        let tiles_fixed_structure_flag = from.read_bool()?;
-        let motion_vectors_over_pic_boundaries_flag = from.read_bool()?;
-        let restricted_ref_pic_lists_flag = from.read_bool()?;
-        let min_spatial_segmentation_idc = read_uvlc(from)?;
-        let max_bytes_per_pic_denom = read_uvlc(from)?;
-        let max_bits_per_min_cu_denom = read_uvlc(from)?;
-        let log2_max_mv_length_horizontal = read_uvlc(from)?;
-        let log2_max_mv_length_vertical = read_uvlc(from)?;
+    vui.bitstream_restriction_flag = from.read_bool()?;
+    if vui.bitstream_restriction_flag {
+        vui.tiles_fixed_structure_flag = from.read_bool()?;
+        vui.motion_vectors_over_pic_boundaries_flag = from.read_bool()?;
+        vui.restricted_ref_pic_lists_flag = from.read_bool()?;
+        vui.min_spatial_segmentation_idc = read_uvlc(from)?;
+        vui.max_bytes_per_pic_denom = read_uvlc(from)?;
+        vui.max_bits_per_min_cu_denom = read_uvlc(from)?;
+        vui.log2_max_mv_length_horizontal = read_uvlc(from)?;
+        vui.log2_max_mv_length_vertical = read_uvlc(from)?;
     }
 
-    Ok(())
+    Ok(vui)
 }
 
 fn short_term_ref_pic_set(
@@ -233,18 +470,10 @@ fn short_term_ref_pic_set(
     };
 
     if inter_ref_pic_set_prediction_flag {
-        bail!("unimplemented inter-ref");
-        if st_rps_idx == num_short_term_ref_pic_sets {
-            let delta_idx_minus1 = read_uvlc(from)?;
-        }
-        let delta_rps_sign = from.read_bool()?;
-        let abs_delta_rps_minus1 = read_uvlc(from)?;
-        for j in 0..unimplemented!("NumDeltaPocs[RefRpsIdx]") {
-            let used_by_curr_pic_flag = from.read_bool()?;
-            if !used_by_curr_pic_flag {
-                let use_delta_flag = from.read_bool()?;
-            }
-        }
+        // needs NumDeltaPocs from the set it predicts from, which we don't keep
+        return Err(unsupported(
+            "inter short-term reference picture set prediction".to_string(),
+        ));
     } else {
         let num_negative_pics = read_uvlc(from)?;
         let num_positive_pics = read_uvlc(from)?;
@@ -260,21 +489,467 @@ fn short_term_ref_pic_set(
     Ok(())
 }
 
-fn un_nal(bytes: &[u8]) -> Vec<u8> {
-    let mut ret = Vec::with_capacity(bytes.len());
-    let mut i = 0;
-    while i < bytes.len() {
-        if i < bytes.len() - 2 && 0x00 == bytes[i] && 0x00 == bytes[i + 1] && 0x03 == bytes[i + 2] {
-            ret.push(0);
-            ret.push(0);
-            i += 3;
-        } else {
-            ret.push(bytes[i]);
-            i += 1;
+// Field by field, in the order of the syntax, with the values derived from them at the end.
+impl fmt::Display for SeqParamSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const WIDTH: usize = 43;
+        let flag = |flag| u8::from(self.flags.contains(flag));
+
+        field(
+            f,
+            WIDTH,
+            "video_parameter_set_id",
+            self.sps_video_parameter_set_id,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "sps_max_sub_layers",
+            self.sps_max_sub_layers_minus1 + 1,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "sps_temporal_id_nesting_flag",
+            flag(Flags::SPS_TEMPORAL_ID_NESTING),
+        )?;
+        self.profile_tier_level.dump(f, WIDTH)?;
+        field(
+            f,
+            WIDTH,
+            "seq_parameter_set_id",
+            self.sps_seq_parameter_set_id,
+        )?;
+        let chroma_format = match self.chroma_format_idc {
+            0 => "4:0:0",
+            1 => "4:2:0",
+            2 => "4:2:2",
+            _ => "4:4:4",
+        };
+        field(
+            f,
+            WIDTH,
+            "chroma_format_idc",
+            format!("{} ({})", self.chroma_format_idc, chroma_format),
+        )?;
+        if 3 == self.chroma_format_idc {
+            field(
+                f,
+                WIDTH,
+                "separate_colour_plane_flag",
+                flag(Flags::SEPARATE_COLOUR_PLANE),
+            )?;
+        }
+        field(
+            f,
+            WIDTH,
+            "pic_width_in_luma_samples",
+            self.pic_width_in_luma_samples,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "pic_height_in_luma_samples",
+            self.pic_height_in_luma_samples,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "conformance_window_flag",
+            flag(Flags::CONFORMANCE_WINDOW),
+        )?;
+        let [left, right, top, bottom] = self.conf_win_offsets;
+        field(f, WIDTH, "conf_win_left_offset", left)?;
+        field(f, WIDTH, "conf_win_right_offset", right)?;
+        field(f, WIDTH, "conf_win_top_offset", top)?;
+        field(f, WIDTH, "conf_win_bottom_offset", bottom)?;
+        field(f, WIDTH, "bit_depth_luma", self.bit_depth_luma_minus8 + 8)?;
+        field(
+            f,
+            WIDTH,
+            "bit_depth_chroma",
+            self.bit_depth_chroma_minus8 + 8,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "log2_max_pic_order_cnt_lsb",
+            self.log2_max_pic_order_cnt_lsb_minus4 + 4,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "sps_sub_layer_ordering_info_present_flag",
+            flag(Flags::SPS_SUB_LAYER_ORDERING_INFO_PRESENT),
+        )?;
+        dump_sub_layer_ordering(f, WIDTH, "sps", &self.sub_layer_ordering)?;
+        field(
+            f,
+            WIDTH,
+            "log2_min_luma_coding_block_size",
+            self.log2_min_luma_coding_block_size_minus3 + 3,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "log2_diff_max_min_luma_coding_block_size",
+            self.log2_diff_max_min_luma_coding_block_size,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "log2_min_transform_block_size",
+            self.log2_min_luma_transform_block_size_minus2 + 2,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "log2_diff_max_min_transform_block_size",
+            self.log2_diff_max_min_luma_transform_block_size,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "max_transform_hierarchy_depth_inter",
+            self.max_transform_hierarchy_depth_inter,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "max_transform_hierarchy_depth_intra",
+            self.max_transform_hierarchy_depth_intra,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "scaling_list_enable_flag",
+            flag(Flags::SCALING_LIST_ENABLED),
+        )?;
+        field(f, WIDTH, "amp_enabled_flag", flag(Flags::AMP_ENABLED))?;
+        field(
+            f,
+            WIDTH,
+            "sample_adaptive_offset_enabled_flag",
+            flag(Flags::SAMPLE_ADAPTIVE_OFFSET_ENABLED),
+        )?;
+        field(f, WIDTH, "pcm_enabled_flag", flag(Flags::PCM_ENABLED))?;
+        if let Some(ref pcm) = self.pcm {
+            field(
+                f,
+                WIDTH,
+                "  pcm_sample_bit_depth_luma",
+                pcm.pcm_sample_bit_depth_luma_minus1 + 1,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  pcm_sample_bit_depth_chroma",
+                pcm.pcm_sample_bit_depth_chroma_minus1 + 1,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  log2_min_pcm_luma_coding_block_size",
+                pcm.log2_min_pcm_luma_coding_block_size_minus3
+                    .saturating_add(3),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  log2_diff_max_min_pcm_luma_coding_block_size",
+                pcm.log2_diff_max_min_pcm_luma_coding_block_size,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  pcm_loop_filter_disable_flag",
+                flag(Flags::PCM_LOOP_FILTER_DISABLED),
+            )?;
+        }
+        field(
+            f,
+            WIDTH,
+            "num_short_term_ref_pic_sets",
+            self.num_short_term_ref_pic_sets,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "long_term_ref_pics_present_flag",
+            flag(Flags::LONG_TERM_REF_PICS_PRESENT),
+        )?;
+        if self.flags.contains(Flags::LONG_TERM_REF_PICS_PRESENT) {
+            field(
+                f,
+                WIDTH,
+                "  num_long_term_ref_pics_sps",
+                self.num_long_term_ref_pics_sps,
+            )?;
+        }
+        field(
+            f,
+            WIDTH,
+            "sps_temporal_mvp_enabled_flag",
+            flag(Flags::SPS_TEMPORAL_MVP_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "strong_intra_smoothing_enable_flag",
+            flag(Flags::STRONG_INTRA_SMOOTHING_ENABLED),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vui_parameters_present_flag",
+            flag(Flags::VUI_PARAMETERS_PRESENT),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "sps_extension_present_flag",
+            flag(Flags::SPS_EXTENSION),
+        )?;
+        // we don't read extensions, so these are always zero
+        field(f, WIDTH, "sps_range_extension_flag", 0)?;
+        field(f, WIDTH, "sps_multilayer_extension_flag", 0)?;
+        field(f, WIDTH, "sps_extension_6bits", 0)?;
+
+        let ctb_size_y = self.ctb_size_y();
+        let min_tb_log2_size_y = self.log2_min_luma_transform_block_size_minus2 + 2;
+        let (sub_width_c, sub_height_c) = self.chroma_subsampling();
+        field(f, WIDTH, "CtbSizeY", ctb_size_y)?;
+        field(
+            f,
+            WIDTH,
+            "MinCbSizeY",
+            1 << (self.log2_min_luma_coding_block_size_minus3 + 3),
+        )?;
+        field(f, WIDTH, "MaxCbSizeY", ctb_size_y)?;
+        field(f, WIDTH, "MinTBSizeY", 1 << min_tb_log2_size_y)?;
+        field(
+            f,
+            WIDTH,
+            "MaxTBSizeY",
+            1 << (min_tb_log2_size_y + self.log2_diff_max_min_luma_transform_block_size),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "PicWidthInCtbsY",
+            ceil_div(self.pic_width_in_luma_samples, ctb_size_y),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "PicHeightInCtbsY",
+            ceil_div(self.pic_height_in_luma_samples, ctb_size_y),
+        )?;
+        field(f, WIDTH, "SubWidthC", sub_width_c)?;
+        field(f, WIDTH, "SubHeightC", sub_height_c)?;
+
+        if let Some(ref vui) = self.vui {
+            write!(f, "{}", vui)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Vui {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const WIDTH: usize = 43;
+        let flag = u8::from;
+
+        writeln!(f, "----------------- VUI -----------------")?;
+        field(
+            f,
+            WIDTH,
+            "sample aspect ratio",
+            format!("{}:{}", self.sar_width, self.sar_height),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "overscan_info_present_flag",
+            flag(self.overscan_info_present_flag),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "overscan_appropriate_flag",
+            flag(self.overscan_appropriate_flag),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "video_signal_type_present_flag",
+            flag(self.video_signal_type_present_flag),
+        )?;
+        if self.video_signal_type_present_flag {
+            field(f, WIDTH, "  video_format", self.video_format)?;
+            field(
+                f,
+                WIDTH,
+                "  video_full_range_flag",
+                flag(self.video_full_range_flag),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  colour_description_present_flag",
+                flag(self.colour_description_present_flag),
+            )?;
+            field(f, WIDTH, "  colour_primaries", self.colour_primaries)?;
+            field(
+                f,
+                WIDTH,
+                "  transfer_characteristics",
+                self.transfer_characteristics,
+            )?;
+            field(f, WIDTH, "  matrix_coeffs", self.matrix_coeffs)?;
+        }
+        field(
+            f,
+            WIDTH,
+            "chroma_loc_info_present_flag",
+            flag(self.chroma_loc_info_present_flag),
+        )?;
+        if self.chroma_loc_info_present_flag {
+            field(
+                f,
+                WIDTH,
+                "  chroma_sample_loc_type_top_field",
+                self.chroma_sample_loc_type_top_field,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  chroma_sample_loc_type_bottom_field",
+                self.chroma_sample_loc_type_bottom_field,
+            )?;
         }
+        field(
+            f,
+            WIDTH,
+            "neutral_chroma_indication_flag",
+            flag(self.neutral_chroma_indication_flag),
+        )?;
+        field(f, WIDTH, "field_seq_flag", flag(self.field_seq_flag))?;
+        field(
+            f,
+            WIDTH,
+            "frame_field_info_present_flag",
+            flag(self.frame_field_info_present_flag),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "default_display_window_flag",
+            flag(self.default_display_window_flag),
+        )?;
+        let [left, right, top, bottom] = self.def_disp_win_offsets;
+        field(f, WIDTH, "  def_disp_win_left_offset", left)?;
+        field(f, WIDTH, "  def_disp_win_right_offset", right)?;
+        field(f, WIDTH, "  def_disp_win_top_offset", top)?;
+        field(f, WIDTH, "  def_disp_win_bottom_offset", bottom)?;
+        field(
+            f,
+            WIDTH,
+            "vui_timing_info_present_flag",
+            flag(self.vui_timing_info_present_flag),
+        )?;
+        if self.vui_timing_info_present_flag {
+            field(
+                f,
+                WIDTH,
+                "  vui_num_units_in_tick",
+                self.vui_num_units_in_tick,
+            )?;
+            field(f, WIDTH, "  vui_time_scale", self.vui_time_scale)?;
+        }
+        field(
+            f,
+            WIDTH,
+            "vui_poc_proportional_to_timing_flag",
+            flag(self.vui_poc_proportional_to_timing_flag),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vui_num_ticks_poc_diff_one",
+            self.vui_num_ticks_poc_diff_one_minus1.saturating_add(1),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vui_hrd_parameters_present_flag",
+            flag(self.vui_hrd_parameters_present_flag),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "bitstream_restriction_flag",
+            flag(self.bitstream_restriction_flag),
+        )?;
+        if self.bitstream_restriction_flag {
+            field(
+                f,
+                WIDTH,
+                "  tiles_fixed_structure_flag",
+                flag(self.tiles_fixed_structure_flag),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  motion_vectors_over_pic_boundaries_flag",
+                flag(self.motion_vectors_over_pic_boundaries_flag),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  restricted_ref_pic_lists_flag",
+                flag(self.restricted_ref_pic_lists_flag),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  min_spatial_segmentation_idc",
+                self.min_spatial_segmentation_idc,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  max_bytes_per_pic_denom",
+                self.max_bytes_per_pic_denom,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  max_bits_per_min_cu_denom",
+                self.max_bits_per_min_cu_denom,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  log2_max_mv_length_horizontal",
+                self.log2_max_mv_length_horizontal,
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  log2_max_mv_length_vertical",
+                self.log2_max_mv_length_vertical,
+            )?;
+        }
+        Ok(())
     }
+}
 
-    ret
+fn ceil_div(num: u64, den: u64) -> u64 {
+    match num % den {
+        0 => num / den,
+        _ => num / den + 1,
+    }
 }
 
 #[inline]
@@ -288,18 +963,35 @@ fn read_flag(from: &mut BitReader, flag: Flags) -> Result<Flags, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::fmt;
+
     use bitreader::BitReader;
 
+    use hevc::nal::un_nal;
+
     #[test]
     fn sps() {
         let bytes = [
             1, 4, 8, 0, 0, 3, 0, 159, 168, 0, 0, 3, 0, 0, 60, 160, 11, 72, 12, 31, 89, 110, 164,
             146, 138, 224, 16, 0, 0, 3, 0, 16, 0, 0, 3, 0, 16, 128,
         ];
-        let un_nalled = super::un_nal(&bytes);
+        let un_nalled = un_nal(&bytes);
         println!("{:?}", un_nalled);
         let mut reader = BitReader::new(&un_nalled);
 
-        super::seq_parameter_set(&mut reader).unwrap();
+        let sps = super::seq_parameter_set(&mut reader).unwrap();
+        assert_eq!(1, sps.chroma_format_idc);
+        assert_eq!(4, sps.profile_tier_level.general_profile_idc);
+        assert_eq!(0, sps.bit_depth_luma_minus8);
+        assert_eq!((360, 190), sps.cropped_size());
+        assert_eq!(64, sps.ctb_size_y());
+        assert_eq!(
+            3,
+            sps.sub_layer_ordering[0].max_dec_pic_buffering_minus1 + 1
+        );
+        assert_eq!(
+            include_str!("../../tests/data/road.sps.txt"),
+            sps.to_string()
+        );
     }
 }
@@ -143,8 +143,8 @@ pub fn slice_segment_header(
         }
 
         if slice_type == SLICE_TYPE_P || slice_type == SLICE_TYPE_B {
-            let mut num_ref_idx_l0_active_minus1 = unimplemented!("default value");
-            let mut num_ref_idx_l1_active_minus1 = unimplemented!("default value");
+            let mut num_ref_idx_l0_active_minus1 = pps.num_ref_idx_l0_default_active_minus1;
+            let mut num_ref_idx_l1_active_minus1 = pps.num_ref_idx_l1_default_active_minus1;
             flags |= read_flag(from, Flags::NUM_REF_IDX_ACTIVE_OVERRIDE)?;
             if flags.contains(Flags::NUM_REF_IDX_ACTIVE_OVERRIDE) {
                 num_ref_idx_l0_active_minus1 = read_uvlc(from)?;
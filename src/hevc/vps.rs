@@ -1,70 +1,428 @@
+use std::fmt;
+
 use bitreader::BitReader;
+use cast::u16;
 use cast::usize;
 use failure::Error;
 
+use hevc::field;
 use hevc::rbsp_trailing_bits;
 use hevc::read_uvlc;
 
 bitflags! {
     #[derive(Default)]
-    struct Flags: u32 {
-        const VPS_TEMPORAL_ID_NESTING       = 1 <<  0;
-
+    pub struct Flags: u32 {
+        const VPS_BASE_LAYER_INTERNAL              = 1 <<  0;
+        const VPS_BASE_LAYER_AVAILABLE             = 1 <<  1;
+        const VPS_TEMPORAL_ID_NESTING              = 1 <<  2;
+        const VPS_SUB_LAYER_ORDERING_INFO_PRESENT  = 1 <<  3;
+        const VPS_TIMING_INFO_PRESENT              = 1 <<  4;
+        const VPS_POC_PROPORTIONAL_TO_TIMING       = 1 <<  5;
+        const VPS_EXTENSION                        = 1 <<  6;
     }
 }
 
-pub fn video_parameter_set(from: &mut BitReader) -> Result<(), Error> {
+#[derive(Clone, Debug)]
+pub struct VidParamSet {
+    pub vps_video_parameter_set_id: u8,
+    pub vps_max_layers_minus1: u8,
+    pub vps_max_sub_layers_minus1: u8,
+    pub profile_tier_level: ProfileTierLevel,
+    pub sub_layer_ordering: Vec<SubLayerOrdering>,
+    pub vps_max_layer_id: u8,
+    pub vps_num_layer_sets_minus1: u16,
+    pub vps_num_units_in_tick: u32,
+    pub vps_time_scale: u32,
+    pub vps_num_ticks_poc_diff_one_minus1: u64,
+    pub flags: Flags,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ProfileTierLevel {
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    // progressive, interlaced, non-packed and frame-only flags, then 44 reserved bits
+    pub general_constraint_indicator_flags: u64,
+    pub general_level_idc: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SubLayerOrdering {
+    pub max_dec_pic_buffering_minus1: u64,
+    pub max_num_reorder_pics: u64,
+    pub max_latency_increase_plus1: u64,
+}
+
+pub fn video_parameter_set(from: &mut BitReader) -> Result<VidParamSet, Error> {
     let mut flags = Flags::default();
 
-    let vps_id = from.read_u8(4)?;
-    let _reserved = from.read_u8(2)?;
-    let max_layers_minus_1 = from.read_u8(6)?;
+    let vps_video_parameter_set_id = from.read_u8(4)?;
+    flags |= read_flag(from, Flags::VPS_BASE_LAYER_INTERNAL)?;
+    flags |= read_flag(from, Flags::VPS_BASE_LAYER_AVAILABLE)?;
+    let vps_max_layers_minus1 = from.read_u8(6)?;
+    let vps_max_sub_layers_minus1 = from.read_u8(3)?;
+    ensure!(
+        vps_max_sub_layers_minus1 < 7,
+        "vps_max_sub_layers_minus1 out of range: {}",
+        vps_max_sub_layers_minus1
+    );
     flags |= read_flag(from, Flags::VPS_TEMPORAL_ID_NESTING)?;
     let _reserved = from.read_u16(16)?;
-    profile_tier_level(from, max_layers_minus_1 + 1)?;
-    Ok(())
+    let profile_tier_level = profile_tier_level(from, vps_max_sub_layers_minus1)?;
+    flags |= read_flag(from, Flags::VPS_SUB_LAYER_ORDERING_INFO_PRESENT)?;
+    let sub_layer_ordering = sub_layer_ordering_info(
+        from,
+        flags.contains(Flags::VPS_SUB_LAYER_ORDERING_INFO_PRESENT),
+        vps_max_sub_layers_minus1,
+    )?;
+
+    let vps_max_layer_id = from.read_u8(6)?;
+    let vps_num_layer_sets_minus1 = {
+        let val = read_uvlc(from)?;
+        ensure!(
+            val <= 1023,
+            "vps_num_layer_sets_minus1 out of range: {}",
+            val
+        );
+        u16(val).unwrap()
+    };
+
+    for _ in 1..=vps_num_layer_sets_minus1 {
+        for _ in 0..=vps_max_layer_id {
+            let _layer_id_included_flag = from.read_bool()?;
+        }
+    }
+
+    let mut vps_num_units_in_tick = 0;
+    let mut vps_time_scale = 0;
+    let mut vps_num_ticks_poc_diff_one_minus1 = 0;
+
+    flags |= read_flag(from, Flags::VPS_TIMING_INFO_PRESENT)?;
+    if flags.contains(Flags::VPS_TIMING_INFO_PRESENT) {
+        vps_num_units_in_tick = from.read_u32(32)?;
+        vps_time_scale = from.read_u32(32)?;
+        flags |= read_flag(from, Flags::VPS_POC_PROPORTIONAL_TO_TIMING)?;
+        if flags.contains(Flags::VPS_POC_PROPORTIONAL_TO_TIMING) {
+            vps_num_ticks_poc_diff_one_minus1 = read_uvlc(from)?;
+        }
+        let vps_num_hrd_parameters = read_uvlc(from)?;
+        if vps_num_hrd_parameters > 0 {
+            bail!("hrd_parameters(cprms_present_flag[i], vps_max_sub_layers_minus1)");
+        }
+    }
+
+    flags |= read_flag(from, Flags::VPS_EXTENSION)?;
+
+    // we don't understand the extension data, so can't find the trailing bits after it
+    if !flags.contains(Flags::VPS_EXTENSION) {
+        rbsp_trailing_bits(from)?;
+    }
+
+    Ok(VidParamSet {
+        vps_video_parameter_set_id,
+        vps_max_layers_minus1,
+        vps_max_sub_layers_minus1,
+        profile_tier_level,
+        sub_layer_ordering,
+        vps_max_layer_id,
+        vps_num_layer_sets_minus1,
+        vps_num_units_in_tick,
+        vps_time_scale,
+        vps_num_ticks_poc_diff_one_minus1,
+        flags,
+    })
 }
 
-pub fn profile_tier_level(from: &mut BitReader, max_sub_layers: u8) -> Result<(), Error> {
+// profile_tier_level(1, max_sub_layers_minus1); the profile is always present for us
+pub fn profile_tier_level(
+    from: &mut BitReader,
+    max_sub_layers_minus1: u8,
+) -> Result<ProfileTierLevel, Error> {
     let general_profile_space = from.read_u8(2)?;
     let general_tier_flag = from.read_bool()?;
     let general_profile_idc = from.read_u8(5)?;
     let general_profile_compatibility_flags = from.read_u32(32)?;
-    let general_progressive_source_flag = from.read_bool()?;
-    let general_interlaced_source_flag = from.read_bool()?;
-    let general_non_packed_constraint_flag = from.read_bool()?;
-    let general_frame_only_constraint_flag = from.read_bool()?;
-    let _reserved = from.read_u64(44)?;
+    let general_constraint_indicator_flags = from.read_u64(48)?;
     let general_level_idc = from.read_u8(8)?;
-    let mut sub_layer_profile_present_flag = vec![false; usize(max_sub_layers)];
-    let mut sub_layer_level_present_flag = vec![false; usize(max_sub_layers)];
-    for i in 0..max_sub_layers {
-        sub_layer_profile_present_flag.push(from.read_bool()?);
-        sub_layer_level_present_flag.push(from.read_bool()?);
-    }
-    if max_sub_layers > 0 {
-        let _reserved = from.read_u16(16)?;
-    }
-    for i in 0..max_sub_layers {
-        if sub_layer_profile_present_flag[usize(i)] {
-            let sub_layer_profile_space = from.read_u8(2)?;
-            let sub_layer_tier_flag = from.read_bool()?;
-            let sub_layer_profile_idc = from.read_u8(5)?;
-            let sub_layer_profile_compatibility_flags = from.read_u32(32)?;
-            let sub_layer_progressive_source_flag = from.read_bool()?;
-            let sub_layer_interlaced_source_flag = from.read_bool()?;
-            let sub_layer_non_packed_constraint_flag = from.read_bool()?;
-            let sub_layer_frame_only_constraint_flag = from.read_bool()?;
-            let sub_layer_reserved_zero_44bits = from.read_u64(44)?;
+
+    let mut sub_layer_profile_present_flag = [false; 8];
+    let mut sub_layer_level_present_flag = [false; 8];
+    for i in 0..usize(max_sub_layers_minus1) {
+        sub_layer_profile_present_flag[i] = from.read_bool()?;
+        sub_layer_level_present_flag[i] = from.read_bool()?;
+    }
+
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            let _reserved_zero_2bits = from.read_u8(2)?;
+        }
+    }
+
+    for i in 0..usize(max_sub_layers_minus1) {
+        if sub_layer_profile_present_flag[i] {
+            // space, tier, idc, compatibility flags, then the constraint flags
+            from.skip(2 + 1 + 5 + 32 + 48)?;
         }
-        if sub_layer_level_present_flag[usize(i)] {
-            let sub_layer_level_idc = from.read_u8(8)?;
+        if sub_layer_level_present_flag[i] {
+            let _sub_layer_level_idc = from.read_u8(8)?;
         }
     }
 
+    Ok(ProfileTierLevel {
+        general_profile_space,
+        general_tier_flag,
+        general_profile_idc,
+        general_profile_compatibility_flags,
+        general_constraint_indicator_flags,
+        general_level_idc,
+    })
+}
+
+impl ProfileTierLevel {
+    // the names from Annex A, as libde265 has them
+    pub fn profile_name(&self) -> Option<&'static str> {
+        Some(match self.general_profile_idc {
+            1 => "Main",
+            2 => "Main10",
+            3 => "MainStillPicture",
+            4 => "FormatRangeExtensions",
+            5 => "HighThroughput",
+            6 => "MultiviewMain",
+            7 => "ScalableMain",
+            8 => "3dMain",
+            9 => "ScreenContentCoding",
+            10 => "ScalableFormatRangeExtensions",
+            11 => "HighThroughputScreenContentCoding",
+            _ => return None,
+        })
+    }
+
+    // general_level_idc is thirty times this, e.g. 60 for level 2
+    pub fn level(&self) -> f64 {
+        f64::from(self.general_level_idc) / 30.0
+    }
+
+    // the general fields, indented under the parameter set's
+    pub fn dump(&self, f: &mut fmt::Formatter, width: usize) -> fmt::Result {
+        let compatibility = (0..32)
+            .map(|j| ((self.general_profile_compatibility_flags >> (31 - j)) & 1).to_string())
+            .collect::<Vec<_>>();
+
+        field(
+            f,
+            width,
+            "  general_profile_space",
+            self.general_profile_space,
+        )?;
+        field(
+            f,
+            width,
+            "  general_tier_flag",
+            u8::from(self.general_tier_flag),
+        )?;
+        match self.profile_name() {
+            Some(name) => field(f, width, "  general_profile_idc", name)?,
+            None => field(f, width, "  general_profile_idc", self.general_profile_idc)?,
+        }
+        field(
+            f,
+            width,
+            "  general_profile_compatibility_flags",
+            compatibility.join(","),
+        )?;
+        field(
+            f,
+            width,
+            "    general_progressive_source_flag",
+            u8::from(self.general_progressive_source_flag()),
+        )?;
+        field(
+            f,
+            width,
+            "    general_interlaced_source_flag",
+            u8::from(self.general_interlaced_source_flag()),
+        )?;
+        field(
+            f,
+            width,
+            "    general_non_packed_constraint_flag",
+            u8::from(self.general_non_packed_constraint_flag()),
+        )?;
+        field(
+            f,
+            width,
+            "    general_frame_only_constraint_flag",
+            u8::from(self.general_frame_only_constraint_flag()),
+        )?;
+        field(
+            f,
+            width,
+            "  general_level_idc",
+            format!("{} ({:.2})", self.general_level_idc, self.level()),
+        )
+    }
+
+    pub fn general_progressive_source_flag(&self) -> bool {
+        0 != self.general_constraint_indicator_flags & (1 << 47)
+    }
+
+    pub fn general_interlaced_source_flag(&self) -> bool {
+        0 != self.general_constraint_indicator_flags & (1 << 46)
+    }
+
+    pub fn general_non_packed_constraint_flag(&self) -> bool {
+        0 != self.general_constraint_indicator_flags & (1 << 45)
+    }
+
+    pub fn general_frame_only_constraint_flag(&self) -> bool {
+        0 != self.general_constraint_indicator_flags & (1 << 44)
+    }
+}
+
+// the sub-layer ordering, as the vps and sps dumps both show it
+pub fn dump_sub_layer_ordering(
+    f: &mut fmt::Formatter,
+    width: usize,
+    prefix: &str,
+    ordering: &[SubLayerOrdering],
+) -> fmt::Result {
+    for (i, layer) in ordering.iter().enumerate() {
+        writeln!(f, "Layer {}", i)?;
+        field(
+            f,
+            width,
+            &format!("  {}_max_dec_pic_buffering", prefix),
+            layer.max_dec_pic_buffering_minus1 + 1,
+        )?;
+        field(
+            f,
+            width,
+            &format!("  {}_max_num_reorder_pics", prefix),
+            layer.max_num_reorder_pics,
+        )?;
+        field(
+            f,
+            width,
+            &format!("  {}_max_latency_increase_plus1", prefix),
+            layer.max_latency_increase_plus1,
+        )?;
+    }
     Ok(())
 }
 
+// shared by the vps and the sps; if the info isn't present, only the highest sub-layer
+// is sent, and the lower ones are inferred to be the same
+pub fn sub_layer_ordering_info(
+    from: &mut BitReader,
+    present: bool,
+    max_sub_layers_minus1: u8,
+) -> Result<Vec<SubLayerOrdering>, Error> {
+    let mut ordering = Vec::with_capacity(usize(max_sub_layers_minus1) + 1);
+
+    let first = if present { 0 } else { max_sub_layers_minus1 };
+    for _ in first..=max_sub_layers_minus1 {
+        ordering.push(SubLayerOrdering {
+            max_dec_pic_buffering_minus1: read_uvlc(from)?,
+            max_num_reorder_pics: read_uvlc(from)?,
+            max_latency_increase_plus1: read_uvlc(from)?,
+        });
+    }
+
+    while ordering.len() <= usize(max_sub_layers_minus1) {
+        let highest = ordering[0];
+        ordering.insert(0, highest);
+    }
+
+    Ok(ordering)
+}
+
+// the same layout as the sps dump
+impl fmt::Display for VidParamSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const WIDTH: usize = 43;
+        let flag = |flag| u8::from(self.flags.contains(flag));
+
+        field(
+            f,
+            WIDTH,
+            "video_parameter_set_id",
+            self.vps_video_parameter_set_id,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vps_base_layer_internal_flag",
+            flag(Flags::VPS_BASE_LAYER_INTERNAL),
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vps_base_layer_available_flag",
+            flag(Flags::VPS_BASE_LAYER_AVAILABLE),
+        )?;
+        field(f, WIDTH, "vps_max_layers", self.vps_max_layers_minus1 + 1)?;
+        field(
+            f,
+            WIDTH,
+            "vps_max_sub_layers",
+            self.vps_max_sub_layers_minus1 + 1,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vps_temporal_id_nesting_flag",
+            flag(Flags::VPS_TEMPORAL_ID_NESTING),
+        )?;
+        self.profile_tier_level.dump(f, WIDTH)?;
+        field(
+            f,
+            WIDTH,
+            "vps_sub_layer_ordering_info_present_flag",
+            flag(Flags::VPS_SUB_LAYER_ORDERING_INFO_PRESENT),
+        )?;
+        dump_sub_layer_ordering(f, WIDTH, "vps", &self.sub_layer_ordering)?;
+        field(f, WIDTH, "vps_max_layer_id", self.vps_max_layer_id)?;
+        field(
+            f,
+            WIDTH,
+            "vps_num_layer_sets",
+            self.vps_num_layer_sets_minus1 + 1,
+        )?;
+        field(
+            f,
+            WIDTH,
+            "vps_timing_info_present_flag",
+            flag(Flags::VPS_TIMING_INFO_PRESENT),
+        )?;
+        if self.flags.contains(Flags::VPS_TIMING_INFO_PRESENT) {
+            field(
+                f,
+                WIDTH,
+                "  vps_num_units_in_tick",
+                self.vps_num_units_in_tick,
+            )?;
+            field(f, WIDTH, "  vps_time_scale", self.vps_time_scale)?;
+            field(
+                f,
+                WIDTH,
+                "  vps_poc_proportional_to_timing_flag",
+                flag(Flags::VPS_POC_PROPORTIONAL_TO_TIMING),
+            )?;
+            field(
+                f,
+                WIDTH,
+                "  vps_num_ticks_poc_diff_one",
+                self.vps_num_ticks_poc_diff_one_minus1 + 1,
+            )?;
+        }
+        field(f, WIDTH, "vps_extension_flag", flag(Flags::VPS_EXTENSION))
+    }
+}
+
 #[inline]
 fn read_flag(from: &mut BitReader, flag: Flags) -> Result<Flags, Error> {
     Ok(if from.read_bool()? {
@@ -76,7 +434,12 @@ fn read_flag(from: &mut BitReader, flag: Flags) -> Result<Flags, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::fmt;
+
     use bitreader::BitReader;
+    use cast::u64;
+
+    use hevc::nal::un_nal;
 
     #[test]
     fn vps() {
@@ -84,8 +447,28 @@ mod tests {
             12, 1, 255, 255, 4, 8, 0, 0, 3, 0, 159, 168, 0, 0, 3, 0, 0, 60, 186, 2, 64,
         ];
 
-        let mut reader = BitReader::new(&bytes);
+        let un_nalled = un_nal(&bytes);
+        let mut reader = BitReader::new(&un_nalled);
+
+        let vps = super::video_parameter_set(&mut reader).unwrap();
+        assert_eq!(0, vps.vps_max_sub_layers_minus1);
+        assert!(vps.flags.contains(super::Flags::VPS_TEMPORAL_ID_NESTING));
+        assert_eq!(4, vps.profile_tier_level.general_profile_idc);
+        assert_eq!(60, vps.profile_tier_level.general_level_idc);
+        assert!(vps.profile_tier_level.general_progressive_source_flag());
+        assert!(vps.profile_tier_level.general_frame_only_constraint_flag());
+        assert_eq!(1, vps.sub_layer_ordering.len());
+        assert_eq!(2, vps.sub_layer_ordering[0].max_dec_pic_buffering_minus1);
+        assert_eq!(u64(un_nalled.len()) * 8, reader.position());
 
-        super::video_parameter_set(&mut reader).unwrap();
+        let dumped = vps.to_string();
+        let lines = dumped.lines().collect::<Vec<_>>();
+        assert_eq!("vps_max_sub_layers                         1", lines[4]);
+        assert!(lines.contains(&"  general_profile_idc                      FormatRangeExtensions"));
+        assert!(lines.contains(&"  general_level_idc                        60 (2.00)"));
+        assert_eq!(
+            Some(&"vps_extension_flag                         0"),
+            lines.last()
+        );
     }
 }
@@ -6,13 +6,52 @@ extern crate cast;
 #[macro_use]
 extern crate failure;
 extern crate generic_array;
+#[cfg(feature = "serde")]
 #[macro_use]
-extern crate more_asserts;
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 extern crate twoway;
 
+pub mod av1;
 mod bit;
+mod edit;
+mod error;
 mod file;
 pub mod hevc;
+mod limits;
 pub mod mpeg;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+mod picture;
+mod source;
+mod transform;
+mod uncompressed;
+mod validate;
+mod writer;
 
+pub use edit::Editor;
+pub use error::ErrorKind;
+pub use error::ParseError;
+pub use file::EssentialPolicy;
 pub use file::Heif;
+pub use file::ItemSummary;
+pub use limits::Budget;
+pub use limits::LimitExceeded;
+pub use limits::Limits;
+#[cfg(feature = "tokio")]
+pub use nonblocking::AsyncExtents;
+#[cfg(feature = "tokio")]
+pub use nonblocking::LoadAsync;
+pub use picture::Picture;
+pub use picture::Plane;
+pub use source::ByteSource;
+pub use transform::Orientation;
+pub use transform::Rect;
+pub use transform::Transform;
+pub use validate::validate;
+pub use validate::Category;
+pub use validate::Problem;
+pub use validate::Report;
+pub use validate::Severity;
+pub use writer::mux_hevc;
@@ -0,0 +1,193 @@
+use std::error;
+use std::fmt;
+use std::io::Read;
+use std::io::Take;
+use std::mem;
+
+use cast::u64;
+use cast::usize;
+use failure::Error;
+
+use mpeg::BoxHeader;
+
+// How much an untrusted file may ask of us. Counts in the file would otherwise drive
+// allocations directly, so a few bytes could ask for gigabytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Limits {
+    // for the boxes we read into memory, like the 'meta'; the 'mdat' is only ever streamed
+    pub max_box_size: u64,
+    // in any one 'iloc', 'iinf', 'ipma' or 'grpl', or in any one entity group
+    pub max_items: u64,
+    // in any one 'ipco'
+    pub max_properties: u64,
+    pub max_nal_size: u64,
+    // for decoded images
+    pub max_image_pixels: u64,
+    // everything read while parsing a file, and any item data read into memory
+    pub max_total_allocation: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_box_size: 256 * 1024 * 1024,
+            max_items: 65_536,
+            max_properties: 65_536,
+            max_nal_size: 64 * 1024 * 1024,
+            max_image_pixels: 32_768 * 32_768,
+            max_total_allocation: 512 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    // for files you trust
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_box_size: u64::MAX,
+            max_items: u64::MAX,
+            max_properties: u64::MAX,
+            max_nal_size: u64::MAX,
+            max_image_pixels: u64::MAX,
+            max_total_allocation: u64::MAX,
+        }
+    }
+
+    pub fn check_box(&self, header: &BoxHeader) -> Result<(), Error> {
+        check("max_box_size", header.size, self.max_box_size)
+    }
+
+    pub fn check_items(&self, count: u64) -> Result<(), Error> {
+        check("max_items", count, self.max_items)
+    }
+
+    pub fn check_properties(&self, count: u64) -> Result<(), Error> {
+        check("max_properties", count, self.max_properties)
+    }
+
+    pub fn check_nal_size(&self, size: u64) -> Result<(), Error> {
+        check("max_nal_size", size, self.max_nal_size)
+    }
+
+    pub fn check_image(&self, width: u64, height: u64) -> Result<(), Error> {
+        check(
+            "max_image_pixels",
+            width.saturating_mul(height),
+            self.max_image_pixels,
+        )
+    }
+
+    // a single allocation, outside of parsing
+    pub fn check_allocation(&self, bytes: u64) -> Result<(), Error> {
+        check("max_total_allocation", bytes, self.max_total_allocation)
+    }
+}
+
+// The error for anything over a limit, which callers can find with `downcast_ref`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LimitExceeded {
+    // the name of the field in `Limits`
+    pub limit: &'static str,
+    pub requested: u64,
+    pub allowed: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} exceeded: the file asks for {}, but only {} is allowed",
+            self.limit, self.requested, self.allowed
+        )
+    }
+}
+
+impl error::Error for LimitExceeded {}
+
+fn check(limit: &'static str, requested: u64, allowed: u64) -> Result<(), Error> {
+    if requested > allowed {
+        return Err(LimitExceeded {
+            limit,
+            requested,
+            allowed,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// The limits, and how much of the total allocation has been used, while parsing one file.
+#[derive(Clone, Debug)]
+pub struct Budget {
+    pub limits: Limits,
+    allocated: u64,
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            allocated: 0,
+        }
+    }
+
+    pub fn allocate(&mut self, bytes: u64) -> Result<(), Error> {
+        let total = self.allocated.saturating_add(bytes);
+        check(
+            "max_total_allocation",
+            total,
+            self.limits.max_total_allocation,
+        )?;
+        self.allocated = total;
+        Ok(())
+    }
+
+    // an empty vec with room for `count` items, if the budget allows it
+    pub fn vec<T>(&mut self, count: u64) -> Result<Vec<T>, Error> {
+        self.allocate(count.saturating_mul(u64(mem::size_of::<T>())))?;
+        Ok(Vec::with_capacity(usize(count)))
+    }
+
+    // as `Read::read_to_end`, from a box whose size has already been checked
+    pub fn read_to_end<R: Read>(&mut self, from: &mut Take<R>) -> Result<Vec<u8>, Error> {
+        self.allocate(from.limit())?;
+        let mut data = Vec::with_capacity(usize(from.limit()));
+        from.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget::new(Limits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budget;
+    use super::LimitExceeded;
+    use super::Limits;
+
+    #[test]
+    fn budget() {
+        let mut budget = Budget::new(Limits {
+            max_total_allocation: 100,
+            ..Limits::default()
+        });
+        assert_eq!(10, budget.vec::<u32>(10).unwrap().capacity());
+        budget.allocate(60).unwrap();
+        let err = budget.vec::<u32>(1).unwrap_err();
+        assert_eq!(
+            Some(&LimitExceeded {
+                limit: "max_total_allocation",
+                requested: 104,
+                allowed: 100,
+            }),
+            err.downcast_ref::<LimitExceeded>()
+        );
+
+        assert!(Limits::default().check_image(32_768, 32_769).is_err());
+        assert!(Limits::unlimited().check_image(32_768, 32_769).is_ok());
+    }
+}
@@ -1,26 +1,56 @@
 use std::io::Read;
 use std::io::Take;
+use std::io::Write;
+use std::mem;
 
 use bitreader::BitReader;
 use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
 use byteorder::BE;
 use cast::u16;
 use cast::u32;
+use cast::u64;
+use cast::u8;
 use cast::usize;
 use failure::Error;
 
 use bit::typenum;
 use bit::Bits;
+use error;
+use hevc;
+use hevc::nal;
+use hevc::pps;
+use hevc::sps;
+use hevc::vps;
+use limits::Budget;
+use mpeg::parse_child;
 use mpeg::read_full_box_header;
 use mpeg::read_header;
+use mpeg::read_raw;
 use mpeg::skip;
-use mpeg::FourCc;
+use mpeg::unci;
+use mpeg::unci::Cmpd;
+use mpeg::unci::Uncc;
+use mpeg::BoxHeader;
+use mpeg::RawBox;
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum Property {
     HvcCodecSettings(Hvcc),
-    Size((u32, u32)),
-    Unknown(FourCc),
+    Av1CodecSettings(Av1c),
+    JpegPrefix(Jpgc),
+    ComponentDefinition(Cmpd),
+    UncompressedConfig(Uncc),
+    Size(Ispe),
+    Rotation(Irot),
+    Mirror(Imir),
+    CleanAperture(Clap),
+    Unknown(RawBox),
+}
+
+pub trait FromProperty {
+    fn from_property(prop: &Property) -> Option<&Self>;
 }
 
 #[derive(Clone, Debug)]
@@ -42,14 +72,16 @@ pub struct Association {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Hvcc {
     header: HvccHeader,
     pub nals: Vec<Nal>,
 }
 
 // what an absolute unit
-#[derive(Copy, Clone, Debug)]
-struct HvccHeader {
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HvccHeader {
     configuration_version: u8,
     general_profile_space: u8,
     general_tier_flag: bool,
@@ -67,32 +99,99 @@ struct HvccHeader {
     num_temporal_layers: u8,
     temporal_id_nested: bool,
     length_size_minus_one: u8,
+    // the padding before each of the short fields; meant to be all ones, but some writers
+    // use zeros, and we want to write back exactly what we read
+    reserved: [u8; 5],
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Av1c {
+    pub seq_profile: u8,
+    pub seq_level_idx_0: u8,
+    pub seq_tier_0: bool,
+    pub high_bitdepth: bool,
+    pub twelve_bit: bool,
+    pub monochrome: bool,
+    pub chroma_subsampling_x: bool,
+    pub chroma_subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub initial_presentation_delay_minus_one: Option<u8>,
+    // usually just the sequence header, in the low overhead bitstream format
+    pub config_obus: Vec<u8>,
+}
+
+// headers shared between JPEG items; each item's data follows on from these
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Jpgc {
+    pub prefix: Vec<u8>,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Ispe {
+    pub width: u32,
+    pub height: u32,
+}
+
+// anticlockwise, in quarter turns
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Irot {
+    pub angle: u8,
+}
+
+// 0 mirrors about the vertical axis, swapping left and right; 1 about the horizontal axis
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Imir {
+    pub axis: u8,
+}
+
+// a centred crop, with the offset of its centre from the centre of the image
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Clap {
+    pub width_n: u32,
+    pub width_d: u32,
+    pub height_n: u32,
+    pub height_d: u32,
+    pub horiz_off_n: i32,
+    pub horiz_off_d: u32,
+    pub vert_off_n: i32,
+    pub vert_off_d: u32,
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Nal {
     pub completeness_and_nal_unit_type: u8,
     pub units: Vec<Vec<u8>>,
 }
 
-pub fn parse_iprp<R: Read>(mut from: &mut Take<R>) -> Result<RawProps, Error> {
+pub fn parse_iprp<R: Read>(
+    mut from: &mut Take<R>,
+    offset: u64,
+    budget: &mut Budget,
+) -> Result<RawProps, Error> {
+    let size = from.limit();
     let mut containers = Vec::with_capacity(1);
     let mut associations = Vec::with_capacity(1);
 
     while 0 != from.limit() {
+        let child_offset = offset + size - from.limit();
         let child_header = read_header(&mut from)?;
+        let data_offset = child_offset + u64(child_header.offset);
         let mut child_data = (&mut from).take(child_header.data_size());
-        match child_header.box_type {
-            super::IPCO => containers.push(parse_ipco(&mut child_data)?),
-            super::IPMA => associations.push(parse_ipma(&mut child_data)?),
-            _ => skip(&mut child_data)?,
-        }
-
-        ensure!(
-            0 == child_data.limit(),
-            "iprp parser failed to parse a segment: {:?}",
-            child_header
-        );
+        parse_child(&child_header, Some(data_offset), &mut child_data, |data| {
+            match child_header.box_type {
+                super::IPCO => containers.push(parse_ipco(data, data_offset, budget)?),
+                super::IPMA => associations.push(parse_ipma(data, budget)?),
+                _ => skip(data)?,
+            }
+            Ok(())
+        })?;
     }
 
     Ok(RawProps {
@@ -101,38 +200,68 @@ pub fn parse_iprp<R: Read>(mut from: &mut Take<R>) -> Result<RawProps, Error> {
     })
 }
 
-pub fn parse_ipco<R: Read>(mut from: &mut Take<R>) -> Result<Vec<Property>, Error> {
+pub fn parse_ipco<R: Read>(
+    mut from: &mut Take<R>,
+    offset: u64,
+    budget: &mut Budget,
+) -> Result<Vec<Property>, Error> {
+    let size = from.limit();
     let mut properties = Vec::with_capacity(2);
 
     while 0 != from.limit() {
+        budget.limits.check_properties(u64(properties.len()) + 1)?;
+        budget.allocate(u64(mem::size_of::<Property>()))?;
+
+        let child_offset = offset + size - from.limit();
         let child_header = read_header(&mut from)?;
+        let data_offset = child_offset + u64(child_header.offset);
         let mut child_data = (&mut from).take(child_header.data_size());
-        match child_header.box_type {
-            super::ISPE => properties.push(Property::Size(parse_ispe(&mut child_data)?)),
-            super::HVCC => {
-                properties.push(Property::HvcCodecSettings(parse_hvcc(&mut child_data)?))
-            }
-            other => {
-                properties.push(Property::Unknown(other));
-                skip(&mut child_data)?
-            }
-        }
-
-        ensure!(
-            0 == child_data.limit(),
-            "ipco parser failed to parse a segment: {:?}",
-            child_header
-        );
+        properties.push(parse_child(
+            &child_header,
+            Some(data_offset),
+            &mut child_data,
+            |data| parse_property(data, &child_header, child_offset, budget),
+        )?);
     }
 
     Ok(properties)
 }
 
-pub fn parse_ipma<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemPropertyAssociation>, Error> {
+fn parse_property<R: Read>(
+    data: &mut Take<R>,
+    header: &BoxHeader,
+    offset: u64,
+    budget: &mut Budget,
+) -> Result<Property, Error> {
+    Ok(match header.box_type {
+        super::ISPE => Property::Size(parse_ispe(data)?),
+        super::HVCC => Property::HvcCodecSettings(parse_hvcc(data, budget)?),
+        super::AV1C => Property::Av1CodecSettings(parse_av1c(data, budget)?),
+        super::JPGC => Property::JpegPrefix(Jpgc {
+            prefix: budget.read_to_end(data)?,
+        }),
+        super::CMPD => Property::ComponentDefinition(unci::parse_cmpd(data, budget)?),
+        super::UNCC => Property::UncompressedConfig(unci::parse_uncc(data, budget)?),
+        super::IROT => Property::Rotation(Irot {
+            angle: data.read_u8()? & 0b11,
+        }),
+        super::IMIR => Property::Mirror(Imir {
+            axis: data.read_u8()? & 0b1,
+        }),
+        super::CLAP => Property::CleanAperture(parse_clap(data)?),
+        _ => Property::Unknown(read_raw(data, header, offset, budget)?),
+    })
+}
+
+pub fn parse_ipma<R: Read>(
+    mut from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<Vec<ItemPropertyAssociation>, Error> {
     let extended = read_full_box_header(&mut from)?;
     let entry_count = from.read_u32::<BE>()?;
 
-    let mut property_associations = Vec::with_capacity(usize(entry_count));
+    budget.limits.check_items(u64(entry_count))?;
+    let mut property_associations = budget.vec(u64(entry_count))?;
 
     for _ in 0..entry_count {
         let item_id = if extended.version < 1 {
@@ -143,7 +272,7 @@ pub fn parse_ipma<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemPropertyAss
 
         let association_count = from.read_u8()?;
 
-        let mut associations = Vec::with_capacity(usize(association_count));
+        let mut associations = budget.vec(u64(association_count))?;
 
         for _ in 0..association_count {
             associations.push(if 0 != (extended.flags & 1) {
@@ -172,51 +301,165 @@ pub fn parse_ipma<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemPropertyAss
     Ok(property_associations)
 }
 
-pub fn parse_ispe<R: Read>(mut from: &mut Take<R>) -> Result<(u32, u32), Error> {
+pub fn parse_ispe<R: Read>(mut from: &mut Take<R>) -> Result<Ispe, Error> {
     let _ = read_full_box_header(&mut from)?;
-    Ok((from.read_u32::<BE>()?, from.read_u32::<BE>()?))
+    Ok(Ispe {
+        width: from.read_u32::<BE>()?,
+        height: from.read_u32::<BE>()?,
+    })
+}
+
+pub fn parse_av1c<R: Read>(from: &mut Take<R>, budget: &mut Budget) -> Result<Av1c, Error> {
+    let mut header = [0u8; 4];
+    from.read_exact(&mut header)?;
+    let mut bits = BitReader::new(&header);
+
+    ensure!(bits.read_bool()?, "av1C marker bit is unset");
+    let version = bits.read_u8(7)?;
+    if 1 != version {
+        return Err(error::unsupported(format!(
+            "unsupported av1C version: {}",
+            version
+        )));
+    }
+
+    let seq_profile = bits.read_u8(3)?;
+    let seq_level_idx_0 = bits.read_u8(5)?;
+    let seq_tier_0 = bits.read_bool()?;
+    let high_bitdepth = bits.read_bool()?;
+    let twelve_bit = bits.read_bool()?;
+    let monochrome = bits.read_bool()?;
+    let chroma_subsampling_x = bits.read_bool()?;
+    let chroma_subsampling_y = bits.read_bool()?;
+    let chroma_sample_position = bits.read_u8(2)?;
+    bits.skip(3)?;
+    let initial_presentation_delay_minus_one = if bits.read_bool()? {
+        Some(bits.read_u8(4)?)
+    } else {
+        bits.skip(4)?;
+        None
+    };
+
+    let config_obus = budget.read_to_end(from)?;
+
+    Ok(Av1c {
+        seq_profile,
+        seq_level_idx_0,
+        seq_tier_0,
+        high_bitdepth,
+        twelve_bit,
+        monochrome,
+        chroma_subsampling_x,
+        chroma_subsampling_y,
+        chroma_sample_position,
+        initial_presentation_delay_minus_one,
+        config_obus,
+    })
+}
+
+// the inverse of `parse_av1c`
+pub fn write_av1c<W: Write>(av1c: &Av1c, mut into: W) -> Result<(), Error> {
+    into.write_u8(0x81)?;
+    into.write_u8((av1c.seq_profile << 5) | av1c.seq_level_idx_0)?;
+    into.write_u8(
+        (u8::from(av1c.seq_tier_0) << 7)
+            | (u8::from(av1c.high_bitdepth) << 6)
+            | (u8::from(av1c.twelve_bit) << 5)
+            | (u8::from(av1c.monochrome) << 4)
+            | (u8::from(av1c.chroma_subsampling_x) << 3)
+            | (u8::from(av1c.chroma_subsampling_y) << 2)
+            | av1c.chroma_sample_position,
+    )?;
+    into.write_u8(match av1c.initial_presentation_delay_minus_one {
+        Some(delay) => 0x10 | delay,
+        None => 0,
+    })?;
+    into.write_all(&av1c.config_obus)?;
+    Ok(())
+}
+
+impl Av1c {
+    pub fn bit_depth(&self) -> u8 {
+        match (self.high_bitdepth, self.twelve_bit) {
+            (false, _) => 8,
+            (true, false) => 10,
+            (true, true) => 12,
+        }
+    }
 }
 
-pub fn parse_hvcc<R: Read>(mut from: &mut Take<R>) -> Result<Hvcc, Error> {
+pub fn parse_clap<R: Read>(from: &mut Take<R>) -> Result<Clap, Error> {
+    Ok(Clap {
+        width_n: from.read_u32::<BE>()?,
+        width_d: from.read_u32::<BE>()?,
+        height_n: from.read_u32::<BE>()?,
+        height_d: from.read_u32::<BE>()?,
+        horiz_off_n: from.read_i32::<BE>()?,
+        horiz_off_d: from.read_u32::<BE>()?,
+        vert_off_n: from.read_i32::<BE>()?,
+        vert_off_d: from.read_u32::<BE>()?,
+    })
+}
+
+pub fn parse_hvcc<R: Read>(mut from: &mut Take<R>, budget: &mut Budget) -> Result<Hvcc, Error> {
     let header = {
         let mut bits = Bits::<typenum::U22>::read_exact(&mut from)?;
+        let mut reserved = [0u8; 5];
 
         let header = HvccHeader {
-            configuration_version: bits.read_u8(8),
-            general_profile_space: bits.read_u8(2),
-            general_tier_flag: bits.read_bool(),
-            general_profile_idc: bits.read_u8(5),
-            general_profile_compatibility_flags: bits.read_u32(32),
-            general_constraint_indicator_flags: bits.read_u64(48),
-            general_level_idc: bits.read_u8(8),
-            min_spatial_segmentation_idc: bits.skip(4).read_u16(12),
-            parallelism_type: bits.skip(6).read_u8(2),
-            chroma_format: bits.skip(6).read_u8(2),
-            bit_depth_luma_minus8: bits.skip(5).read_u8(3),
-            bit_depth_chroma_minus8: bits.skip(5).read_u8(3),
-            avg_frame_rate: bits.read_u16(16),
-            constant_frame_rate: bits.read_u8(2),
-            num_temporal_layers: bits.read_u8(3),
-            temporal_id_nested: bits.read_bool(),
-            length_size_minus_one: bits.read_u8(2),
+            configuration_version: bits.read_u8(8)?,
+            general_profile_space: bits.read_u8(2)?,
+            general_tier_flag: bits.read_bool()?,
+            general_profile_idc: bits.read_u8(5)?,
+            general_profile_compatibility_flags: bits.read_u32(32)?,
+            general_constraint_indicator_flags: bits.read_u64(48)?,
+            general_level_idc: bits.read_u8(8)?,
+            min_spatial_segmentation_idc: {
+                reserved[0] = bits.read_u8(4)?;
+                bits.read_u16(12)?
+            },
+            parallelism_type: {
+                reserved[1] = bits.read_u8(6)?;
+                bits.read_u8(2)?
+            },
+            chroma_format: {
+                reserved[2] = bits.read_u8(6)?;
+                bits.read_u8(2)?
+            },
+            bit_depth_luma_minus8: {
+                reserved[3] = bits.read_u8(5)?;
+                bits.read_u8(3)?
+            },
+            bit_depth_chroma_minus8: {
+                reserved[4] = bits.read_u8(5)?;
+                bits.read_u8(3)?
+            },
+            avg_frame_rate: bits.read_u16(16)?,
+            constant_frame_rate: bits.read_u8(2)?,
+            num_temporal_layers: bits.read_u8(3)?,
+            temporal_id_nested: bits.read_bool()?,
+            length_size_minus_one: bits.read_u8(2)?,
+            reserved,
         };
 
-        assert!(bits.done());
+        ensure!(bits.done(), "hvcC header not fully read");
 
         header
     };
 
     let num_of_arrays = from.read_u8()?;
-    let mut nals = Vec::with_capacity(usize(num_of_arrays));
+    let mut nals = budget.vec(u64(num_of_arrays))?;
 
     for _ in 0..num_of_arrays {
         let completeness_and_nal_unit_type = from.read_u8()? & 0b1011_1111;
 
         let num_nal_units = from.read_u16::<BE>()?;
-        let mut units = Vec::with_capacity(usize(num_nal_units));
+        let mut units = budget.vec(u64(num_nal_units))?;
 
         for _ in 0..num_nal_units {
             let nal_unit_length = from.read_u16::<BE>()?;
+            budget.limits.check_nal_size(u64(nal_unit_length))?;
+            budget.allocate(u64(nal_unit_length))?;
             let mut unit = vec![0u8; usize(nal_unit_length)];
             from.read_exact(&mut unit)?;
             units.push(unit);
@@ -230,3 +473,301 @@ pub fn parse_hvcc<R: Read>(mut from: &mut Take<R>) -> Result<Hvcc, Error> {
 
     Ok(Hvcc { header, nals })
 }
+
+// the inverse of `parse_hvcc`
+pub fn write_hvcc<W: Write>(hvcc: &Hvcc, mut into: W) -> Result<(), Error> {
+    let header = &hvcc.header;
+    into.write_u8(header.configuration_version)?;
+    into.write_u8(
+        (header.general_profile_space << 6)
+            | (u8::from(header.general_tier_flag) << 5)
+            | header.general_profile_idc,
+    )?;
+    into.write_u32::<BE>(header.general_profile_compatibility_flags)?;
+    into.write_uint::<BE>(header.general_constraint_indicator_flags, 6)?;
+    into.write_u8(header.general_level_idc)?;
+    let reserved = &header.reserved;
+    into.write_u16::<BE>((u16(reserved[0]) << 12) | header.min_spatial_segmentation_idc)?;
+    into.write_u8((reserved[1] << 2) | header.parallelism_type)?;
+    into.write_u8((reserved[2] << 2) | header.chroma_format)?;
+    into.write_u8((reserved[3] << 3) | header.bit_depth_luma_minus8)?;
+    into.write_u8((reserved[4] << 3) | header.bit_depth_chroma_minus8)?;
+    into.write_u16::<BE>(header.avg_frame_rate)?;
+    into.write_u8(
+        (header.constant_frame_rate << 6)
+            | (header.num_temporal_layers << 3)
+            | (u8::from(header.temporal_id_nested) << 2)
+            | header.length_size_minus_one,
+    )?;
+
+    into.write_u8(u8(hvcc.nals.len())?)?;
+    for nal in &hvcc.nals {
+        into.write_u8(nal.completeness_and_nal_unit_type)?;
+        into.write_u16::<BE>(u16(nal.units.len())?)?;
+        for unit in &nal.units {
+            into.write_u16::<BE>(u16(unit.len())?)?;
+            into.write_all(unit)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Hvcc {
+    pub fn new(header: HvccHeader, nals: Vec<Nal>) -> Hvcc {
+        Hvcc { header, nals }
+    }
+
+    // builds the record for a stream with exactly these parameter sets, which are complete
+    // NAL units, i.e. with their headers and emulation prevention
+    pub fn from_parameter_sets(vps: &[u8], sps: &[u8], pps: &[u8]) -> Result<Hvcc, Error> {
+        let parsed_vps =
+            vps::video_parameter_set(&mut BitReader::new(&nal::payload(vps, hevc::NAL_VPS_NUT)?))?;
+        let parsed_sps =
+            sps::seq_parameter_set(&mut BitReader::new(&nal::payload(sps, hevc::NAL_SPS_NUT)?))?;
+        let parsed_pps = pps::picture_parameter_set(&mut BitReader::new(&nal::payload(
+            pps,
+            hevc::NAL_PPS_NUT,
+        )?))?;
+
+        let header = HvccHeader::from_parameter_sets(&parsed_vps, &parsed_sps, &parsed_pps)?;
+
+        let nals = [
+            (hevc::NAL_VPS_NUT, vps),
+            (hevc::NAL_SPS_NUT, sps),
+            (hevc::NAL_PPS_NUT, pps),
+        ]
+        .iter()
+        .map(|&(nal_unit_type, unit)| Nal {
+            // all the parameter sets are here, so the arrays are complete
+            completeness_and_nal_unit_type: 0b1000_0000 | nal_unit_type,
+            units: vec![unit.to_vec()],
+        })
+        .collect();
+
+        Ok(Hvcc { header, nals })
+    }
+
+    pub fn header(&self) -> &HvccHeader {
+        &self.header
+    }
+
+    // bytes in the length prefix of each NAL unit in the item data
+    pub fn length_size(&self) -> u8 {
+        self.header.length_size_minus_one + 1
+    }
+}
+
+impl HvccHeader {
+    // the profile, tier and level come from the sps, and the segmentation from its vui;
+    // parallelism and the frame rate are left as 0, "unknown", as they'd need the sei and
+    // the slices
+    pub fn from_parameter_sets(
+        vps: &vps::VidParamSet,
+        sps: &sps::SeqParamSet,
+        pps: &pps::PicParamSet,
+    ) -> Result<HvccHeader, Error> {
+        ensure!(
+            vps.vps_video_parameter_set_id == sps.sps_video_parameter_set_id,
+            "sps refers to a different vps: {} != {}",
+            sps.sps_video_parameter_set_id,
+            vps.vps_video_parameter_set_id
+        );
+        ensure!(
+            sps.sps_seq_parameter_set_id == pps.pps_seq_parameter_set_id,
+            "pps refers to a different sps: {} != {}",
+            pps.pps_seq_parameter_set_id,
+            sps.sps_seq_parameter_set_id
+        );
+        // the sps allows 16-bit samples, but the record only has three bits for each depth
+        ensure!(
+            sps.bit_depth_luma_minus8 <= 7 && sps.bit_depth_chroma_minus8 <= 7,
+            "hvcC can't describe a bit depth above 15: luma {}, chroma {}",
+            u16::from(sps.bit_depth_luma_minus8) + 8,
+            u16::from(sps.bit_depth_chroma_minus8) + 8
+        );
+
+        let min_spatial_segmentation_idc = match sps.vui {
+            Some(ref vui) if vui.bitstream_restriction_flag => {
+                ensure!(
+                    vui.min_spatial_segmentation_idc < 4096,
+                    "min_spatial_segmentation_idc out of range: {}",
+                    vui.min_spatial_segmentation_idc
+                );
+                u16(vui.min_spatial_segmentation_idc)?
+            }
+            _ => 0,
+        };
+
+        let ptl = &sps.profile_tier_level;
+        Ok(HvccHeader {
+            configuration_version: 1,
+            general_profile_space: ptl.general_profile_space,
+            general_tier_flag: ptl.general_tier_flag,
+            general_profile_idc: ptl.general_profile_idc,
+            general_profile_compatibility_flags: ptl.general_profile_compatibility_flags,
+            general_constraint_indicator_flags: ptl.general_constraint_indicator_flags,
+            general_level_idc: ptl.general_level_idc,
+            min_spatial_segmentation_idc,
+            parallelism_type: 0,
+            chroma_format: sps.chroma_format_idc,
+            bit_depth_luma_minus8: sps.bit_depth_luma_minus8,
+            bit_depth_chroma_minus8: sps.bit_depth_chroma_minus8,
+            avg_frame_rate: 0,
+            constant_frame_rate: 0,
+            num_temporal_layers: sps.sps_max_sub_layers_minus1 + 1,
+            temporal_id_nested: sps.flags.contains(sps::Flags::SPS_TEMPORAL_ID_NESTING),
+            length_size_minus_one: 3,
+            reserved: [0b1111, 0b11_1111, 0b11_1111, 0b1_1111, 0b1_1111],
+        })
+    }
+
+    pub fn configuration_version(&self) -> u8 {
+        self.configuration_version
+    }
+
+    pub fn general_profile_space(&self) -> u8 {
+        self.general_profile_space
+    }
+
+    pub fn general_tier_flag(&self) -> bool {
+        self.general_tier_flag
+    }
+
+    pub fn general_profile_idc(&self) -> u8 {
+        self.general_profile_idc
+    }
+
+    pub fn general_profile_compatibility_flags(&self) -> u32 {
+        self.general_profile_compatibility_flags
+    }
+
+    pub fn general_constraint_indicator_flags(&self) -> u64 {
+        self.general_constraint_indicator_flags
+    }
+
+    pub fn general_level_idc(&self) -> u8 {
+        self.general_level_idc
+    }
+
+    pub fn min_spatial_segmentation_idc(&self) -> u16 {
+        self.min_spatial_segmentation_idc
+    }
+
+    pub fn parallelism_type(&self) -> u8 {
+        self.parallelism_type
+    }
+
+    pub fn chroma_format(&self) -> u8 {
+        self.chroma_format
+    }
+
+    pub fn bit_depth_luma(&self) -> u8 {
+        self.bit_depth_luma_minus8 + 8
+    }
+
+    pub fn bit_depth_chroma(&self) -> u8 {
+        self.bit_depth_chroma_minus8 + 8
+    }
+
+    pub fn avg_frame_rate(&self) -> u16 {
+        self.avg_frame_rate
+    }
+
+    pub fn constant_frame_rate(&self) -> u8 {
+        self.constant_frame_rate
+    }
+
+    pub fn num_temporal_layers(&self) -> u8 {
+        self.num_temporal_layers
+    }
+
+    pub fn temporal_id_nested(&self) -> bool {
+        self.temporal_id_nested
+    }
+
+    pub fn length_size_minus_one(&self) -> u8 {
+        self.length_size_minus_one
+    }
+}
+
+impl FromProperty for Hvcc {
+    fn from_property(prop: &Property) -> Option<&Hvcc> {
+        match prop {
+            Property::HvcCodecSettings(hvcc) => Some(hvcc),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Ispe {
+    fn from_property(prop: &Property) -> Option<&Ispe> {
+        match prop {
+            Property::Size(ispe) => Some(ispe),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Av1c {
+    fn from_property(prop: &Property) -> Option<&Av1c> {
+        match prop {
+            Property::Av1CodecSettings(av1c) => Some(av1c),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Jpgc {
+    fn from_property(prop: &Property) -> Option<&Jpgc> {
+        match prop {
+            Property::JpegPrefix(jpgc) => Some(jpgc),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Cmpd {
+    fn from_property(prop: &Property) -> Option<&Cmpd> {
+        match prop {
+            Property::ComponentDefinition(cmpd) => Some(cmpd),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Uncc {
+    fn from_property(prop: &Property) -> Option<&Uncc> {
+        match prop {
+            Property::UncompressedConfig(uncc) => Some(uncc),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Irot {
+    fn from_property(prop: &Property) -> Option<&Irot> {
+        match prop {
+            Property::Rotation(irot) => Some(irot),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Imir {
+    fn from_property(prop: &Property) -> Option<&Imir> {
+        match prop {
+            Property::Mirror(imir) => Some(imir),
+            _ => None,
+        }
+    }
+}
+
+impl FromProperty for Clap {
+    fn from_property(prop: &Property) -> Option<&Clap> {
+        match prop {
+            Property::CleanAperture(clap) => Some(clap),
+            _ => None,
+        }
+    }
+}
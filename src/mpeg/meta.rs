@@ -1,38 +1,53 @@
-use std::io;
-use std::io::BufRead;
 use std::io::Read;
 use std::io::Take;
+use std::mem;
 
 use byteorder::ReadBytesExt;
 use byteorder::BE;
 use cast::u32;
-use cast::usize;
+use cast::u64;
 use failure::Error;
 
+use error;
+use limits::Budget;
 use mpeg;
 use mpeg::iprp;
+use mpeg::parse_child;
 use mpeg::read_full_box_header;
 use mpeg::read_header;
+use mpeg::read_raw;
 use mpeg::read_u4_pair;
 use mpeg::read_value_of_size;
 use mpeg::skip;
+use mpeg::EntityGroup;
 use mpeg::Extent;
 use mpeg::FourCc;
 use mpeg::ItemInfo;
 use mpeg::ItemLoc;
+use mpeg::ItemReference;
+use mpeg::RawBox;
 
 // It's unclear that there should be at-least-, or precisely-, one of most of these.
 // TODO: It's probably specified.
 #[derive(Clone, Debug)]
 pub struct RawMeta {
-    pub handler: Vec<FourCc>,             // hdlr
-    pub primary_item: Vec<u16>,           // pitm
-    pub item_locators: Vec<Vec<ItemLoc>>, // iloc
-    pub item_infos: Vec<Vec<ItemInfo>>,   // iinf
-    pub item_props: Vec<iprp::RawProps>,  // iprp
+    pub handler: Vec<FourCc>,                 // hdlr
+    pub primary_item: Vec<u32>,               // pitm
+    pub item_locators: Vec<Vec<ItemLoc>>,     // iloc
+    pub item_infos: Vec<Vec<ItemInfo>>,       // iinf
+    pub item_props: Vec<iprp::RawProps>,      // iprp
+    pub item_refs: Vec<Vec<ItemReference>>,   // iref
+    pub entity_groups: Vec<Vec<EntityGroup>>, // grpl
+    pub item_data: Vec<Vec<u8>>,              // idat
+    pub unknown: Vec<RawBox>,
 }
 
-pub fn parse<R: Read>(mut from: &mut Take<R>) -> Result<RawMeta, Error> {
+pub fn parse<R: Read>(
+    mut from: &mut Take<R>,
+    offset: u64,
+    budget: &mut Budget,
+) -> Result<RawMeta, Error> {
+    let size = from.limit();
     let _ = read_full_box_header(&mut from)?;
 
     let mut handler = Vec::with_capacity(1);
@@ -40,24 +55,30 @@ pub fn parse<R: Read>(mut from: &mut Take<R>) -> Result<RawMeta, Error> {
     let mut item_locators = Vec::with_capacity(1);
     let mut item_infos = Vec::with_capacity(1);
     let mut item_props = Vec::with_capacity(1);
+    let mut item_refs = Vec::with_capacity(1);
+    let mut entity_groups = Vec::new();
+    let mut item_data = Vec::with_capacity(1);
+    let mut unknown = Vec::new();
 
     while 0 != from.limit() {
+        let child_offset = offset + size - from.limit();
         let child_header = read_header(&mut from)?;
+        let data_offset = child_offset + u64(child_header.offset);
         let mut child_data = (&mut from).take(child_header.data_size());
-        match child_header.box_type {
-            mpeg::HDLR => handler.push(parse_hdlr(&mut child_data)?),
-            mpeg::PITM => primary_item.push(parse_pitm(&mut child_data)?),
-            mpeg::ILOC => item_locators.push(parse_iloc(&mut child_data)?),
-            mpeg::IINF => item_infos.push(parse_iinf(&mut child_data)?),
-            mpeg::IPRP => item_props.push(iprp::parse_iprp(&mut child_data)?),
-            _ => skip(&mut child_data)?,
-        }
-
-        ensure!(
-            0 == child_data.limit(),
-            "meta parser failed to parse a segment: {:?}",
-            child_header
-        );
+        parse_child(&child_header, Some(data_offset), &mut child_data, |data| {
+            match child_header.box_type {
+                mpeg::HDLR => handler.push(parse_hdlr(data)?),
+                mpeg::PITM => primary_item.push(parse_pitm(data)?),
+                mpeg::ILOC => item_locators.push(parse_iloc(data, budget)?),
+                mpeg::IINF => item_infos.push(parse_iinf(data, budget)?),
+                mpeg::IPRP => item_props.push(iprp::parse_iprp(data, data_offset, budget)?),
+                mpeg::IREF => item_refs.push(parse_iref(data, budget)?),
+                mpeg::GRPL => entity_groups.push(parse_grpl(data, budget)?),
+                mpeg::IDAT => item_data.push(budget.read_to_end(data)?),
+                _ => unknown.push(read_raw(data, &child_header, child_offset, budget)?),
+            }
+            Ok(())
+        })?;
     }
 
     Ok(RawMeta {
@@ -66,42 +87,52 @@ pub fn parse<R: Read>(mut from: &mut Take<R>) -> Result<RawMeta, Error> {
         item_locators,
         item_infos,
         item_props,
+        item_refs,
+        entity_groups,
+        item_data,
+        unknown,
     })
 }
 
 pub fn parse_hdlr<R: Read>(mut from: &mut Take<R>) -> Result<FourCc, Error> {
     ensure!(from.limit() >= 4 + 4 + 4 + 12, "hdlr box is too small");
     let extended = read_full_box_header(&mut from)?;
-    ensure!(
-        0 == extended.version,
-        "unsupported hdlr version: {}",
-        extended.version
-    );
+    if 0 != extended.version {
+        return Err(error::unsupported(format!(
+            "unsupported hdlr version: {}",
+            extended.version
+        )));
+    }
 
     from.read_exact(&mut [0u8; 4])?;
     let ret = FourCc(from.read_u32::<BE>()?);
-    let remaining = usize(from.limit());
-    from.read_exact(&mut vec![0u8; remaining])?;
+    skip(from)?;
     Ok(ret)
 }
 
-pub fn parse_pitm<R: Read>(mut from: &mut Take<R>) -> Result<u16, Error> {
+pub fn parse_pitm<R: Read>(mut from: &mut Take<R>) -> Result<u32, Error> {
     let extended = read_full_box_header(&mut from)?;
-    ensure!(
-        0 == extended.version,
-        "unsupported pitm version: {}",
-        extended.version
-    );
-    Ok(from.read_u16::<BE>()?)
+    match extended.version {
+        0 => Ok(u32::from(from.read_u16::<BE>()?)),
+        1 => Ok(from.read_u32::<BE>()?),
+        version => Err(error::unsupported(format!(
+            "unsupported pitm version: {}",
+            version
+        ))),
+    }
 }
 
-pub fn parse_iloc<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemLoc>, Error> {
+pub fn parse_iloc<R: Read>(
+    mut from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<Vec<ItemLoc>, Error> {
     let extended = read_full_box_header(&mut from)?;
-    ensure!(
-        extended.version <= 2,
-        "unsupported iloc version: {}",
-        extended.version
-    );
+    if extended.version > 2 {
+        return Err(error::unsupported(format!(
+            "unsupported iloc version: {}",
+            extended.version
+        )));
+    }
     let (offset_size, length_size) = read_u4_pair(&mut from)?;
     let (base_offset_size, mut index_size) = read_u4_pair(&mut from)?;
 
@@ -115,7 +146,8 @@ pub fn parse_iloc<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemLoc>, Error
         from.read_u32::<BE>()?
     };
 
-    let mut items = Vec::with_capacity(usize(item_count));
+    budget.limits.check_items(u64(item_count))?;
+    let mut items = budget.vec(u64(item_count))?;
 
     for _ in 0..item_count {
         let id = if extended.version < 2 {
@@ -124,16 +156,17 @@ pub fn parse_iloc<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemLoc>, Error
             from.read_u32::<BE>()?
         };
 
+        let mut construction_method = 0;
         if extended.version > 0 {
             let _reserved = from.read_u8()?;
-            let (_, construction_method) = read_u4_pair(&mut from)?;
+            construction_method = read_u4_pair(&mut from)?.1;
         }
 
         let data_reference_index = from.read_u16::<BE>()?;
         let base_offset = read_value_of_size(&mut from, base_offset_size)?;
         let extent_count = from.read_u16::<BE>()?;
 
-        let mut extents = Vec::with_capacity(usize(extent_count));
+        let mut extents = budget.vec(u64(extent_count))?;
 
         for _ in 0..extent_count {
             let index = if index_size > 0 {
@@ -152,6 +185,7 @@ pub fn parse_iloc<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemLoc>, Error
 
         items.push(ItemLoc {
             id,
+            construction_method,
             data_reference_index,
             base_offset,
             extents,
@@ -161,16 +195,25 @@ pub fn parse_iloc<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemLoc>, Error
     Ok(items)
 }
 
-pub fn parse_iinf<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemInfo>, Error> {
+pub fn parse_iinf<R: Read>(
+    mut from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<Vec<ItemInfo>, Error> {
     let extended = read_full_box_header(&mut from)?;
-    ensure!(
-        0 == extended.version,
-        "unsupported iinf version: {}",
-        extended.version
-    );
-    let entry_count = from.read_u16::<BE>()?;
+    if extended.version > 1 {
+        return Err(error::unsupported(format!(
+            "unsupported iinf version: {}",
+            extended.version
+        )));
+    }
+    let entry_count = if 0 == extended.version {
+        u32(from.read_u16::<BE>()?)
+    } else {
+        from.read_u32::<BE>()?
+    };
 
-    let mut entries = Vec::with_capacity(usize(entry_count));
+    budget.limits.check_items(u64(entry_count))?;
+    let mut entries = budget.vec(u64(entry_count))?;
 
     for _ in 0..entry_count {
         let header = read_header(&mut from)?;
@@ -180,35 +223,358 @@ pub fn parse_iinf<R: Read>(mut from: &mut Take<R>) -> Result<Vec<ItemInfo>, Erro
             header
         );
 
-        let mut infe = io::BufReader::new(from.take(header.data_size()));
+        let mut infe = (&mut from).take(header.data_size());
+        entries.push(parse_child(&header, None, &mut infe, parse_infe)?);
+    }
 
-        let extended = read_full_box_header(&mut infe)?;
-        ensure!(
-            2 == extended.version,
+    Ok(entries)
+}
+
+pub fn parse_infe<R: Read>(from: &mut Take<R>) -> Result<ItemInfo, Error> {
+    let extended = read_full_box_header(&mut *from)?;
+    if extended.version > 3 {
+        return Err(error::unsupported(format!(
             "unsupported infe version: {}",
             extended.version
-        );
+        )));
+    }
 
-        let id = infe.read_u16::<BE>()?;
-        let protection_index = infe.read_u16::<BE>()?;
-        let item_type = FourCc(infe.read_u32::<BE>()?);
-        let mut item_name = Vec::new();
-        infe.read_until(0, &mut item_name)?;
+    let id = if extended.version < 3 {
+        u32(from.read_u16::<BE>()?)
+    } else {
+        from.read_u32::<BE>()?
+    };
+    let protection_index = from.read_u16::<BE>()?;
 
-        // TODO: presumably this doesn't actually work, due to BufReader
-        ensure!(
-            0 == infe.get_ref().limit(),
-            "failed to consume entire infe box: {}",
-            infe.get_ref().limit()
+    // versions 0 and 1 only describe MIME items, and have no type field
+    let item_type = if extended.version < 2 {
+        mpeg::MIME
+    } else {
+        FourCc(from.read_u32::<BE>()?)
+    };
+
+    let item_name = read_string(from)?;
+
+    let mut content_type = None;
+    let mut content_encoding = None;
+    let mut item_uri_type = None;
+
+    match item_type {
+        mpeg::MIME => {
+            content_type = Some(read_string(from)?);
+            if 0 != from.limit() {
+                content_encoding = Some(read_string(from)?);
+            }
+        }
+        mpeg::URI => item_uri_type = Some(read_string(from)?),
+        _ => (),
+    }
+
+    // version 1 may have an extension, which we have no use for
+    skip(from)?;
+
+    Ok(ItemInfo {
+        id,
+        protection_index,
+        item_type,
+        item_name,
+        content_type,
+        content_encoding,
+        item_uri_type,
+        hidden: 0 != (extended.flags & 1),
+    })
+}
+
+// null terminated, but some writers forget the terminator on the last string in the box
+pub fn read_string<R: Read>(from: &mut Take<R>) -> Result<String, Error> {
+    let mut bytes = Vec::new();
+    while 0 != from.limit() {
+        match from.read_u8()? {
+            0 => break,
+            byte => bytes.push(byte),
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+pub fn parse_iref<R: Read>(
+    mut from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<Vec<ItemReference>, Error> {
+    let extended = read_full_box_header(&mut from)?;
+    if extended.version > 1 {
+        return Err(error::unsupported(format!(
+            "unsupported iref version: {}",
+            extended.version
+        )));
+    }
+
+    let mut refs = Vec::new();
+
+    while 0 != from.limit() {
+        let header = read_header(&mut from)?;
+        let mut reference = (&mut from).take(header.data_size());
+
+        let (from_item_id, to_item_ids) = parse_child(&header, None, &mut reference, |data| {
+            let from_item_id = read_item_id(data, extended.version)?;
+            let reference_count = data.read_u16::<BE>()?;
+
+            let mut to_item_ids = budget.vec(u64(reference_count))?;
+            for _ in 0..reference_count {
+                to_item_ids.push(read_item_id(data, extended.version)?);
+            }
+            Ok((from_item_id, to_item_ids))
+        })?;
+
+        refs.push(ItemReference {
+            reference_type: header.box_type,
+            from_item_id,
+            to_item_ids,
+        });
+    }
+
+    Ok(refs)
+}
+
+// the short form is used unless the file has ids that don't fit
+fn read_item_id<R: Read>(from: &mut R, version: u8) -> Result<u32, Error> {
+    Ok(if 0 == version {
+        u32(from.read_u16::<BE>()?)
+    } else {
+        from.read_u32::<BE>()?
+    })
+}
+
+pub fn parse_grpl<R: Read>(
+    mut from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<Vec<EntityGroup>, Error> {
+    let mut groups = Vec::new();
+
+    while 0 != from.limit() {
+        // the count isn't given up front, so each group is charged as it's found
+        budget.limits.check_items(u64(groups.len()) + 1)?;
+        budget.allocate(u64(mem::size_of::<EntityGroup>()))?;
+
+        let header = read_header(&mut from)?;
+        let mut group = (&mut from).take(header.data_size());
+
+        let (group_id, entity_ids) = parse_child(&header, None, &mut group, |data| {
+            let extended = read_full_box_header(&mut *data)?;
+            if 0 != extended.version {
+                return Err(error::unsupported(format!(
+                    "unsupported {:?} version: {}",
+                    header.box_type, extended.version
+                )));
+            }
+
+            let group_id = data.read_u32::<BE>()?;
+            let num_entities_in_group = data.read_u32::<BE>()?;
+            ensure!(
+                u64(num_entities_in_group) * 4 <= data.limit(),
+                "too many entities for {:?} box: {}",
+                header.box_type,
+                num_entities_in_group
+            );
+
+            budget.limits.check_items(u64(num_entities_in_group))?;
+            let mut entity_ids = budget.vec(u64(num_entities_in_group))?;
+            for _ in 0..num_entities_in_group {
+                entity_ids.push(data.read_u32::<BE>()?);
+            }
+
+            // some grouping types carry extra data, which we don't understand yet
+            skip(data)?;
+
+            Ok((group_id, entity_ids))
+        })?;
+
+        groups.push(EntityGroup {
+            grouping_type: header.box_type,
+            group_id,
+            entity_ids,
+        });
+    }
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::io::Read;
+
+    use limits::Budget;
+    use limits::LimitExceeded;
+    use limits::Limits;
+    use mpeg;
+
+    #[test]
+    fn grpl() {
+        let bytes = [
+            0, 0, 0, 28, b'a', b'l', b't', b'r', 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, 3, 0,
+            0, 0, 1, //
+            0, 0, 0, 28, b's', b't', b'e', b'r', 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 2, 0, 0, 0, 4, 0,
+            0, 0, 5,
+        ];
+
+        let groups = super::parse_grpl(
+            &mut Cursor::new(&bytes[..]).take(bytes.len() as u64),
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+
+        assert_eq!(2, groups.len());
+        assert_eq!(mpeg::ALTR, groups[0].grouping_type);
+        assert_eq!(7, groups[0].group_id);
+        assert_eq!(vec![3, 1], groups[0].entity_ids);
+        assert_eq!(mpeg::STER, groups[1].grouping_type);
+        assert_eq!(vec![4, 5], groups[1].entity_ids);
+    }
+
+    #[test]
+    fn unknown_children() {
+        let bytes = [0, 0, 0, 0, 0, 0, 0, 12, b'a', b'p', b'p', b'l', 1, 2, 3, 4];
+
+        let meta = super::parse(
+            &mut Cursor::new(&bytes[..]).take(bytes.len() as u64),
+            100,
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+
+        assert_eq!(1, meta.unknown.len());
+        assert_eq!(104, meta.unknown[0].offset);
+        assert_eq!(vec![1, 2, 3, 4], meta.unknown[0].data);
+    }
+
+    #[test]
+    fn infe_versions() {
+        let bytes = [
+            0, 0, 0, 0, 0, 2, //
+            0, 0, 0, 27, b'i', b'n', b'f', b'e', 3, 0, 0, 1, 0, 0, 0, 9, 0, 0, b'm', b'i', b'm',
+            b'e', 0, b'x', b'm', b'p', 0, //
+            0, 0, 0, 23, b'i', b'n', b'f', b'e', 2, 0, 0, 0, 0, 7, 0, 0, b'E', b'x', b'i', b'f',
+            b'h', b'i', 0,
+        ];
+
+        let items = super::parse_iinf(
+            &mut Cursor::new(&bytes[..]).take(bytes.len() as u64),
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+
+        assert_eq!(2, items.len());
+        assert_eq!(9, items[0].id);
+        assert_eq!(mpeg::MIME, items[0].item_type);
+        assert_eq!("", items[0].item_name);
+        assert_eq!(Some("xmp".to_string()), items[0].content_type);
+        assert_eq!(None, items[0].content_encoding);
+        assert!(items[0].hidden);
+
+        assert_eq!(7, items[1].id);
+        assert_eq!("hi", items[1].item_name);
+        assert_eq!(None, items[1].content_type);
+        assert!(!items[1].hidden);
+    }
+
+    #[test]
+    fn iref() {
+        let bytes = [
+            0, 0, 0, 0, //
+            0, 0, 0, 14, b't', b'h', b'm', b'b', 0, 2, 0, 1, 0, 1, //
+            0, 0, 0, 16, b'c', b'd', b's', b'c', 0, 3, 0, 2, 0, 1, 0, 2,
+        ];
+
+        let refs = super::parse_iref(
+            &mut Cursor::new(&bytes[..]).take(bytes.len() as u64),
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+
+        assert_eq!(2, refs.len());
+        assert_eq!(mpeg::THMB, refs[0].reference_type);
+        assert_eq!(2, refs[0].from_item_id);
+        assert_eq!(&[1], refs[0].to_item_ids.as_slice());
+        assert_eq!(3, refs[1].from_item_id);
+        assert_eq!(&[1, 2], refs[1].to_item_ids.as_slice());
+    }
+
+    #[test]
+    fn limits() {
+        // an iloc which claims a million items, but has none of them
+        let bytes = [2, 0, 0, 0, 0x44, 0x00, 0x00, 0x0f, 0x42, 0x40];
+        let mut budget = Budget::default();
+        let err = super::parse_iloc(&mut Cursor::new(&bytes[..]).take(10), &mut budget)
+            .expect_err("too many items");
+        assert_eq!(
+            Some("max_items"),
+            err.downcast_ref::<LimitExceeded>().map(|e| e.limit)
         );
 
-        entries.push(ItemInfo {
-            id,
-            protection_index,
-            item_type,
-            item_name: String::from_utf8_lossy(&item_name).to_string(),
+        // a single item, with more extents than the budget allows room for
+        let bytes = [
+            1, 0, 0, 0, 0x44, 0x00, 0x00, 0x01, //
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
+        ];
+        let mut budget = Budget::new(Limits {
+            max_total_allocation: 1024,
+            ..Limits::default()
         });
+        let err = super::parse_iloc(&mut Cursor::new(&bytes[..]).take(16), &mut budget)
+            .expect_err("too many extents");
+        assert_eq!(
+            Some("max_total_allocation"),
+            err.downcast_ref::<LimitExceeded>().map(|e| e.limit)
+        );
     }
 
-    Ok(entries)
+    #[test]
+    fn grpl_limits() {
+        let group = |entities: &[u8]| {
+            let mut group = vec![0, 0, 0, 20 + 4 * entities.len() as u8];
+            group.extend_from_slice(b"altr");
+            group.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, entities.len() as u8]);
+            for &entity in entities {
+                group.extend_from_slice(&[0, 0, 0, entity]);
+            }
+            group
+        };
+        let parse = |bytes: &[u8], limits| {
+            super::parse_grpl(
+                &mut Cursor::new(bytes).take(bytes.len() as u64),
+                &mut Budget::new(limits),
+            )
+            .map_err(|e| {
+                // the limits inside a group come back wrapped in the group's box
+                e.iter_chain()
+                    .filter_map(|cause| cause.downcast_ref::<LimitExceeded>())
+                    .map(|e| e.limit)
+                    .next()
+            })
+        };
+        let two_items = Limits {
+            max_items: 2,
+            ..Limits::default()
+        };
+
+        let big = group(&[1, 2, 3]);
+        assert_eq!(Some("max_items"), parse(&big, two_items).unwrap_err());
+
+        let mut many = group(&[1]);
+        many.extend(group(&[2]));
+        assert_eq!(2, parse(&many, two_items).unwrap().len());
+        many.extend(group(&[3]));
+        assert_eq!(Some("max_items"), parse(&many, two_items).unwrap_err());
+
+        let tight = Limits {
+            max_total_allocation: 8,
+            ..Limits::default()
+        };
+        assert_eq!(
+            Some("max_total_allocation"),
+            parse(&group(&[1]), tight).unwrap_err()
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io;
 use std::io::Read;
 use std::io::Take;
 
@@ -10,28 +11,76 @@ use cast::u8;
 use cast::usize;
 use failure::Error;
 
+use error;
+use limits::Budget;
+use limits::Limits;
+
 pub mod iprp;
 pub mod meta;
+pub mod tree;
+pub mod unci;
+pub mod write;
 
 #[derive(Copy, Clone, Hash, Eq, PartialEq)]
 pub struct FourCc(u32);
 
+pub const ALTR: FourCc = FourCc(0x616c7472); // altr
+pub const AV01: FourCc = FourCc(0x61763031); // av01
+pub const AV1C: FourCc = FourCc(0x61763143); // av1C
+pub const AVIF: FourCc = FourCc(0x61766966); // avif
+pub const AVIS: FourCc = FourCc(0x61766973); // avis
+pub const BASE: FourCc = FourCc(0x62617365); // base
+pub const CDSC: FourCc = FourCc(0x63647363); // cdsc
+pub const CLAP: FourCc = FourCc(0x636c6170); // clap
+pub const CMPD: FourCc = FourCc(0x636d7064); // cmpd
+pub const DIMG: FourCc = FourCc(0x64696d67); // dimg
+pub const EXIF: FourCc = FourCc(0x45786966); // Exif
+pub const FREE: FourCc = FourCc(0x66726565); // free
 pub const FTYP: FourCc = FourCc(0x66747970); // ftyp
+pub const GRID: FourCc = FourCc(0x67726964); // grid
+pub const GRPL: FourCc = FourCc(0x6772706c); // grpl
 pub const HDLR: FourCc = FourCc(0x68646c72); // hdlr
 pub const HEIC: FourCc = FourCc(0x68656963); // heic
+pub const HEIM: FourCc = FourCc(0x6865696d); // heim
+pub const HEIS: FourCc = FourCc(0x68656973); // heis
+pub const HEIX: FourCc = FourCc(0x68656978); // heix
+pub const HEVC: FourCc = FourCc(0x68657663); // hevc
+pub const HEVM: FourCc = FourCc(0x6865766d); // hevm
+pub const HEVS: FourCc = FourCc(0x68657673); // hevs
+pub const HEVX: FourCc = FourCc(0x68657678); // hevx
 pub const HVC1: FourCc = FourCc(0x68766331); // hvc1
 pub const HVCC: FourCc = FourCc(0x68766343); // hvcC
+pub const IDAT: FourCc = FourCc(0x69646174); // idat
+pub const IDEN: FourCc = FourCc(0x6964656e); // iden
 pub const IINF: FourCc = FourCc(0x69696e66); // iinf
 pub const ILOC: FourCc = FourCc(0x696c6f63); // iloc
+pub const IMIR: FourCc = FourCc(0x696d6972); // imir
 pub const INFE: FourCc = FourCc(0x696e6665); // infe
+pub const IOVL: FourCc = FourCc(0x696f766c); // iovl
 pub const IPCO: FourCc = FourCc(0x6970636f); // ipco
 pub const IPMA: FourCc = FourCc(0x69706d61); // ipma
 pub const IPRP: FourCc = FourCc(0x69707270); // iprp
+pub const IREF: FourCc = FourCc(0x69726566); // iref
+pub const IROT: FourCc = FourCc(0x69726f74); // irot
 pub const ISPE: FourCc = FourCc(0x69737065); // ispe
+pub const JPEG: FourCc = FourCc(0x6a706567); // jpeg
+pub const JPGC: FourCc = FourCc(0x6a706743); // jpgC
 pub const META: FourCc = FourCc(0x6d657461); // meta
 pub const MDAT: FourCc = FourCc(0x6d646174); // mdat
+pub const MIAF: FourCc = FourCc(0x6d696166); // miaf
+pub const MIF1: FourCc = FourCc(0x6d696631); // mif1
+pub const MIF2: FourCc = FourCc(0x6d696632); // mif2
+pub const MIME: FourCc = FourCc(0x6d696d65); // mime
 pub const MOOV: FourCc = FourCc(0x6d6f6f76); // moov
+pub const MSF1: FourCc = FourCc(0x6d736631); // msf1
+pub const PICT: FourCc = FourCc(0x70696374); // pict
 pub const PITM: FourCc = FourCc(0x7069746d); // pitm
+pub const SKIP: FourCc = FourCc(0x736b6970); // skip
+pub const STER: FourCc = FourCc(0x73746572); // ster
+pub const THMB: FourCc = FourCc(0x74686d62); // thmb
+pub const UNCC: FourCc = FourCc(0x756e6343); // uncC
+pub const UNCI: FourCc = FourCc(0x756e6369); // unci
+pub const URI: FourCc = FourCc(0x75726920); // uri
 
 #[derive(Copy, Clone, Debug)]
 pub struct BoxHeader {
@@ -40,27 +89,42 @@ pub struct BoxHeader {
     pub offset: u8,
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct ExtendedHeader {
     pub version: u8,
     pub flags: u32,
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct FileType {
     pub major_brand: FourCc,
     pub minor_version: u32,
     pub brands: Vec<FourCc>,
 }
 
+bitflags! {
+    // what a file's brands promise it contains
+    #[derive(Default)]
+    pub struct Structure: u8 {
+        const IMAGE_ITEMS    = 1 << 0;
+        const IMAGE_SEQUENCE = 1 << 1;
+    }
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ItemLoc {
     pub id: u32,
+    // 0: offsets are in the file, 1: offsets are in the 'idat', 2: offsets are in an item
+    pub construction_method: u8,
     pub data_reference_index: u16,
     pub base_offset: u64,
     pub extents: Vec<Extent>,
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Extent {
     pub index: u64,
     pub offset: u64,
@@ -68,11 +132,66 @@ pub struct Extent {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct ItemInfo {
-    pub id: u16,
+    pub id: u32,
     pub protection_index: u16,
     pub item_type: FourCc,
     pub item_name: String,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub item_uri_type: Option<String>,
+    pub hidden: bool,
+}
+
+// a box we don't understand, kept so it can be inspected, or written back out
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RawBox {
+    pub box_type: FourCc,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+// 'from' refers to each of the 'to' items, e.g. a 'thmb' from a thumbnail to its master
+#[derive(Clone, Debug)]
+pub struct ItemReference {
+    pub reference_type: FourCc,
+    pub from_item_id: u32,
+    pub to_item_ids: Vec<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntityGroup {
+    pub grouping_type: FourCc,
+    pub group_id: u32,
+    pub entity_ids: Vec<u32>,
+}
+
+impl FileType {
+    pub fn has_brand(&self, brand: FourCc) -> bool {
+        self.major_brand == brand || self.brands.contains(&brand)
+    }
+
+    // the structural brands, mif1 and msf1, are meant to be present, but many writers only
+    // list the codec brand, so the codec brands imply the structure too
+    pub fn structure(&self) -> Structure {
+        let mut structure = Structure::default();
+
+        for brand in &[MIF1, MIF2, MIAF, HEIC, HEIX, HEIM, HEIS, AVIF] {
+            if self.has_brand(*brand) {
+                structure |= Structure::IMAGE_ITEMS;
+            }
+        }
+
+        for brand in &[MSF1, HEVC, HEVX, HEVM, HEVS, AVIS] {
+            if self.has_brand(*brand) {
+                structure |= Structure::IMAGE_SEQUENCE;
+            }
+        }
+
+        structure
+    }
 }
 
 impl BoxHeader {
@@ -81,35 +200,155 @@ impl BoxHeader {
     }
 }
 
-pub fn load_meta<R: Read>(mut from: R) -> Result<meta::RawMeta, Error> {
-    let file_type = loop {
-        let header = read_header(&mut from)?;
+pub fn load_meta<R: Read>(from: R, limits: &Limits) -> Result<(FileType, meta::RawMeta), Error> {
+    let mut loader = MetaLoader::new(limits);
+    let loaded = read_meta(from, &mut loader);
+    error::at(loaded, loader.position())
+}
 
-        match header.box_type {
-            FTYP => break parse_ftyp(&mut (&mut from).take(header.data_size()))?,
-            META | MOOV => bail!("invalid header before 'ftyp': {:?}", header),
-            _ => skip_box(&mut from, &header)?,
+fn read_meta<R: Read>(
+    mut from: R,
+    loader: &mut MetaLoader,
+) -> Result<(FileType, meta::RawMeta), Error> {
+    loop {
+        let header = match read_header(&mut from) {
+            Err(ref e) if loader.file_type.is_some() && is_eof(e) => {
+                return Err(error::unsupported(format!(
+                    "there's no meta, so no image items, and image sequences are not supported: {:?}",
+                    loader.file_type
+                )));
+            }
+            header => header?,
+        };
+        let mut data = (&mut from).take(header.data_size());
+
+        if !loader.wants(&header)? {
+            skip(&mut data)?;
+            loader.skipped(&header);
+            continue;
         }
-    };
 
-    ensure!(
-        file_type.major_brand == HEIC || file_type.brands.contains(&HEIC),
-        "file is not an heic file: {:?}",
-        file_type
-    );
+        if let Some(loaded) = loader.parse(&header, &mut data)? {
+            return Ok(loaded);
+        }
+    }
+}
+
+fn is_eof(e: &Error) -> bool {
+    match e.downcast_ref::<io::Error>() {
+        Some(e) => io::ErrorKind::UnexpectedEof == e.kind(),
+        None => false,
+    }
+}
 
-    Ok(loop {
-        let header = read_header(&mut from)?;
+// Which of the top-level boxes we need, in order, and what they hold; the reading is up
+// to the caller, so this can be driven by sync or async I/O alike.
+pub struct MetaLoader {
+    limits: Limits,
+    budget: Budget,
+    // absolute position of the start of the box being read, for reporting offsets
+    pos: u64,
+    file_type: Option<FileType>,
+}
 
-        match header.box_type {
-            META => break meta::parse(&mut (&mut from).take(header.data_size()))?,
-            FTYP | MOOV | MDAT => bail!("invalid header before 'meta': {:?}", header),
-            _ => skip_box(&mut from, &header)?,
+impl MetaLoader {
+    pub fn new(limits: &Limits) -> MetaLoader {
+        MetaLoader {
+            limits: *limits,
+            budget: Budget::new(*limits),
+            pos: 0,
+            file_type: None,
         }
-    })
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    // whether this box's data should be read, and passed to `parse`, or skipped
+    pub fn wants(&self, header: &BoxHeader) -> Result<bool, Error> {
+        let wanted = match (&self.file_type, header.box_type) {
+            (None, FTYP) | (Some(_), META) => true,
+            (None, META) | (None, MOOV) => bail!("invalid header before 'ftyp': {:?}", header),
+            (Some(_), FTYP) | (Some(_), MDAT) => {
+                bail!("invalid header before 'meta': {:?}", header)
+            }
+            _ => false,
+        };
+
+        if wanted {
+            // before anyone reads it into memory
+            let data_offset = self.pos + u64(header.offset);
+            error::in_box(
+                self.limits.check_box(header),
+                header.box_type,
+                Some(data_offset),
+            )?;
+        }
+        Ok(wanted)
+    }
+
+    pub fn skipped(&mut self, header: &BoxHeader) {
+        // nothing may have been read to check the size against
+        self.pos = self.pos.saturating_add(header.size);
+    }
+
+    // the data of a box it `wants`; done when the 'meta' has been read
+    pub fn parse<R: Read>(
+        &mut self,
+        header: &BoxHeader,
+        data: &mut Take<R>,
+    ) -> Result<Option<(FileType, meta::RawMeta)>, Error> {
+        let data_offset = self.pos + u64(header.offset);
+
+        if let Some(file_type) = self.file_type.take() {
+            let budget = &mut self.budget;
+            let meta = parse_child(header, Some(data_offset), data, |data| {
+                meta::parse(data, data_offset, budget)
+            })?;
+            return Ok(Some((file_type, meta)));
+        }
+
+        let file_type = parse_child(header, Some(data_offset), data, parse_ftyp)?;
+        self.pos += header.size;
+
+        let structure = file_type.structure();
+
+        ensure!(
+            !structure.is_empty(),
+            "file is not a heif file: {:?}",
+            file_type
+        );
+
+        self.file_type = Some(file_type);
+        Ok(None)
+    }
 }
 
-fn read_header<R: Read>(mut from: R) -> Result<BoxHeader, Error> {
+// Parse the data of a child box, which must all be used. Failures are marked with the box,
+// and with where parsing stopped, if we know where the data starts.
+pub fn parse_child<R: Read, T, F>(
+    header: &BoxHeader,
+    data_offset: Option<u64>,
+    data: &mut Take<R>,
+    parse: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&mut Take<R>) -> Result<T, Error>,
+{
+    let parsed = parse(data).and_then(|value| {
+        ensure!(
+            0 == data.limit(),
+            "{} bytes at the end of the box weren't understood",
+            data.limit()
+        );
+        Ok(value)
+    });
+    let stopped = data_offset.map(|start| start + header.data_size() - data.limit());
+    error::in_box(parsed, header.box_type, stopped)
+}
+
+pub fn read_header<R: Read>(mut from: R) -> Result<BoxHeader, Error> {
     let size_low = from.read_u32::<BE>()?;
     let box_type = FourCc(from.read_u32::<BE>()?);
 
@@ -123,7 +362,12 @@ fn read_header<R: Read>(mut from: R) -> Result<BoxHeader, Error> {
             );
             (16, size_high)
         }
-        0 | 2...7 => bail!("unsupported box length: {}", size_low),
+        0 | 2...7 => {
+            return Err(error::unsupported(format!(
+                "unsupported box length: {}",
+                size_low
+            )))
+        }
         other => (8, u64(other)),
     };
 
@@ -153,7 +397,7 @@ pub fn parse_ftyp<R: Read>(from: &mut Take<R>) -> Result<FileType, Error> {
         brands.push(FourCc(from.read_u32::<BE>()?));
     }
 
-    assert_eq!(0, from.limit());
+    ensure!(0 == from.limit(), "'ftyp' not fully read");
 
     Ok(FileType {
         major_brand,
@@ -169,9 +413,10 @@ fn read_u4_pair<R: Read>(mut from: R) -> Result<(u8, u8), Error> {
 
 fn read_value_of_size<R: Read>(mut from: R, bytes: u8) -> Result<u64, Error> {
     Ok(match bytes {
+        0 => 0,
         4 => u64(from.read_u32::<BE>()?),
         8 => from.read_u64::<BE>()?,
-        other => bail!("unsupported size: {}", other),
+        other => return Err(error::unsupported(format!("unsupported size: {}", other))),
     })
 }
 
@@ -179,23 +424,67 @@ pub fn skip_box<R: Read>(mut from: R, header: &BoxHeader) -> Result<(), Error> {
     skip(&mut (&mut from).take(header.data_size()))
 }
 
+fn read_raw<R: Read>(
+    child_data: &mut Take<R>,
+    header: &BoxHeader,
+    offset: u64,
+    budget: &mut Budget,
+) -> Result<RawBox, Error> {
+    let data = budget.read_to_end(child_data)?;
+    if u64(data.len()) != header.data_size() {
+        return Err(error::truncated(format!("box truncated: {:?}", header)));
+    }
+    Ok(RawBox {
+        box_type: header.box_type,
+        offset,
+        data,
+    })
+}
+
 fn skip<R: Read>(child_data: &mut Take<R>) -> Result<(), Error> {
-    let remaining = usize(child_data.limit());
-    // TODO: don't have unbounded allocation here
-    child_data.read_exact(&mut vec![0u8; remaining])?;
+    let remaining = child_data.limit();
+    if remaining != io::copy(child_data, &mut io::sink())? {
+        return Err(error::truncated(format!(
+            "box truncated while skipping {} bytes",
+            remaining
+        )));
+    }
     Ok(())
 }
 
+impl From<[u8; 4]> for FourCc {
+    fn from(bytes: [u8; 4]) -> FourCc {
+        FourCc(BE::read_u32(&bytes))
+    }
+}
+
 impl fmt::Debug for FourCc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl fmt::Display for FourCc {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut buf = [0u8; 4];
         BE::write_u32(&mut buf, self.0);
-        write!(f, "{:?}", String::from_utf8_lossy(&buf))
+        f.write_str(&String::from_utf8_lossy(&buf))
+    }
+}
+
+// as the four characters, not the number
+#[cfg(feature = "serde")]
+impl serde::Serialize for FourCc {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::FileType;
+    use super::Structure;
+
     fn pack_fourcc(str: &[u8]) -> u32 {
         use byteorder::ByteOrder;
         use byteorder::BE;
@@ -205,8 +494,12 @@ mod tests {
     #[test]
     fn packing_fourcc() {
         for key in &[
-            "ftyp", "hdlr", "heic", "hvc1", "hvcC", "iinf", "iloc", "infe", "ipco", "ipma", "iprp",
-            "ispe", "meta", "mdat", "moov", "pitm",
+            "altr", "av01", "av1C", "avif", "avis", "base", "cdsc", "clap", "cmpd", "dimg", "Exif",
+            "free", "ftyp", "grid", "grpl", "hdlr", "heic", "heim", "heis", "heix", "hevc", "hevm",
+            "hevs", "hevx", "hvc1", "hvcC", "idat", "iden", "iinf", "iloc", "imir", "infe", "iovl",
+            "ipco", "ipma", "iprp", "iref", "irot", "ispe", "jpeg", "jpgC", "mdat", "meta", "miaf",
+            "mif1", "mif2", "mime", "moov", "msf1", "pict", "pitm", "skip", "ster", "thmb", "uncC",
+            "unci", "uri ",
         ] {
             println!(
                 "pub const {}: FourCc = FourCc(0x{:08x}); // {}",
@@ -216,4 +509,31 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn brand_structure() {
+        let file_type = |major, brands: &[super::FourCc]| FileType {
+            major_brand: major,
+            minor_version: 0,
+            brands: brands.to_vec(),
+        };
+
+        assert_eq!(
+            Structure::IMAGE_ITEMS,
+            file_type(super::MIF1, &[super::MIF1]).structure()
+        );
+        assert_eq!(
+            Structure::IMAGE_ITEMS,
+            file_type(super::AVIF, &[super::MIAF]).structure()
+        );
+        assert_eq!(
+            Structure::IMAGE_SEQUENCE,
+            file_type(super::MSF1, &[super::HEVC]).structure()
+        );
+        assert_eq!(
+            Structure::all(),
+            file_type(super::HEIX, &[super::MIF1, super::MSF1]).structure()
+        );
+        assert!(file_type(super::MOOV, &[]).structure().is_empty());
+    }
 }
@@ -0,0 +1,567 @@
+use std::io::Read;
+use std::io::Take;
+
+use byteorder::ReadBytesExt;
+use byteorder::BE;
+use cast::u64;
+use cast::usize;
+use failure::Error;
+
+use error;
+use limits::Budget;
+use limits::Limits;
+use mpeg;
+use mpeg::iprp;
+use mpeg::meta;
+use mpeg::read_full_box_header;
+use mpeg::read_header;
+use mpeg::skip;
+use mpeg::BoxHeader;
+use mpeg::ExtendedHeader;
+use mpeg::FourCc;
+
+// Every box in a file, as it's laid out, for inspecting files rather than reading images.
+#[derive(Clone, Debug)]
+pub struct BoxNode {
+    pub header: BoxHeader,
+    // absolute, in the file, of the start of the header
+    pub offset: u64,
+    // for the full boxes we know of
+    pub full: Option<ExtendedHeader>,
+    // after the header, including any version and flags; for containers, only up to the
+    // children, and empty for boxes we don't decode, like the 'mdat'
+    pub data: Vec<u8>,
+    pub children: Vec<BoxNode>,
+}
+
+impl BoxNode {
+    // as libheif counts it, with the version and flags
+    pub fn header_size(&self) -> u64 {
+        u64(self.header.offset) + if self.full.is_some() { 4 } else { 0 }
+    }
+}
+
+pub fn read_tree<R: Read>(mut from: R, limits: &Limits) -> Result<Vec<BoxNode>, Error> {
+    let mut budget = Budget::new(*limits);
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+
+    loop {
+        // the end of the file is only fine between boxes
+        let mut first = [0u8; 1];
+        if 0 == from.read(&mut first)? {
+            return Ok(boxes);
+        }
+
+        let header = error::at(read_header((&first[..]).chain(&mut from)), offset)?;
+        let mut data = (&mut from).take(header.data_size());
+        let node = read_node(header, offset, &mut data, &mut budget);
+        boxes.push(error::in_box(node, header.box_type, Some(offset))?);
+        offset = offset.saturating_add(header.size);
+    }
+}
+
+// the children of a container, which has already been read into memory
+fn read_children(
+    mut data: &[u8],
+    mut offset: u64,
+    budget: &mut Budget,
+) -> Result<Vec<BoxNode>, Error> {
+    let mut children = Vec::new();
+
+    while !data.is_empty() {
+        let header = read_header(&mut data)?;
+        ensure!(
+            header.data_size() <= u64(data.len()),
+            "{:?} is larger than its parent",
+            header
+        );
+
+        let (child, rest) = data.split_at(usize(header.data_size()));
+        let node = read_node(header, offset, &mut child.take(header.data_size()), budget);
+        children.push(error::in_box(node, header.box_type, Some(offset))?);
+        offset += header.size;
+        data = rest;
+    }
+
+    Ok(children)
+}
+
+fn read_node<R: Read>(
+    header: BoxHeader,
+    offset: u64,
+    from: &mut Take<R>,
+    budget: &mut Budget,
+) -> Result<BoxNode, Error> {
+    let mut node = BoxNode {
+        header,
+        offset,
+        full: None,
+        data: Vec::new(),
+        children: Vec::new(),
+    };
+
+    let container = match header.box_type {
+        mpeg::META | mpeg::IINF | mpeg::IPRP | mpeg::IPCO => true,
+        mpeg::FTYP
+        | mpeg::HDLR
+        | mpeg::PITM
+        | mpeg::ILOC
+        | mpeg::INFE
+        | mpeg::IREF
+        | mpeg::GRPL
+        | mpeg::IDAT
+        | mpeg::HVCC
+        | mpeg::AV1C
+        | mpeg::ISPE
+        | mpeg::IROT
+        | mpeg::IMIR
+        | mpeg::CLAP
+        | mpeg::IPMA => false,
+        _ => {
+            skip(from)?;
+            return Ok(node);
+        }
+    };
+
+    budget.limits.check_box(&header)?;
+
+    if !container {
+        node.data = read_rest(from, &header, budget)?;
+        if full_box(header.box_type) {
+            node.full = Some(read_full_box_header(&node.data[..])?);
+        }
+        return Ok(node);
+    }
+
+    if full_box(header.box_type) {
+        let mut version_and_flags = [0u8; 4];
+        from.read_exact(&mut version_and_flags)?;
+        node.data.extend_from_slice(&version_and_flags);
+        let extended = read_full_box_header(&version_and_flags[..])?;
+        if mpeg::IINF == header.box_type {
+            // the entry count, which the children make redundant
+            let count_size = if 0 == extended.version { 2 } else { 4 };
+            for _ in 0..count_size {
+                node.data.push(from.read_u8()?);
+            }
+        }
+        node.full = Some(extended);
+    }
+
+    let children_offset = offset + u64(header.offset) + u64(node.data.len());
+    let children = read_rest(from, &header, budget)?;
+    node.children = read_children(&children, children_offset, budget)?;
+    Ok(node)
+}
+
+fn read_rest<R: Read>(
+    from: &mut Take<R>,
+    header: &BoxHeader,
+    budget: &mut Budget,
+) -> Result<Vec<u8>, Error> {
+    let expected = from.limit();
+    let data = budget.read_to_end(from)?;
+    if u64(data.len()) != expected {
+        return Err(error::truncated(format!("box truncated: {:?}", header)));
+    }
+    Ok(data)
+}
+
+fn full_box(box_type: FourCc) -> bool {
+    [
+        mpeg::META,
+        mpeg::HDLR,
+        mpeg::PITM,
+        mpeg::ILOC,
+        mpeg::IINF,
+        mpeg::INFE,
+        mpeg::IREF,
+        mpeg::ISPE,
+        mpeg::IPMA,
+    ]
+    .contains(&box_type)
+}
+
+// The same text as libheif's `heif-info -d`: each box's header, then whatever we can
+// decode of its fields, then its children, indented.
+pub fn dump(boxes: &[BoxNode]) -> Result<String, Error> {
+    let mut out = String::new();
+    dump_children(&mut out, boxes, 0)?;
+    Ok(out)
+}
+
+fn dump_children(out: &mut String, boxes: &[BoxNode], depth: usize) -> Result<(), Error> {
+    for (i, node) in boxes.iter().enumerate() {
+        if 0 != i {
+            line(out, depth, "");
+        }
+        dump_box(out, node, depth)?;
+    }
+    Ok(())
+}
+
+// libheif leaves trailing spaces, e.g. after an empty name; the reference dumps don't
+fn line(out: &mut String, depth: usize, text: &str) {
+    let mut line = "| ".repeat(depth);
+    line.push_str(text);
+    out.push_str(line.trim_end());
+    out.push('\n');
+}
+
+fn dump_box(out: &mut String, node: &BoxNode, depth: usize) -> Result<(), Error> {
+    let header = &node.header;
+    line(out, depth, &format!("Box: {} -----", header.box_type));
+    line(
+        out,
+        depth,
+        &format!(
+            "size: {}   (header size: {})",
+            header.size,
+            node.header_size()
+        ),
+    );
+    if let Some(ref extended) = node.full {
+        line(out, depth, &format!("version: {}", extended.version));
+        line(out, depth, &format!("flags: {:x}", extended.flags));
+    }
+
+    let len = u64(node.data.len());
+    let mut data = (&node.data[..]).take(len);
+    let result = dump_fields(out, header.box_type, &mut data, depth);
+    error::in_box(result, header.box_type, Some(node.offset))?;
+
+    dump_children(out, &node.children, depth + 1)
+}
+
+fn dump_fields(
+    out: &mut String,
+    box_type: FourCc,
+    data: &mut Take<&[u8]>,
+    depth: usize,
+) -> Result<(), Error> {
+    // the data has already been read, so this only guards the counts in it
+    let budget = &mut Budget::default();
+
+    match box_type {
+        mpeg::FTYP => {
+            let file_type = mpeg::parse_ftyp(data)?;
+            let brands = file_type
+                .brands
+                .iter()
+                .map(|brand| brand.to_string())
+                .collect::<Vec<_>>();
+            line(
+                out,
+                depth,
+                &format!("major brand: {}", file_type.major_brand),
+            );
+            line(
+                out,
+                depth,
+                &format!("minor version: {}", file_type.minor_version),
+            );
+            line(
+                out,
+                depth,
+                &format!("compatible brands: {}", brands.join(",")),
+            );
+        }
+        mpeg::HDLR => {
+            let _ = read_full_box_header(&mut *data)?;
+            let pre_defined = data.read_u32::<BE>()?;
+            let handler_type = FourCc(data.read_u32::<BE>()?);
+            data.read_exact(&mut [0u8; 12])?;
+            let name = meta::read_string(data)?;
+            line(out, depth, &format!("pre_defined: {}", pre_defined));
+            line(out, depth, &format!("handler_type: {}", handler_type));
+            line(out, depth, &format!("name: {}", name));
+        }
+        mpeg::PITM => {
+            let item = meta::parse_pitm(data)?;
+            line(out, depth, &format!("item_ID: {}", item));
+        }
+        mpeg::ILOC => {
+            for loc in meta::parse_iloc(data, budget)? {
+                let extents = loc
+                    .extents
+                    .iter()
+                    .map(|extent| match extent.index {
+                        0 => format!("{},{}", extent.offset, extent.length),
+                        index => format!("{},{};index={}", extent.offset, extent.length, index),
+                    })
+                    .collect::<Vec<_>>();
+                line(out, depth, &format!("item ID: {}", loc.id));
+                line(
+                    out,
+                    depth,
+                    &format!("  construction method: {}", loc.construction_method),
+                );
+                line(
+                    out,
+                    depth,
+                    &format!("  data_reference_index: {:x}", loc.data_reference_index),
+                );
+                line(out, depth, &format!("  base_offset: {}", loc.base_offset));
+                line(out, depth, &format!("  extents: {}", extents.join(" ")));
+            }
+        }
+        mpeg::INFE => {
+            let info = meta::parse_infe(data)?;
+            let or_empty = |value: &Option<String>| value.clone().unwrap_or_default();
+            line(out, depth, &format!("item_ID: {}", info.id));
+            line(
+                out,
+                depth,
+                &format!("item_protection_index: {}", info.protection_index),
+            );
+            line(out, depth, &format!("item_type: {}", info.item_type));
+            line(out, depth, &format!("item_name: {}", info.item_name));
+            line(
+                out,
+                depth,
+                &format!("content_type: {}", or_empty(&info.content_type)),
+            );
+            line(
+                out,
+                depth,
+                &format!("content_encoding: {}", or_empty(&info.content_encoding)),
+            );
+            line(
+                out,
+                depth,
+                &format!("item uri type: {}", or_empty(&info.item_uri_type)),
+            );
+            line(out, depth, &format!("hidden item: {}", info.hidden));
+        }
+        mpeg::IREF => {
+            for reference in meta::parse_iref(data, budget)? {
+                let to = reference
+                    .to_item_ids
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>();
+                line(
+                    out,
+                    depth,
+                    &format!(
+                        "reference with type '{}' from ID: {} to IDs: {}",
+                        reference.reference_type,
+                        reference.from_item_id,
+                        to.join(" ")
+                    ),
+                );
+            }
+        }
+        mpeg::GRPL => {
+            for group in meta::parse_grpl(data, budget)? {
+                let entities = group
+                    .entity_ids
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>();
+                line(out, depth, &format!("group type: {}", group.grouping_type));
+                line(out, depth + 1, &format!("group id: {}", group.group_id));
+                line(
+                    out,
+                    depth + 1,
+                    &format!("entity IDs: {}", entities.join(" ")),
+                );
+            }
+        }
+        mpeg::IDAT => {
+            line(
+                out,
+                depth,
+                &format!("number of data bytes: {}", data.limit()),
+            );
+            skip(data)?;
+        }
+        mpeg::HVCC => dump_hvcc(out, &iprp::parse_hvcc(data, budget)?, depth),
+        mpeg::AV1C => {
+            let av1c = iprp::parse_av1c(data, budget)?;
+            let delay = match av1c.initial_presentation_delay_minus_one {
+                Some(delay) => (u64::from(delay) + 1).to_string(),
+                None => "not present".to_string(),
+            };
+            for &(name, value) in &[
+                ("seq_profile", av1c.seq_profile),
+                ("seq_level_idx_0", av1c.seq_level_idx_0),
+                ("seq_tier_0", u8::from(av1c.seq_tier_0)),
+                ("high_bitdepth", u8::from(av1c.high_bitdepth)),
+                ("twelve_bit", u8::from(av1c.twelve_bit)),
+                ("monochrome", u8::from(av1c.monochrome)),
+                ("chroma_subsampling_x", u8::from(av1c.chroma_subsampling_x)),
+                ("chroma_subsampling_y", u8::from(av1c.chroma_subsampling_y)),
+                ("chroma_sample_position", av1c.chroma_sample_position),
+            ] {
+                line(out, depth, &format!("{}: {}", name, value));
+            }
+            line(
+                out,
+                depth,
+                &format!("initial_presentation_delay: {}", delay),
+            );
+            line(
+                out,
+                depth,
+                &format!("config OBUs: {}", hex(&av1c.config_obus)),
+            );
+        }
+        mpeg::ISPE => {
+            let ispe = iprp::parse_ispe(data)?;
+            line(out, depth, &format!("image width: {}", ispe.width));
+            line(out, depth, &format!("image height: {}", ispe.height));
+        }
+        mpeg::IROT => {
+            let angle = data.read_u8()? & 0b11;
+            line(
+                out,
+                depth,
+                &format!("rotation: {} degrees (CCW)", u64::from(angle) * 90),
+            );
+        }
+        mpeg::IMIR => {
+            let axis = match data.read_u8()? & 0b1 {
+                0 => "vertical",
+                _ => "horizontal",
+            };
+            line(out, depth, &format!("mirror axis: {}", axis));
+        }
+        mpeg::CLAP => {
+            let clap = iprp::parse_clap(data)?;
+            line(
+                out,
+                depth,
+                &format!(
+                    "clean_aperture: {}/{} x {}/{}",
+                    clap.width_n, clap.width_d, clap.height_n, clap.height_d
+                ),
+            );
+            line(
+                out,
+                depth,
+                &format!(
+                    "offset: {}/{} ; {}/{}",
+                    clap.horiz_off_n, clap.horiz_off_d, clap.vert_off_n, clap.vert_off_d
+                ),
+            );
+        }
+        mpeg::IPMA => {
+            for entry in iprp::parse_ipma(data, budget)? {
+                line(
+                    out,
+                    depth,
+                    &format!("associations for item ID: {}", entry.item_id),
+                );
+                for association in &entry.associations {
+                    line(
+                        out,
+                        depth + 1,
+                        &format!(
+                            "property index: {} (essential: {})",
+                            association.property_index, association.essential
+                        ),
+                    );
+                }
+            }
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn dump_hvcc(out: &mut String, hvcc: &iprp::Hvcc, depth: usize) {
+    let header = hvcc.header();
+
+    // most significant first, in nibbles
+    let mut compatibility = String::new();
+    for i in 0..32 {
+        let bit = (header.general_profile_compatibility_flags() >> (31 - i)) & 1;
+        compatibility.push_str(&bit.to_string());
+        if 7 == i % 8 {
+            compatibility.push(' ');
+        } else if 3 == i % 4 {
+            compatibility.push('.');
+        }
+    }
+
+    let mut constraints = String::new();
+    for i in 0..48 {
+        let bit = (header.general_constraint_indicator_flags() >> (47 - i)) & 1;
+        constraints.push_str(&bit.to_string());
+        if 7 == i % 8 {
+            constraints.push(' ');
+        }
+    }
+
+    for &(name, ref value) in &[
+        (
+            "configuration_version",
+            header.configuration_version().to_string(),
+        ),
+        (
+            "general_profile_space",
+            header.general_profile_space().to_string(),
+        ),
+        (
+            "general_tier_flag",
+            u8::from(header.general_tier_flag()).to_string(),
+        ),
+        (
+            "general_profile_idc",
+            header.general_profile_idc().to_string(),
+        ),
+        ("general_profile_compatibility_flags", compatibility),
+        ("general_constraint_indicator_flags", constraints),
+        ("general_level_idc", header.general_level_idc().to_string()),
+        (
+            "min_spatial_segmentation_idc",
+            header.min_spatial_segmentation_idc().to_string(),
+        ),
+        ("parallelism_type", header.parallelism_type().to_string()),
+        ("chroma_format", header.chroma_format().to_string()),
+        ("bit_depth_luma", header.bit_depth_luma().to_string()),
+        ("bit_depth_chroma", header.bit_depth_chroma().to_string()),
+        ("avg_frame_rate", header.avg_frame_rate().to_string()),
+        (
+            "constant_frame_rate",
+            header.constant_frame_rate().to_string(),
+        ),
+        (
+            "num_temporal_layers",
+            header.num_temporal_layers().to_string(),
+        ),
+        (
+            "temporal_id_nested",
+            u8::from(header.temporal_id_nested()).to_string(),
+        ),
+        ("length_size", hvcc.length_size().to_string()),
+    ] {
+        line(out, depth, &format!("{}: {}", name, value));
+    }
+
+    for nal in &hvcc.nals {
+        line(out, depth, "<array>");
+        let completeness = nal.completeness_and_nal_unit_type >> 7;
+        let unit_type = nal.completeness_and_nal_unit_type & 0b11_1111;
+        line(
+            out,
+            depth + 1,
+            &format!("array_completeness: {}", completeness),
+        );
+        line(out, depth + 1, &format!("NAL_unit_type: {}", unit_type));
+        for unit in &nal.units {
+            line(out, depth + 1, &hex(unit));
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
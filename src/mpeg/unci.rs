@@ -0,0 +1,332 @@
+use std::io::Read;
+use std::io::Take;
+
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+use cast::u32;
+use cast::u64;
+use failure::Error;
+
+use error;
+use limits::Budget;
+use mpeg::meta::read_string;
+use mpeg::read_full_box_header;
+use mpeg::FourCc;
+
+pub const COMPONENT_MONOCHROME: u16 = 0;
+pub const COMPONENT_Y: u16 = 1;
+pub const COMPONENT_CB: u16 = 2;
+pub const COMPONENT_CR: u16 = 3;
+pub const COMPONENT_RED: u16 = 4;
+pub const COMPONENT_GREEN: u16 = 5;
+pub const COMPONENT_BLUE: u16 = 6;
+pub const COMPONENT_ALPHA: u16 = 7;
+
+pub const SAMPLING_NONE: u8 = 0;
+pub const SAMPLING_422: u8 = 1;
+pub const SAMPLING_420: u8 = 2;
+pub const SAMPLING_411: u8 = 3;
+
+pub const INTERLEAVE_COMPONENT: u8 = 0;
+pub const INTERLEAVE_PIXEL: u8 = 1;
+pub const INTERLEAVE_MIXED: u8 = 2;
+pub const INTERLEAVE_ROW: u8 = 3;
+pub const INTERLEAVE_TILE_COMPONENT: u8 = 4;
+pub const INTERLEAVE_MULTI_Y: u8 = 5;
+
+pub const FORMAT_UNSIGNED: u8 = 0;
+
+pub const RGB3: FourCc = FourCc(0x72676233); // rgb3
+pub const RGBA: FourCc = FourCc(0x72676261); // rgba
+pub const ABGR: FourCc = FourCc(0x61626772); // abgr
+
+// what each component of an uncompressed image is
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Cmpd {
+    pub components: Vec<ComponentDefinition>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ComponentDefinition {
+    pub component_type: u16,
+    // only for the user defined types, from 0x8000
+    pub component_type_uri: Option<String>,
+}
+
+// how the components of an uncompressed image are laid out
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Uncc {
+    pub version: u8,
+    pub profile: FourCc,
+    pub components: Vec<UnccComponent>,
+    pub sampling_type: u8,
+    pub interleave_type: u8,
+    pub block_size: u8,
+    pub components_little_endian: bool,
+    pub block_pad_lsb: bool,
+    pub block_little_endian: bool,
+    pub block_reversed: bool,
+    pub pad_unknown: bool,
+    pub pixel_size: u32,
+    pub row_align_size: u32,
+    pub tile_align_size: u32,
+    pub num_tile_cols_minus_one: u32,
+    pub num_tile_rows_minus_one: u32,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct UnccComponent {
+    // into the cmpd
+    pub component_index: u16,
+    pub component_bit_depth_minus_one: u8,
+    pub component_format: u8,
+    pub component_align_size: u8,
+}
+
+pub fn parse_cmpd<R: Read>(from: &mut Take<R>, budget: &mut Budget) -> Result<Cmpd, Error> {
+    let component_count = from.read_u32::<BE>()?;
+    let mut components = budget.vec(u64(component_count))?;
+    for _ in 0..component_count {
+        let component_type = from.read_u16::<BE>()?;
+        let component_type_uri = if component_type >= 0x8000 {
+            Some(read_string(from)?)
+        } else {
+            None
+        };
+        components.push(ComponentDefinition {
+            component_type,
+            component_type_uri,
+        });
+    }
+
+    Ok(Cmpd { components })
+}
+
+pub fn parse_uncc<R: Read>(mut from: &mut Take<R>, budget: &mut Budget) -> Result<Uncc, Error> {
+    let extended = read_full_box_header(&mut from)?;
+    let profile = FourCc(from.read_u32::<BE>()?);
+
+    match extended.version {
+        0 => (),
+        1 => return Uncc::from_profile(profile),
+        other => {
+            return Err(error::unsupported(format!(
+                "unsupported uncC version: {}",
+                other
+            )))
+        }
+    }
+
+    let component_count = from.read_u32::<BE>()?;
+    let mut components = budget.vec(u64(component_count))?;
+    for _ in 0..component_count {
+        components.push(UnccComponent {
+            component_index: from.read_u16::<BE>()?,
+            component_bit_depth_minus_one: from.read_u8()?,
+            component_format: from.read_u8()?,
+            component_align_size: from.read_u8()?,
+        });
+    }
+
+    let sampling_type = from.read_u8()?;
+    let interleave_type = from.read_u8()?;
+    let block_size = from.read_u8()?;
+    let flags = from.read_u8()?;
+
+    Ok(Uncc {
+        version: 0,
+        profile,
+        components,
+        sampling_type,
+        interleave_type,
+        block_size,
+        components_little_endian: 0 != flags & 0x80,
+        block_pad_lsb: 0 != flags & 0x40,
+        block_little_endian: 0 != flags & 0x20,
+        block_reversed: 0 != flags & 0x10,
+        pad_unknown: 0 != flags & 0x08,
+        pixel_size: from.read_u32::<BE>()?,
+        row_align_size: from.read_u32::<BE>()?,
+        tile_align_size: from.read_u32::<BE>()?,
+        num_tile_cols_minus_one: from.read_u32::<BE>()?,
+        num_tile_rows_minus_one: from.read_u32::<BE>()?,
+    })
+}
+
+// the inverse of `parse_cmpd`
+pub fn write_cmpd(cmpd: &Cmpd, into: &mut Vec<u8>) -> Result<(), Error> {
+    into.write_u32::<BE>(u32(cmpd.components.len())?)?;
+    for component in &cmpd.components {
+        into.write_u16::<BE>(component.component_type)?;
+        if let Some(ref uri) = component.component_type_uri {
+            into.extend_from_slice(uri.as_bytes());
+            into.push(0);
+        }
+    }
+    Ok(())
+}
+
+// the inverse of `parse_uncc`, less the full box header
+pub fn write_uncc(uncc: &Uncc, into: &mut Vec<u8>) -> Result<(), Error> {
+    into.write_u32::<BE>(uncc.profile.0)?;
+    if 1 == uncc.version {
+        return Ok(());
+    }
+
+    into.write_u32::<BE>(u32(uncc.components.len())?)?;
+    for component in &uncc.components {
+        into.write_u16::<BE>(component.component_index)?;
+        into.write_u8(component.component_bit_depth_minus_one)?;
+        into.write_u8(component.component_format)?;
+        into.write_u8(component.component_align_size)?;
+    }
+
+    into.write_u8(uncc.sampling_type)?;
+    into.write_u8(uncc.interleave_type)?;
+    into.write_u8(uncc.block_size)?;
+    into.write_u8(
+        (u8::from(uncc.components_little_endian) << 7)
+            | (u8::from(uncc.block_pad_lsb) << 6)
+            | (u8::from(uncc.block_little_endian) << 5)
+            | (u8::from(uncc.block_reversed) << 4)
+            | (u8::from(uncc.pad_unknown) << 3),
+    )?;
+    into.write_u32::<BE>(uncc.pixel_size)?;
+    into.write_u32::<BE>(uncc.row_align_size)?;
+    into.write_u32::<BE>(uncc.tile_align_size)?;
+    into.write_u32::<BE>(uncc.num_tile_cols_minus_one)?;
+    into.write_u32::<BE>(uncc.num_tile_rows_minus_one)?;
+    Ok(())
+}
+
+impl Uncc {
+    // version 1 only names a profile, which stands for a whole configuration
+    pub fn from_profile(profile: FourCc) -> Result<Uncc, Error> {
+        let component_count = match profile {
+            RGB3 => 3,
+            RGBA | ABGR => 4,
+            other => {
+                return Err(error::unsupported(format!(
+                    "unsupported uncC profile: {:?}",
+                    other
+                )))
+            }
+        };
+
+        Ok(Uncc {
+            version: 1,
+            profile,
+            components: (0..component_count)
+                .map(|component_index| UnccComponent {
+                    component_index,
+                    component_bit_depth_minus_one: 7,
+                    component_format: FORMAT_UNSIGNED,
+                    component_align_size: 0,
+                })
+                .collect(),
+            sampling_type: SAMPLING_NONE,
+            interleave_type: INTERLEAVE_PIXEL,
+            block_size: 0,
+            components_little_endian: false,
+            block_pad_lsb: false,
+            block_little_endian: false,
+            block_reversed: false,
+            pad_unknown: false,
+            pixel_size: 0,
+            row_align_size: 0,
+            tile_align_size: 0,
+            num_tile_cols_minus_one: 0,
+            num_tile_rows_minus_one: 0,
+        })
+    }
+
+    // the component definitions a profile implies, as a version 1 uncC may come without a cmpd
+    pub fn implied_cmpd(&self) -> Option<Cmpd> {
+        let types: &[u16] = match self.profile {
+            RGB3 => &[COMPONENT_RED, COMPONENT_GREEN, COMPONENT_BLUE],
+            RGBA => &[
+                COMPONENT_RED,
+                COMPONENT_GREEN,
+                COMPONENT_BLUE,
+                COMPONENT_ALPHA,
+            ],
+            ABGR => &[
+                COMPONENT_ALPHA,
+                COMPONENT_BLUE,
+                COMPONENT_GREEN,
+                COMPONENT_RED,
+            ],
+            _ => return None,
+        };
+
+        Some(Cmpd {
+            components: types
+                .iter()
+                .map(|&component_type| ComponentDefinition {
+                    component_type,
+                    component_type_uri: None,
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::io::Read;
+
+    use limits::Budget;
+
+    use super::parse_cmpd;
+    use super::parse_uncc;
+    use super::write_cmpd;
+    use super::write_uncc;
+
+    #[test]
+    fn round_trip() {
+        let cmpd = [
+            0, 0, 0, 2, // two components
+            0, 1, // y
+            0x80, 0x00, b'x', b':', b'z', 0, // user defined
+        ];
+        let parsed =
+            parse_cmpd(&mut Cursor::new(&cmpd[..]).take(12), &mut Budget::default()).unwrap();
+        assert_eq!(2, parsed.components.len());
+        assert_eq!(
+            Some("x:z"),
+            parsed.components[1].component_type_uri.as_deref()
+        );
+        let mut written = Vec::new();
+        write_cmpd(&parsed, &mut written).unwrap();
+        assert_eq!(&cmpd[..], written.as_slice());
+
+        let uncc = [
+            0, 0, 0, 0, // version, flags
+            0, 0, 0, 0, // profile
+            0, 0, 0, 1, // one component
+            0, 0, 9, 0, 2, // index, ten bits, unsigned, two byte aligned
+            0, 1, 0, 0x80, // no subsampling, pixel interleave, no blocks, little endian
+            0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0,
+        ];
+        let parsed =
+            parse_uncc(&mut Cursor::new(&uncc[..]).take(41), &mut Budget::default()).unwrap();
+        assert!(parsed.components_little_endian);
+        assert_eq!(4, parsed.row_align_size);
+        assert_eq!(1, parsed.num_tile_cols_minus_one);
+        let mut written = Vec::new();
+        write_uncc(&parsed, &mut written).unwrap();
+        assert_eq!(&uncc[4..], written.as_slice());
+
+        let rgba = [1, 0, 0, 0, b'r', b'g', b'b', b'a'];
+        let parsed =
+            parse_uncc(&mut Cursor::new(&rgba[..]).take(8), &mut Budget::default()).unwrap();
+        assert_eq!(4, parsed.components.len());
+        assert_eq!(4, parsed.implied_cmpd().unwrap().components.len());
+    }
+}
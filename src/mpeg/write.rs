@@ -0,0 +1,488 @@
+use std::io::Write;
+
+use byteorder::ByteOrder;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+use cast::u16;
+use cast::u32;
+use cast::u8;
+use failure::Error;
+
+use mpeg;
+use mpeg::iprp;
+use mpeg::iprp::ItemPropertyAssociation;
+use mpeg::iprp::Property;
+use mpeg::unci;
+use mpeg::EntityGroup;
+use mpeg::FileType;
+use mpeg::FourCc;
+use mpeg::ItemInfo;
+use mpeg::ItemLoc;
+use mpeg::ItemReference;
+use mpeg::RawBox;
+
+// Boxes are built in memory, so the size can be filled in once the body is known.
+// Everything but 'mdat' is small, and 'mdat' is written by the caller.
+pub fn write_box<F>(into: &mut Vec<u8>, box_type: FourCc, body: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+{
+    let start = into.len();
+    into.write_u32::<BE>(0)?;
+    into.write_u32::<BE>(box_type.0)?;
+    body(into)?;
+
+    let size = u32(into.len() - start)
+        .map_err(|_| format_err!("{:?} box is too large to write", box_type))?;
+    BE::write_u32(&mut into[start..start + 4], size);
+    Ok(())
+}
+
+pub fn write_full_box<F>(
+    into: &mut Vec<u8>,
+    box_type: FourCc,
+    version: u8,
+    flags: u32,
+    body: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+{
+    ensure!(flags <= 0x00ff_ffff, "flags out of range: {:x}", flags);
+    write_box(into, box_type, |into| {
+        into.write_u32::<BE>((u32(version) << 24) | flags)?;
+        body(into)
+    })
+}
+
+// just the header, for boxes which are streamed out after it, i.e. 'mdat'
+pub fn write_box_header<W: Write>(
+    mut into: W,
+    box_type: FourCc,
+    data_size: u64,
+) -> Result<(), Error> {
+    match u32(data_size + 8) {
+        Ok(size) => {
+            into.write_u32::<BE>(size)?;
+            into.write_u32::<BE>(box_type.0)?;
+        }
+        Err(_) => {
+            into.write_u32::<BE>(1)?;
+            into.write_u32::<BE>(box_type.0)?;
+            into.write_u64::<BE>(data_size + 16)?;
+        }
+    }
+
+    Ok(())
+}
+
+// how big `write_box_header` will make the header
+pub fn box_header_size(data_size: u64) -> u64 {
+    if u32(data_size + 8).is_ok() {
+        8
+    } else {
+        16
+    }
+}
+
+pub fn write_ftyp(into: &mut Vec<u8>, file_type: &FileType) -> Result<(), Error> {
+    write_box(into, mpeg::FTYP, |into| {
+        into.write_u32::<BE>(file_type.major_brand.0)?;
+        into.write_u32::<BE>(file_type.minor_version)?;
+        for brand in &file_type.brands {
+            into.write_u32::<BE>(brand.0)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn write_hdlr(into: &mut Vec<u8>, handler: FourCc) -> Result<(), Error> {
+    write_full_box(into, mpeg::HDLR, 0, 0, |into| {
+        into.write_u32::<BE>(0)?;
+        into.write_u32::<BE>(handler.0)?;
+        into.write_all(&[0u8; 12])?;
+        // empty name
+        into.write_u8(0)?;
+        Ok(())
+    })
+}
+
+pub fn write_pitm(into: &mut Vec<u8>, item: u32) -> Result<(), Error> {
+    match u16(item) {
+        Ok(short) => write_full_box(into, mpeg::PITM, 0, 0, |into| {
+            Ok(into.write_u16::<BE>(short)?)
+        }),
+        Err(_) => write_full_box(into, mpeg::PITM, 1, 0, |into| {
+            Ok(into.write_u32::<BE>(item)?)
+        }),
+    }
+}
+
+// offsets and lengths are written with four bytes unless they need eight
+pub fn write_iloc(into: &mut Vec<u8>, items: &[ItemLoc]) -> Result<(), Error> {
+    let wide_ids = items.iter().any(|item| u16(item.id).is_err());
+    let constructed = items.iter().any(|item| 0 != item.construction_method);
+    let version = if wide_ids {
+        2
+    } else if constructed {
+        1
+    } else {
+        0
+    };
+
+    let size_for = |value: u64| if u32(value).is_ok() { 4 } else { 8 };
+    let extents = || items.iter().flat_map(|item| item.extents.iter());
+    let offset_size = extents().map(|e| size_for(e.offset)).max().unwrap_or(4);
+    let length_size = extents().map(|e| size_for(e.length)).max().unwrap_or(4);
+    let base_offset_size = items
+        .iter()
+        .map(|item| size_for(item.base_offset))
+        .max()
+        .unwrap_or(4);
+
+    write_full_box(into, mpeg::ILOC, version, 0, |into| {
+        into.write_u8((offset_size << 4) | length_size)?;
+        into.write_u8(base_offset_size << 4)?;
+        if wide_ids {
+            into.write_u32::<BE>(u32(items.len())?)?;
+        } else {
+            into.write_u16::<BE>(u16(items.len())?)?;
+        }
+
+        for item in items {
+            if wide_ids {
+                into.write_u32::<BE>(item.id)?;
+            } else {
+                into.write_u16::<BE>(u16(item.id)?)?;
+            }
+
+            if version > 0 {
+                ensure!(
+                    item.construction_method <= 2,
+                    "invalid construction method: {}",
+                    item.construction_method
+                );
+                into.write_u16::<BE>(u16(item.construction_method))?;
+            }
+
+            into.write_u16::<BE>(item.data_reference_index)?;
+            write_value_of_size(into, item.base_offset, base_offset_size)?;
+            into.write_u16::<BE>(u16(item.extents.len())?)?;
+            for extent in &item.extents {
+                ensure!(0 == extent.index, "extent indexes are not supported");
+                write_value_of_size(into, extent.offset, offset_size)?;
+                write_value_of_size(into, extent.length, length_size)?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn write_value_of_size(into: &mut Vec<u8>, value: u64, bytes: u8) -> Result<(), Error> {
+    match bytes {
+        4 => into.write_u32::<BE>(u32(value)?)?,
+        8 => into.write_u64::<BE>(value)?,
+        other => bail!("unsupported size: {}", other),
+    }
+    Ok(())
+}
+
+pub fn write_iinf(into: &mut Vec<u8>, items: &[ItemInfo]) -> Result<(), Error> {
+    let short = u16(items.len());
+    let version = if short.is_ok() { 0 } else { 1 };
+    write_full_box(into, mpeg::IINF, version, 0, |into| {
+        match short {
+            Ok(count) => into.write_u16::<BE>(count)?,
+            Err(_) => into.write_u32::<BE>(u32(items.len())?)?,
+        }
+
+        for item in items {
+            write_infe(into, item)?;
+        }
+
+        Ok(())
+    })
+}
+
+// always version 2 or 3, which have the item type
+pub fn write_infe(into: &mut Vec<u8>, item: &ItemInfo) -> Result<(), Error> {
+    let short_id = u16(item.id);
+    let version = if short_id.is_ok() { 2 } else { 3 };
+    let flags = if item.hidden { 1 } else { 0 };
+
+    write_full_box(into, mpeg::INFE, version, flags, |into| {
+        match short_id {
+            Ok(id) => into.write_u16::<BE>(id)?,
+            Err(_) => into.write_u32::<BE>(item.id)?,
+        }
+        into.write_u16::<BE>(item.protection_index)?;
+        into.write_u32::<BE>(item.item_type.0)?;
+        write_string(into, &item.item_name)?;
+
+        match item.item_type {
+            mpeg::MIME => {
+                write_string(into, item.content_type.as_ref().map_or("", |s| s.as_str()))?;
+                if let Some(ref encoding) = item.content_encoding {
+                    write_string(into, encoding)?;
+                }
+            }
+            mpeg::URI => {
+                write_string(into, item.item_uri_type.as_ref().map_or("", |s| s.as_str()))?
+            }
+            _ => (),
+        }
+
+        Ok(())
+    })
+}
+
+fn write_string(into: &mut Vec<u8>, value: &str) -> Result<(), Error> {
+    ensure!(!value.contains('\0'), "strings can't contain nulls");
+    into.write_all(value.as_bytes())?;
+    into.write_u8(0)?;
+    Ok(())
+}
+
+pub fn write_iref(into: &mut Vec<u8>, references: &[ItemReference]) -> Result<(), Error> {
+    let wide_ids = references.iter().any(|reference| {
+        u16(reference.from_item_id).is_err()
+            || reference.to_item_ids.iter().any(|&id| u16(id).is_err())
+    });
+    let version = if wide_ids { 1 } else { 0 };
+
+    let write_id = |into: &mut Vec<u8>, id: u32| -> Result<(), Error> {
+        if wide_ids {
+            into.write_u32::<BE>(id)?;
+        } else {
+            into.write_u16::<BE>(u16(id)?)?;
+        }
+        Ok(())
+    };
+
+    write_full_box(into, mpeg::IREF, version, 0, |into| {
+        for reference in references {
+            write_box(into, reference.reference_type, |into| {
+                write_id(into, reference.from_item_id)?;
+                into.write_u16::<BE>(u16(reference.to_item_ids.len())?)?;
+                for &id in &reference.to_item_ids {
+                    write_id(into, id)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    })
+}
+
+pub fn write_grpl(into: &mut Vec<u8>, groups: &[EntityGroup]) -> Result<(), Error> {
+    write_box(into, mpeg::GRPL, |into| {
+        for group in groups {
+            write_full_box(into, group.grouping_type, 0, 0, |into| {
+                into.write_u32::<BE>(group.group_id)?;
+                into.write_u32::<BE>(u32(group.entity_ids.len())?)?;
+                for &id in &group.entity_ids {
+                    into.write_u32::<BE>(id)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    })
+}
+
+pub fn write_raw(into: &mut Vec<u8>, raw: &RawBox) -> Result<(), Error> {
+    write_box(into, raw.box_type, |into| Ok(into.write_all(&raw.data)?))
+}
+
+pub fn write_iprp(
+    into: &mut Vec<u8>,
+    properties: &[Property],
+    associations: &[ItemPropertyAssociation],
+) -> Result<(), Error> {
+    write_box(into, mpeg::IPRP, |into| {
+        write_box(into, mpeg::IPCO, |into| {
+            for property in properties {
+                write_property(into, property)?;
+            }
+            Ok(())
+        })?;
+        write_ipma(into, associations)
+    })
+}
+
+pub fn write_property(into: &mut Vec<u8>, property: &Property) -> Result<(), Error> {
+    match property {
+        Property::HvcCodecSettings(hvcc) => {
+            write_box(into, mpeg::HVCC, |into| iprp::write_hvcc(hvcc, into))
+        }
+        Property::Av1CodecSettings(av1c) => {
+            write_box(into, mpeg::AV1C, |into| iprp::write_av1c(av1c, into))
+        }
+        Property::JpegPrefix(jpgc) => write_box(into, mpeg::JPGC, |into| {
+            into.extend_from_slice(&jpgc.prefix);
+            Ok(())
+        }),
+        Property::ComponentDefinition(cmpd) => {
+            write_box(into, mpeg::CMPD, |into| unci::write_cmpd(cmpd, into))
+        }
+        Property::UncompressedConfig(uncc) => {
+            write_full_box(into, mpeg::UNCC, uncc.version, 0, |into| {
+                unci::write_uncc(uncc, into)
+            })
+        }
+        Property::Size(ispe) => write_full_box(into, mpeg::ISPE, 0, 0, |into| {
+            into.write_u32::<BE>(ispe.width)?;
+            into.write_u32::<BE>(ispe.height)?;
+            Ok(())
+        }),
+        Property::Rotation(irot) => write_box(into, mpeg::IROT, |into| {
+            into.write_u8(irot.angle & 0b11)?;
+            Ok(())
+        }),
+        Property::Mirror(imir) => write_box(into, mpeg::IMIR, |into| {
+            into.write_u8(imir.axis & 0b1)?;
+            Ok(())
+        }),
+        Property::CleanAperture(clap) => write_box(into, mpeg::CLAP, |into| {
+            into.write_u32::<BE>(clap.width_n)?;
+            into.write_u32::<BE>(clap.width_d)?;
+            into.write_u32::<BE>(clap.height_n)?;
+            into.write_u32::<BE>(clap.height_d)?;
+            into.write_i32::<BE>(clap.horiz_off_n)?;
+            into.write_u32::<BE>(clap.horiz_off_d)?;
+            into.write_i32::<BE>(clap.vert_off_n)?;
+            into.write_u32::<BE>(clap.vert_off_d)?;
+            Ok(())
+        }),
+        Property::Unknown(raw) => write_raw(into, raw),
+    }
+}
+
+pub fn write_ipma(
+    into: &mut Vec<u8>,
+    associations: &[ItemPropertyAssociation],
+) -> Result<(), Error> {
+    let wide_ids = associations.iter().any(|item| u16(item.item_id).is_err());
+    let wide_indexes = associations
+        .iter()
+        .flat_map(|item| item.associations.iter())
+        .any(|assoc| assoc.property_index > 0x7f);
+
+    let version = if wide_ids { 1 } else { 0 };
+    let flags = if wide_indexes { 1 } else { 0 };
+
+    write_full_box(into, mpeg::IPMA, version, flags, |into| {
+        into.write_u32::<BE>(u32(associations.len())?)?;
+        for item in associations {
+            if wide_ids {
+                into.write_u32::<BE>(item.item_id)?;
+            } else {
+                into.write_u16::<BE>(u16(item.item_id)?)?;
+            }
+
+            into.write_u8(u8(item.associations.len())?)?;
+            for assoc in &item.associations {
+                if wide_indexes {
+                    ensure!(
+                        assoc.property_index <= 0x7fff,
+                        "property index out of range: {}",
+                        assoc.property_index
+                    );
+                    let essential = if assoc.essential { 0x8000 } else { 0 };
+                    into.write_u16::<BE>(essential | assoc.property_index)?;
+                } else {
+                    let essential = if assoc.essential { 0x80 } else { 0 };
+                    into.write_u8(essential | u8(assoc.property_index)?)?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::io::Read;
+
+    use cast::u64;
+
+    use limits::Budget;
+    use mpeg;
+    use mpeg::meta;
+    use mpeg::Extent;
+    use mpeg::ItemInfo;
+    use mpeg::ItemLoc;
+
+    #[test]
+    fn iloc_round_trip() {
+        let items = vec![
+            ItemLoc {
+                id: 1,
+                construction_method: 0,
+                data_reference_index: 0,
+                base_offset: 333,
+                extents: vec![Extent {
+                    index: 0,
+                    offset: 0,
+                    length: 1048,
+                }],
+            },
+            ItemLoc {
+                id: 70_000,
+                construction_method: 1,
+                data_reference_index: 0,
+                base_offset: 0,
+                extents: vec![Extent {
+                    index: 0,
+                    offset: 1 << 40,
+                    length: 7,
+                }],
+            },
+        ];
+
+        let mut buf = Vec::new();
+        super::write_iloc(&mut buf, &items).expect("writing");
+
+        let parsed = meta::parse_iloc(
+            &mut Cursor::new(&buf[8..]).take(u64(buf.len() - 8)),
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+        assert_eq!(2, parsed.len());
+        assert_eq!(333, parsed[0].base_offset);
+        assert_eq!(1048, parsed[0].extents[0].length);
+        assert_eq!(70_000, parsed[1].id);
+        assert_eq!(1, parsed[1].construction_method);
+        assert_eq!(1 << 40, parsed[1].extents[0].offset);
+    }
+
+    #[test]
+    fn iinf_round_trip() {
+        let items = vec![ItemInfo {
+            id: 2,
+            protection_index: 0,
+            item_type: mpeg::MIME,
+            item_name: "XMP".to_string(),
+            content_type: Some("application/rdf+xml".to_string()),
+            content_encoding: None,
+            item_uri_type: None,
+            hidden: true,
+        }];
+
+        let mut buf = Vec::new();
+        super::write_iinf(&mut buf, &items).expect("writing");
+
+        let parsed = meta::parse_iinf(
+            &mut Cursor::new(&buf[8..]).take(u64(buf.len() - 8)),
+            &mut Budget::default(),
+        )
+        .expect("parsing");
+        assert_eq!(1, parsed.len());
+        assert_eq!("XMP", parsed[0].item_name);
+        assert_eq!(items[0].content_type, parsed[0].content_type);
+        assert!(parsed[0].hidden);
+    }
+}
@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::SeekFrom;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use byteorder::ByteOrder;
+use byteorder::BE;
+use cast::i64;
+use cast::u64;
+use cast::usize;
+use failure::Error;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncSeek;
+use tokio::io::ReadBuf;
+
+use error;
+use error::ParseError;
+use file::Heif;
+use limits::Limits;
+use mpeg;
+use mpeg::meta::RawMeta;
+use mpeg::BoxHeader;
+use mpeg::Extent;
+use mpeg::FileType;
+use mpeg::MetaLoader;
+
+// Reading from tokio's `AsyncRead + AsyncSeek`. Only the I/O differs: the boxes we need are
+// read into memory, and parsed just as `Heif::new` would; the rest are seeked over.
+
+macro_rules! ready {
+    ($e:expr) => {
+        match $e {
+            Poll::Ready(value) => value,
+            Poll::Pending => return Poll::Pending,
+        }
+    };
+}
+
+impl Heif {
+    pub fn new_async<R: AsyncRead + AsyncSeek + Unpin>(from: R) -> LoadAsync<R> {
+        Heif::with_limits_async(from, Limits::default())
+    }
+
+    pub fn with_limits_async<R: AsyncRead + AsyncSeek + Unpin>(
+        from: R,
+        limits: Limits,
+    ) -> LoadAsync<R> {
+        LoadAsync {
+            from,
+            limits,
+            loader: MetaLoader::new(&limits),
+            state: State::Header {
+                buf: [0u8; 16],
+                filled: 0,
+            },
+        }
+    }
+
+    // as `open_item_data`
+    pub fn open_item_data_async<R: AsyncRead + AsyncSeek + Unpin>(
+        &self,
+        from: R,
+        item: u32,
+    ) -> Result<AsyncExtents<'_, R>, ParseError> {
+        let location = self.readable_location(item)?;
+
+        let inner = match location.construction_method {
+            0 => AsyncSource::File(from),
+            _ => AsyncSource::ItemData(Cursor::new(self.inline_item_data())),
+        };
+
+        let base = location.base_offset;
+        Ok(AsyncExtents {
+            inner,
+            base,
+            extents: &location.extents,
+            current_extent: 0,
+            current_pos: 0,
+            seek: Some((base + location.extents[0].offset, false)),
+        })
+    }
+}
+
+// The future from `Heif::new_async`.
+pub struct LoadAsync<R> {
+    from: R,
+    limits: Limits,
+    loader: MetaLoader,
+    state: State,
+}
+
+enum State {
+    // eight bytes, or sixteen for a large size
+    Header {
+        buf: [u8; 16],
+        filled: usize,
+    },
+    Data {
+        header: BoxHeader,
+        buf: Vec<u8>,
+        filled: usize,
+    },
+    Skip {
+        header: BoxHeader,
+        started: bool,
+    },
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> LoadAsync<R> {
+    fn poll_meta(&mut self, cx: &mut Context) -> Poll<Result<(FileType, RawMeta), Error>> {
+        loop {
+            let next = match self.state {
+                State::Header {
+                    ref mut buf,
+                    ref mut filled,
+                } => {
+                    ready!(poll_fill(&mut self.from, cx, &mut buf[..8], filled))?;
+                    let len = if 1 == BE::read_u32(&buf[..4]) { 16 } else { 8 };
+                    ready!(poll_fill(&mut self.from, cx, &mut buf[..len], filled))?;
+
+                    let header = mpeg::read_header(&buf[..len])?;
+                    if self.loader.wants(&header)? {
+                        State::Data {
+                            buf: vec![0u8; usize(header.data_size())],
+                            header,
+                            filled: 0,
+                        }
+                    } else {
+                        State::Skip {
+                            header,
+                            started: false,
+                        }
+                    }
+                }
+                State::Data {
+                    ref header,
+                    ref mut buf,
+                    ref mut filled,
+                } => {
+                    ready!(poll_fill(&mut self.from, cx, buf, filled))?;
+                    let mut data = Cursor::new(&buf[..]).take(header.data_size());
+                    if let Some(loaded) = self.loader.parse(header, &mut data)? {
+                        return Poll::Ready(Ok(loaded));
+                    }
+                    header_state()
+                }
+                State::Skip {
+                    ref header,
+                    ref mut started,
+                } => {
+                    if !*started {
+                        let by = SeekFrom::Current(i64(header.data_size())?);
+                        Pin::new(&mut self.from).start_seek(by)?;
+                        *started = true;
+                    }
+                    ready!(Pin::new(&mut self.from).poll_complete(cx))?;
+                    self.loader.skipped(header);
+                    header_state()
+                }
+            };
+            self.state = next;
+        }
+    }
+}
+
+fn header_state() -> State {
+    State::Header {
+        buf: [0u8; 16],
+        filled: 0,
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Future for LoadAsync<R> {
+    type Output = Result<Heif, ParseError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let loaded = ready!(self.poll_meta(cx));
+        let (file_type, raw) = error::at(loaded, self.loader.position())?;
+        Poll::Ready(Heif::from_raw(file_type, raw, self.limits))
+    }
+}
+
+// read until `buf[..*filled]` is all of `buf`, failing at the end of the file
+fn poll_fill<R: AsyncRead + Unpin>(
+    from: &mut R,
+    cx: &mut Context,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> Poll<io::Result<()>> {
+    while *filled < buf.len() {
+        let mut read = ReadBuf::new(&mut buf[*filled..]);
+        ready!(Pin::new(&mut *from).poll_read(cx, &mut read))?;
+        if read.filled().is_empty() {
+            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+        *filled += read.filled().len();
+    }
+    Poll::Ready(Ok(()))
+}
+
+// As `Extents`, for `AsyncRead`.
+pub struct AsyncExtents<'h, R> {
+    inner: AsyncSource<'h, R>,
+    base: u64,
+    extents: &'h [Extent],
+    current_extent: usize,
+    current_pos: u64,
+    // where to seek to before the next read, and whether that's been started
+    seek: Option<(u64, bool)>,
+}
+
+impl<'h, R: AsyncRead + AsyncSeek + Unpin> AsyncRead for AsyncExtents<'h, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        if let Some((pos, started)) = this.seek {
+            if !started {
+                this.inner.start_seek(pos)?;
+                this.seek = Some((pos, true));
+            }
+            ready!(this.inner.poll_complete(cx))?;
+            this.seek = None;
+        }
+
+        if 0 == buf.remaining() || this.extents.len() == this.current_extent {
+            return Poll::Ready(Ok(()));
+        }
+
+        // checked when the extents were opened: every length is non-zero, and every offset fits
+        let extents = this.extents;
+        let current = &extents[this.current_extent];
+        let to_read = usize((current.length - this.current_pos).min(u64(buf.remaining())));
+
+        let actually_read = {
+            let mut limited = ReadBuf::new(buf.initialize_unfilled_to(to_read));
+            ready!(this.inner.poll_read(cx, &mut limited))?;
+            limited.filled().len()
+        };
+        buf.advance(actually_read);
+
+        this.current_pos += u64(actually_read);
+
+        if this.current_pos == current.length {
+            this.current_extent += 1;
+            this.current_pos = 0;
+
+            if let Some(new_extent) = extents.get(this.current_extent) {
+                this.seek = Some((this.base + new_extent.offset, false));
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+enum AsyncSource<'h, R> {
+    File(R),
+    ItemData(Cursor<&'h [u8]>),
+}
+
+impl<'h, R: AsyncRead + AsyncSeek + Unpin> AsyncSource<'h, R> {
+    fn poll_read(&mut self, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        match self {
+            AsyncSource::File(from) => Pin::new(from).poll_read(cx, buf),
+            AsyncSource::ItemData(from) => Pin::new(from).poll_read(cx, buf),
+        }
+    }
+
+    fn start_seek(&mut self, pos: u64) -> io::Result<()> {
+        match self {
+            AsyncSource::File(from) => Pin::new(from).start_seek(SeekFrom::Start(pos)),
+            AsyncSource::ItemData(from) => Pin::new(from).start_seek(SeekFrom::Start(pos)),
+        }
+    }
+
+    fn poll_complete(&mut self, cx: &mut Context) -> Poll<io::Result<u64>> {
+        match self {
+            AsyncSource::File(from) => Pin::new(from).poll_complete(cx),
+            AsyncSource::ItemData(from) => Pin::new(from).poll_complete(cx),
+        }
+    }
+}
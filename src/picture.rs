@@ -0,0 +1,49 @@
+use cast::usize;
+
+// Decoded samples, one plane per component, in the order the file lists them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Picture {
+    pub width: u32,
+    pub height: u32,
+    pub planes: Vec<Plane>,
+}
+
+// row-major, with no padding; subsampled planes are smaller than the picture
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Plane {
+    // as in 'cmpd', e.g. 1 for luma, 4 for red
+    pub component_type: u16,
+    pub bit_depth: u8,
+    pub width: u32,
+    pub height: u32,
+    pub samples: Vec<u16>,
+}
+
+impl Picture {
+    pub fn plane(&self, component_type: u16) -> Option<&Plane> {
+        self.planes
+            .iter()
+            .find(|plane| plane.component_type == component_type)
+    }
+}
+
+impl Plane {
+    pub fn new(component_type: u16, bit_depth: u8, width: u32, height: u32) -> Plane {
+        Plane {
+            component_type,
+            bit_depth,
+            width,
+            height,
+            samples: vec![0; usize(width) * usize(height)],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.samples[usize(y) * usize(self.width) + usize(x)]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: u16) {
+        let width = usize(self.width);
+        self.samples[usize(y) * width + usize(x)] = value;
+    }
+}
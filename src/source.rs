@@ -0,0 +1,230 @@
+use std::cmp;
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use cast::u64;
+use cast::usize;
+use failure::Error;
+
+use error;
+use error::ParseError;
+use file::Heif;
+use limits::Limits;
+use mpeg;
+use mpeg::meta::RawMeta;
+use mpeg::FileType;
+use mpeg::MetaLoader;
+
+// Somewhere bytes can be fetched from by range, like object storage, where each request
+// costs more than the bytes it returns.
+pub trait ByteSource {
+    // `len` bytes from `offset`, or fewer if the source ends first
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, Error>;
+}
+
+impl ByteSource for File {
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        self.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        (&mut *self).take(len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl ByteSource for &[u8] {
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        Ok(slice_at(self, offset, len).to_vec())
+    }
+}
+
+impl ByteSource for Vec<u8> {
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        Ok(slice_at(self, offset, len).to_vec())
+    }
+}
+
+fn slice_at(bytes: &[u8], offset: u64, len: u64) -> &[u8] {
+    let available = u64(bytes.len());
+    let start = cmp::min(offset, available);
+    let end = cmp::min(start.saturating_add(len), available);
+    &bytes[usize(start)..usize(end)]
+}
+
+// the least we fetch while looking for the boxes we want, so that the box headers, and
+// usually the boxes themselves, come in one request
+const READ_AHEAD: u64 = 16 * 1024;
+
+// extents closer than this are fetched together, as the gap is cheaper than another request
+const COALESCE_GAP: u64 = 16 * 1024;
+
+// the last range fetched, which later reads are served from where they can be
+struct Window {
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl Window {
+    fn read<S: ByteSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+        offset: u64,
+        len: u64,
+    ) -> Result<&[u8], Error> {
+        let end = offset.saturating_add(len);
+        let covered = offset >= self.start && end <= self.start + u64(self.data.len());
+        if !covered {
+            self.data = source.read_at(offset, cmp::max(len, READ_AHEAD))?;
+            self.start = offset;
+        }
+
+        let data = slice_at(&self.data, offset - self.start, len);
+        if u64(data.len()) != len {
+            return Err(error::truncated(format!(
+                "wanted {} bytes at {}, but the source ends after {}",
+                len,
+                offset,
+                data.len()
+            )));
+        }
+        Ok(data)
+    }
+}
+
+impl Heif {
+    // as `new`, fetching only the boxes that are needed, and not any item data
+    pub fn from_source<S: ByteSource + ?Sized>(source: &mut S) -> Result<Heif, ParseError> {
+        Heif::from_source_with_limits(source, Limits::default())
+    }
+
+    pub fn from_source_with_limits<S: ByteSource + ?Sized>(
+        source: &mut S,
+        limits: Limits,
+    ) -> Result<Heif, ParseError> {
+        let mut loader = MetaLoader::new(&limits);
+        let loaded = fetch_meta(source, &mut loader);
+        let (file_type, raw) = error::at(loaded, loader.position())?;
+        Heif::from_raw(file_type, raw, limits)
+    }
+
+    // the whole of the item's data, as stored, fetching nearby extents in one request
+    pub fn read_item_data_from<S: ByteSource + ?Sized>(
+        &self,
+        source: &mut S,
+        item: u32,
+    ) -> Result<Vec<u8>, ParseError> {
+        Ok(self.fetch_item_data(source, item)?)
+    }
+
+    fn fetch_item_data<S: ByteSource + ?Sized>(
+        &self,
+        source: &mut S,
+        item: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let location = self.readable_location(item)?;
+        let total = location
+            .extents
+            .iter()
+            .fold(0u64, |total, extent| total.saturating_add(extent.length));
+        self.limits().check_allocation(total)?;
+
+        if 1 == location.construction_method {
+            let item_data = self.inline_item_data();
+            let mut data = Vec::with_capacity(usize(total));
+            for extent in &location.extents {
+                data.extend_from_slice(slice_at(item_data, extent.offset, extent.length));
+            }
+            ensure!(u64(data.len()) == total, "item data runs past the 'idat'");
+            return Ok(data);
+        }
+
+        // the absolute ranges, in file order, each merged with any others that are close by
+        let base = location.base_offset;
+        let mut ranges: Vec<(u64, u64)> = location
+            .extents
+            .iter()
+            .map(|extent| (base + extent.offset, extent.length))
+            .collect();
+        ranges.sort();
+
+        let mut requests: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (start, len) in ranges {
+            let end = start.saturating_add(len);
+            if let Some(last) = requests.last_mut() {
+                let last_end = last.0.saturating_add(last.1);
+                if start <= last_end.saturating_add(COALESCE_GAP) {
+                    last.1 = cmp::max(last_end, end) - last.0;
+                    continue;
+                }
+            }
+            requests.push((start, len));
+        }
+
+        let fetched_total = requests
+            .iter()
+            .fold(0u64, |total, &(_, len)| total.saturating_add(len));
+        self.limits().check_allocation(fetched_total)?;
+
+        let mut fetched = Vec::with_capacity(requests.len());
+        for &(start, len) in &requests {
+            let data = source.read_at(start, len)?;
+            if u64(data.len()) != len {
+                return Err(error::truncated(format!(
+                    "item data truncated: {} bytes at {}, not {}",
+                    data.len(),
+                    start,
+                    len
+                )));
+            }
+            fetched.push((start, data));
+        }
+
+        let mut data = Vec::with_capacity(usize(total));
+        for extent in &location.extents {
+            let start = base + extent.offset;
+            let &(fetched_start, ref fetched_data) = fetched
+                .iter()
+                .rev()
+                .find(|&&(fetched_start, _)| fetched_start <= start)
+                .ok_or_else(|| format_err!("extent at {} wasn't fetched", start))?;
+            data.extend_from_slice(slice_at(fetched_data, start - fetched_start, extent.length));
+        }
+        Ok(data)
+    }
+}
+
+fn fetch_meta<S: ByteSource + ?Sized>(
+    source: &mut S,
+    loader: &mut MetaLoader,
+) -> Result<(FileType, RawMeta), Error> {
+    let mut window = Window {
+        start: 0,
+        data: Vec::new(),
+    };
+
+    loop {
+        let pos = loader.position();
+        let header = {
+            // eight bytes, or sixteen for a large size
+            let small = window.read(source, pos, 8)?;
+            if [0, 0, 0, 1] == small[..4] {
+                mpeg::read_header(window.read(source, pos, 16)?)?
+            } else {
+                mpeg::read_header(small)?
+            }
+        };
+
+        if !loader.wants(&header)? {
+            loader.skipped(&header);
+            continue;
+        }
+
+        let data = window.read(source, pos + u64(header.offset), header.data_size())?;
+        let mut data = Cursor::new(data).take(header.data_size());
+        if let Some(loaded) = loader.parse(&header, &mut data)? {
+            return Ok(loaded);
+        }
+    }
+}
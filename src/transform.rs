@@ -0,0 +1,414 @@
+use cast::i32;
+use cast::i64;
+use cast::u32;
+use failure::Error;
+
+use mpeg::iprp::Clap;
+use mpeg::iprp::Imir;
+use mpeg::iprp::Irot;
+use mpeg::iprp::Property;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rect {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// one of the eight ways to lay a rectangle down: rotate anticlockwise, then maybe swap
+// left and right
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Orientation {
+    quarter_turns: u8,
+    mirrored: bool,
+}
+
+// a crop of the coded image, then a change of orientation; everything the transformative
+// properties of an item can express, in the order they're applied
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Transform {
+    pub size: (u32, u32),
+    pub crop: Rect,
+    pub orientation: Orientation,
+}
+
+impl Rect {
+    pub fn full(width: u32, height: u32) -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    fn fits(&self, (width, height): (u32, u32)) -> bool {
+        0 != self.width
+            && 0 != self.height
+            && u64::from(self.left) + u64::from(self.width) <= u64::from(width)
+            && u64::from(self.top) + u64::from(self.height) <= u64::from(height)
+    }
+}
+
+impl Orientation {
+    pub fn new(quarter_turns: u8, mirrored: bool) -> Orientation {
+        Orientation {
+            quarter_turns: quarter_turns % 4,
+            mirrored,
+        }
+    }
+
+    pub fn rotation(quarter_turns: u8) -> Orientation {
+        Orientation::new(quarter_turns, false)
+    }
+
+    // swap left and right
+    pub fn flip_horizontal() -> Orientation {
+        Orientation::new(0, true)
+    }
+
+    // swap top and bottom; a half turn, then swap left and right
+    pub fn flip_vertical() -> Orientation {
+        Orientation::new(2, true)
+    }
+
+    pub fn quarter_turns(&self) -> u8 {
+        self.quarter_turns
+    }
+
+    pub fn mirrored(&self) -> bool {
+        self.mirrored
+    }
+
+    // this, followed by `next`; a rotation after a mirror turns the other way
+    pub fn then(self, next: Orientation) -> Orientation {
+        let turns = if self.mirrored {
+            4 - next.quarter_turns
+        } else {
+            next.quarter_turns
+        };
+
+        Orientation::new(self.quarter_turns + turns, self.mirrored != next.mirrored)
+    }
+
+    pub fn inverse(self) -> Orientation {
+        if self.mirrored {
+            self
+        } else {
+            Orientation::rotation(4 - self.quarter_turns)
+        }
+    }
+
+    pub fn apply_size(self, (width, height): (u32, u32)) -> (u32, u32) {
+        if 0 == self.quarter_turns & 1 {
+            (width, height)
+        } else {
+            (height, width)
+        }
+    }
+
+    // where a rectangle of an image of this size ends up
+    pub fn apply_rect(self, mut rect: Rect, (mut width, mut height): (u32, u32)) -> Rect {
+        for _ in 0..self.quarter_turns {
+            rect = Rect {
+                left: rect.top,
+                top: width - rect.left - rect.width,
+                width: rect.height,
+                height: rect.width,
+            };
+            let turned = (height, width);
+            width = turned.0;
+            height = turned.1;
+        }
+
+        if self.mirrored {
+            rect.left = width - rect.left - rect.width;
+        }
+
+        rect
+    }
+
+    // the irot and imir to write, in that order
+    pub fn to_properties(self) -> Vec<Property> {
+        let mut properties = Vec::with_capacity(2);
+        if 0 != self.quarter_turns {
+            properties.push(Property::Rotation(Irot {
+                angle: self.quarter_turns,
+            }));
+        }
+        if self.mirrored {
+            properties.push(Property::Mirror(Imir { axis: 0 }));
+        }
+        properties
+    }
+}
+
+impl Transform {
+    pub fn identity(width: u32, height: u32) -> Transform {
+        Transform {
+            size: (width, height),
+            crop: Rect::full(width, height),
+            orientation: Orientation::default(),
+        }
+    }
+
+    // fold an item's transformative properties, in association order, over its ispe size
+    pub fn from_properties<'p, I: IntoIterator<Item = &'p Property>>(
+        width: u32,
+        height: u32,
+        properties: I,
+    ) -> Result<Transform, Error> {
+        let mut transform = Transform::identity(width, height);
+        for property in properties {
+            match property {
+                Property::Rotation(irot) => transform.rotate(irot.angle),
+                Property::Mirror(imir) => transform.reorient(if 0 == imir.axis {
+                    Orientation::flip_horizontal()
+                } else {
+                    Orientation::flip_vertical()
+                }),
+                Property::CleanAperture(clap) => {
+                    let rect = clap_rect(clap, transform.display_size())?;
+                    transform.crop(rect)?
+                }
+                _ => (),
+            }
+        }
+        Ok(transform)
+    }
+
+    pub fn display_size(&self) -> (u32, u32) {
+        self.orientation
+            .apply_size((self.crop.width, self.crop.height))
+    }
+
+    pub fn rotate(&mut self, quarter_turns: u8) {
+        self.reorient(Orientation::rotation(quarter_turns))
+    }
+
+    pub fn reorient(&mut self, next: Orientation) {
+        self.orientation = self.orientation.then(next);
+    }
+
+    // crop to a rectangle of the image as it's currently displayed
+    pub fn crop(&mut self, rect: Rect) -> Result<(), Error> {
+        let display_size = self.display_size();
+        ensure!(
+            rect.fits(display_size),
+            "crop {:?} doesn't fit in the {:?} image",
+            rect,
+            display_size
+        );
+
+        let within = self.orientation.inverse().apply_rect(rect, display_size);
+        self.crop = Rect {
+            left: self.crop.left + within.left,
+            top: self.crop.top + within.top,
+            width: within.width,
+            height: within.height,
+        };
+        Ok(())
+    }
+
+    // the clap, irot and imir to write, in that order
+    pub fn to_properties(&self) -> Result<Vec<Property>, Error> {
+        let mut properties = Vec::with_capacity(3);
+        if self.crop != Rect::full(self.size.0, self.size.1) {
+            properties.push(Property::CleanAperture(rect_clap(self.crop, self.size)?));
+        }
+        properties.extend(self.orientation.to_properties());
+        Ok(properties)
+    }
+}
+
+// only whole-pixel apertures can be composed losslessly
+fn clap_rect(clap: &Clap, (width, height): (u32, u32)) -> Result<Rect, Error> {
+    let (left, crop_width) = clap_span(
+        clap.width_n,
+        clap.width_d,
+        clap.horiz_off_n,
+        clap.horiz_off_d,
+        width,
+    )?;
+    let (top, crop_height) = clap_span(
+        clap.height_n,
+        clap.height_d,
+        clap.vert_off_n,
+        clap.vert_off_d,
+        height,
+    )?;
+
+    let rect = Rect {
+        left,
+        top,
+        width: crop_width,
+        height: crop_height,
+    };
+    ensure!(
+        rect.fits((width, height)),
+        "clean aperture {:?} doesn't fit in the {}x{} image",
+        clap,
+        width,
+        height
+    );
+    Ok(rect)
+}
+
+// the centre is at `off + (full - 1) / 2`, so the first pixel is `off + (full - size) / 2`
+fn clap_span(
+    size_n: u32,
+    size_d: u32,
+    off_n: i32,
+    off_d: u32,
+    full: u32,
+) -> Result<(u32, u32), Error> {
+    ensure!(
+        0 != size_d && 0 != off_d,
+        "clean aperture has a zero denominator"
+    );
+    let size = size_n / size_d;
+    ensure!(
+        size * size_d == size_n,
+        "fractional clean aperture sizes are not supported: {}/{}",
+        size_n,
+        size_d
+    );
+
+    let twice_start = 2 * i64(off_n) + (i64(full) - i64(size)) * i64(off_d);
+    let denominator = 2 * i64(off_d);
+    ensure!(
+        0 == twice_start % denominator,
+        "fractional clean aperture offsets are not supported: {}/{}",
+        off_n,
+        off_d
+    );
+
+    Ok((u32(twice_start / denominator)?, size))
+}
+
+fn rect_clap(rect: Rect, (width, height): (u32, u32)) -> Result<Clap, Error> {
+    let (horiz_off_n, horiz_off_d) = centre_offset(rect.left, rect.width, width)?;
+    let (vert_off_n, vert_off_d) = centre_offset(rect.top, rect.height, height)?;
+    Ok(Clap {
+        width_n: rect.width,
+        width_d: 1,
+        height_n: rect.height,
+        height_d: 1,
+        horiz_off_n,
+        horiz_off_d,
+        vert_off_n,
+        vert_off_d,
+    })
+}
+
+fn centre_offset(start: u32, size: u32, full: u32) -> Result<(i32, u32), Error> {
+    let twice = i32(2 * i64(start) + i64(size) - i64(full))?;
+    Ok(if 0 == twice % 2 {
+        (twice / 2, 1)
+    } else {
+        (twice, 2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Orientation;
+    use super::Rect;
+    use super::Transform;
+
+    #[test]
+    fn orientations() {
+        let all: Vec<Orientation> = (0..8).map(|i| Orientation::new(i % 4, i >= 4)).collect();
+
+        for &a in &all {
+            assert_eq!(Orientation::default(), a.then(a.inverse()));
+            assert_eq!(Orientation::default(), a.inverse().then(a));
+            for &b in &all {
+                // composing matches applying one after the other
+                let rect = Rect {
+                    left: 1,
+                    top: 2,
+                    width: 3,
+                    height: 4,
+                };
+                let size = (10, 20);
+                let stepwise = b.apply_rect(a.apply_rect(rect, size), a.apply_size(size));
+                assert_eq!(stepwise, a.then(b).apply_rect(rect, size));
+            }
+        }
+
+        let rotation = Orientation::rotation(1);
+        assert_eq!(
+            Orientation::default(),
+            (0..4).fold(Orientation::default(), |o, _| o.then(rotation))
+        );
+        assert_eq!(
+            Orientation::default(),
+            Orientation::flip_vertical().then(Orientation::flip_vertical())
+        );
+        assert_eq!(
+            Orientation::rotation(2),
+            Orientation::flip_vertical().then(Orientation::flip_horizontal())
+        );
+    }
+
+    #[test]
+    fn crop_after_rotate() {
+        let mut transform = Transform::identity(360, 190);
+        transform.rotate(1);
+        assert_eq!((190, 360), transform.display_size());
+
+        // the top of the turned image is the right of the coded one
+        transform
+            .crop(Rect {
+                left: 0,
+                top: 0,
+                width: 190,
+                height: 100,
+            })
+            .unwrap();
+        assert_eq!(
+            Rect {
+                left: 260,
+                top: 0,
+                width: 100,
+                height: 190,
+            },
+            transform.crop
+        );
+
+        let properties = transform.to_properties().unwrap();
+        assert_eq!(2, properties.len());
+        assert_eq!(
+            transform,
+            Transform::from_properties(360, 190, &properties).unwrap()
+        );
+    }
+
+    #[test]
+    fn odd_clap() {
+        let mut transform = Transform::identity(5, 5);
+        transform
+            .crop(Rect {
+                left: 0,
+                top: 1,
+                width: 2,
+                height: 3,
+            })
+            .unwrap();
+        let properties = transform.to_properties().unwrap();
+        assert_eq!(
+            transform,
+            Transform::from_properties(5, 5, &properties).unwrap()
+        );
+        assert!(transform
+            .crop(Rect {
+                left: 1,
+                top: 0,
+                width: 2,
+                height: 3,
+            })
+            .is_err());
+    }
+}
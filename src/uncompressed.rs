@@ -0,0 +1,372 @@
+use bitreader::BitReader;
+use cast::u16;
+use cast::u32;
+use cast::u64;
+use cast::usize;
+use failure::Error;
+
+use mpeg::unci;
+use mpeg::unci::Cmpd;
+use mpeg::unci::Uncc;
+use mpeg::unci::UnccComponent;
+use picture::Picture;
+use picture::Plane;
+
+// Unpack the data of an 'unci' item. Only whole samples of unsigned integers up to 16 bits
+// are supported, without blocks, in the component, pixel, row and tile-component
+// interleavings.
+pub fn decode(
+    uncc: &Uncc,
+    cmpd: &Cmpd,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<Picture, Error> {
+    ensure!(
+        0 == uncc.block_size,
+        "uncompressed blocks are not supported"
+    );
+
+    for component in &uncc.components {
+        ensure!(
+            unci::FORMAT_UNSIGNED == component.component_format,
+            "unsupported uncompressed component format: {}",
+            component.component_format
+        );
+        ensure!(
+            component.component_bit_depth_minus_one < 16,
+            "uncompressed components over 16 bits are not supported: {}",
+            component.component_bit_depth_minus_one + 1
+        );
+    }
+
+    let tile_cols = u32(u64(uncc.num_tile_cols_minus_one) + 1)?;
+    let tile_rows = u32(u64(uncc.num_tile_rows_minus_one) + 1)?;
+    let tile_width = width / tile_cols;
+    let tile_height = height / tile_rows;
+    ensure!(
+        tile_width * tile_cols == width && tile_height * tile_rows == height,
+        "{}x{} tiles don't evenly divide a {}x{} image",
+        tile_cols,
+        tile_rows,
+        width,
+        height
+    );
+
+    let subsampled = unci::SAMPLING_NONE != uncc.sampling_type;
+    ensure!(
+        !subsampled
+            || unci::INTERLEAVE_COMPONENT == uncc.interleave_type
+            || unci::INTERLEAVE_TILE_COMPONENT == uncc.interleave_type,
+        "subsampling is only supported with component interleaving"
+    );
+
+    let mut planes = Vec::with_capacity(uncc.components.len());
+    let mut scales = Vec::with_capacity(uncc.components.len());
+    for component in &uncc.components {
+        let definition = cmpd
+            .components
+            .get(usize(component.component_index))
+            .ok_or_else(|| {
+                format_err!(
+                    "uncC refers to component {}, but cmpd only has {}",
+                    component.component_index,
+                    cmpd.components.len()
+                )
+            })?;
+
+        let (scale_x, scale_y) = match definition.component_type {
+            unci::COMPONENT_CB | unci::COMPONENT_CR => match uncc.sampling_type {
+                unci::SAMPLING_NONE => (1, 1),
+                unci::SAMPLING_422 => (2, 1),
+                unci::SAMPLING_420 => (2, 2),
+                unci::SAMPLING_411 => (4, 1),
+                other => bail!("unsupported sampling type: {}", other),
+            },
+            _ => (1, 1),
+        };
+        ensure!(
+            tile_width / scale_x * scale_x == tile_width
+                && tile_height / scale_y * scale_y == tile_height,
+            "subsampled tiles must have whole chroma samples"
+        );
+
+        planes.push(Plane::new(
+            definition.component_type,
+            component.component_bit_depth_minus_one + 1,
+            width / scale_x,
+            height / scale_y,
+        ));
+        scales.push((scale_x, scale_y));
+    }
+
+    let mut decoder = Decoder {
+        uncc,
+        bits: BitReader::new(data),
+        planes,
+    };
+
+    let tiles: Vec<(u32, u32)> = (0..tile_rows)
+        .flat_map(|row| (0..tile_cols).map(move |col| (col, row)))
+        .collect();
+
+    match uncc.interleave_type {
+        unci::INTERLEAVE_COMPONENT => {
+            for &(col, row) in &tiles {
+                for (c, &(scale_x, scale_y)) in scales.iter().enumerate() {
+                    let (w, h) = (tile_width / scale_x, tile_height / scale_y);
+                    for y in 0..h {
+                        decoder.read_row(c, col * w, row * h + y, w)?;
+                    }
+                }
+                decoder.end_tile()?;
+            }
+        }
+        unci::INTERLEAVE_TILE_COMPONENT => {
+            for (c, &(scale_x, scale_y)) in scales.iter().enumerate() {
+                let (w, h) = (tile_width / scale_x, tile_height / scale_y);
+                for &(col, row) in &tiles {
+                    for y in 0..h {
+                        decoder.read_row(c, col * w, row * h + y, w)?;
+                    }
+                    decoder.end_tile()?;
+                }
+            }
+        }
+        unci::INTERLEAVE_PIXEL => {
+            for &(col, row) in &tiles {
+                for y in 0..tile_height {
+                    let row_start = decoder.bits.position();
+                    for x in 0..tile_width {
+                        decoder.read_pixel(col * tile_width + x, row * tile_height + y)?;
+                    }
+                    decoder.end_row(row_start)?;
+                }
+                decoder.end_tile()?;
+            }
+        }
+        unci::INTERLEAVE_ROW => {
+            for &(col, row) in &tiles {
+                for y in 0..tile_height {
+                    for c in 0..decoder.planes.len() {
+                        decoder.read_row(c, col * tile_width, row * tile_height + y, tile_width)?;
+                    }
+                }
+                decoder.end_tile()?;
+            }
+        }
+        other => bail!("unsupported interleave type: {}", other),
+    }
+
+    Ok(Picture {
+        width,
+        height,
+        planes: decoder.planes,
+    })
+}
+
+struct Decoder<'u, 'd> {
+    uncc: &'u Uncc,
+    bits: BitReader<'d>,
+    planes: Vec<Plane>,
+}
+
+impl<'u, 'd> Decoder<'u, 'd> {
+    fn read_row(&mut self, c: usize, left: u32, y: u32, width: u32) -> Result<(), Error> {
+        let row_start = self.bits.position();
+        let component = self.uncc.components[c];
+        for x in 0..width {
+            let sample = self.read_sample(&component)?;
+            self.planes[c].set(left + x, y, sample);
+        }
+        self.end_row(row_start)
+    }
+
+    fn read_pixel(&mut self, x: u32, y: u32) -> Result<(), Error> {
+        let pixel_start = self.bits.position();
+        for c in 0..self.planes.len() {
+            let component = self.uncc.components[c];
+            let sample = self.read_sample(&component)?;
+            self.planes[c].set(x, y, sample);
+        }
+
+        if 0 != self.uncc.pixel_size {
+            let used = self.bits.position() - pixel_start;
+            let size = 8 * u64(self.uncc.pixel_size);
+            ensure!(
+                used <= size,
+                "pixel size {} is too small for the components",
+                self.uncc.pixel_size
+            );
+            self.bits.skip(size - used)?;
+        }
+        Ok(())
+    }
+
+    // packed bit by bit unless the depth or alignment is in whole bytes, with any padding in
+    // the most significant bits
+    fn read_sample(&mut self, component: &UnccComponent) -> Result<u16, Error> {
+        let depth = component.component_bit_depth_minus_one + 1;
+        let align = component.component_align_size;
+        if 0 == align && 0 != depth & 7 {
+            return Ok(self.bits.read_u16(depth)?);
+        }
+
+        let bytes = if 0 == align { depth / 8 } else { align };
+        ensure!(
+            bytes <= 8 && 8 * bytes >= depth,
+            "component alignment {} doesn't fit {} bits",
+            align,
+            depth
+        );
+
+        let mut value = 0u64;
+        for i in 0..bytes {
+            let byte = u64(self.bits.read_u8(8)?);
+            if self.uncc.components_little_endian {
+                value |= byte << (8 * i);
+            } else {
+                value = (value << 8) | byte;
+            }
+        }
+
+        Ok(u16(value & ((1 << depth) - 1))?)
+    }
+
+    // rows end on a byte, then are padded to the row alignment
+    fn end_row(&mut self, row_start: u64) -> Result<(), Error> {
+        self.bits.align(1)?;
+        if 0 != self.uncc.row_align_size {
+            let align = u64(self.uncc.row_align_size);
+            let used = (self.bits.position() - row_start) / 8;
+            self.bits.skip(8 * ((align - used % align) % align))?;
+        }
+        Ok(())
+    }
+
+    fn end_tile(&mut self) -> Result<(), Error> {
+        self.bits.align(1)?;
+        if 0 != self.uncc.tile_align_size {
+            self.bits.align(self.uncc.tile_align_size)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cast::u16;
+
+    use mpeg::unci;
+    use mpeg::unci::Cmpd;
+    use mpeg::unci::ComponentDefinition;
+    use mpeg::unci::Uncc;
+    use mpeg::unci::UnccComponent;
+
+    use super::decode;
+
+    fn cmpd(types: &[u16]) -> Cmpd {
+        Cmpd {
+            components: types
+                .iter()
+                .map(|&component_type| ComponentDefinition {
+                    component_type,
+                    component_type_uri: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn uncc(depths: &[u8], sampling_type: u8, interleave_type: u8) -> Uncc {
+        Uncc {
+            version: 0,
+            profile: unci::RGB3,
+            components: depths
+                .iter()
+                .enumerate()
+                .map(|(i, &depth)| UnccComponent {
+                    component_index: u16(i).unwrap(),
+                    component_bit_depth_minus_one: depth - 1,
+                    component_format: unci::FORMAT_UNSIGNED,
+                    component_align_size: 0,
+                })
+                .collect(),
+            sampling_type,
+            interleave_type,
+            block_size: 0,
+            components_little_endian: false,
+            block_pad_lsb: false,
+            block_little_endian: false,
+            block_reversed: false,
+            pad_unknown: false,
+            pixel_size: 0,
+            row_align_size: 0,
+            tile_align_size: 0,
+            num_tile_cols_minus_one: 0,
+            num_tile_rows_minus_one: 0,
+        }
+    }
+
+    #[test]
+    fn pixel_interleaved_rgb() {
+        let rgb = cmpd(&[
+            unci::COMPONENT_RED,
+            unci::COMPONENT_GREEN,
+            unci::COMPONENT_BLUE,
+        ]);
+        let mut config = uncc(&[8, 8, 8], unci::SAMPLING_NONE, unci::INTERLEAVE_PIXEL);
+        // each two-pixel row is padded from six bytes to eight
+        config.row_align_size = 4;
+        let data = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+
+        let picture = decode(&config, &rgb, 2, 2, &data).unwrap();
+        let green = picture.plane(unci::COMPONENT_GREEN).unwrap();
+        assert_eq!(vec![2, 5, 8, 11], green.samples);
+        assert_eq!(12, picture.plane(unci::COMPONENT_BLUE).unwrap().get(1, 1));
+    }
+
+    #[test]
+    fn tiled_420() {
+        let ycbcr = cmpd(&[unci::COMPONENT_Y, unci::COMPONENT_CB, unci::COMPONENT_CR]);
+        let mut config = uncc(&[8, 8, 8], unci::SAMPLING_420, unci::INTERLEAVE_COMPONENT);
+        config.num_tile_cols_minus_one = 1;
+
+        // two 2x2 tiles, each with four luma samples then one of each chroma
+        let data = [1, 2, 5, 6, 20, 30, 3, 4, 7, 8, 21, 31];
+        let picture = decode(&config, &ycbcr, 4, 2, &data).unwrap();
+
+        let luma = picture.plane(unci::COMPONENT_Y).unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8], luma.samples);
+        let cr = picture.plane(unci::COMPONENT_CR).unwrap();
+        assert_eq!((2, 1), (cr.width, cr.height));
+        assert_eq!(vec![30, 31], cr.samples);
+    }
+
+    #[test]
+    fn packed_bits() {
+        let mono = cmpd(&[unci::COMPONENT_MONOCHROME]);
+        let config = uncc(&[10], unci::SAMPLING_NONE, unci::INTERLEAVE_COMPONENT);
+
+        // three ten bit samples per row, with the row padded out to four bytes
+        let data = [
+            0b1111_1111,
+            0b1100_0000,
+            0b0000_0000,
+            0b0000_0100,
+            0b0100_0000,
+            0b0000_0000,
+            0b0000_0000,
+            0b0000_0000,
+        ];
+        let picture = decode(&config, &mono, 3, 2, &data).unwrap();
+        assert_eq!(vec![1023, 0, 1, 256, 0, 0], picture.planes[0].samples);
+
+        let mut wide = config.clone();
+        wide.components[0].component_align_size = 2;
+        wide.components_little_endian = true;
+        let picture = decode(&wide, &mono, 1, 1, &[0xff, 0x02]).unwrap();
+        assert_eq!(vec![0x2ff], picture.planes[0].samples);
+
+        assert!(decode(&config, &mono, 3, 2, &data[..6]).is_err());
+    }
+}
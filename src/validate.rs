@@ -0,0 +1,335 @@
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+
+use cast::u64;
+use failure::Error;
+
+use error::ParseError;
+use file::Heif;
+use mpeg;
+use mpeg::iprp::FromProperty;
+use mpeg::iprp::Ispe;
+use mpeg::read_header;
+use mpeg::FourCc;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    // allowed, but likely to confuse readers
+    Warning,
+    // a violation of the spec
+    Error,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Category {
+    // boxes out of order, or running off the end of their parent
+    Structure,
+    // a required box or property isn't there
+    Missing,
+    Duplicate,
+    // references, groups and associations that don't line up with the items
+    Reference,
+    // item data outside the file, or outside the idat
+    Location,
+    // ipma entries pointing past the end of the ipco
+    Association,
+}
+
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub category: Category,
+    pub item: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+// Where problems go while a `Heif` is built: straight out as an error, or into a report,
+// in which case the builder carries on as best it can.
+pub enum Problems<'r> {
+    Strict,
+    Collect(&'r mut Report),
+}
+
+impl Report {
+    pub fn push(
+        &mut self,
+        severity: Severity,
+        category: Category,
+        item: Option<u32>,
+        message: String,
+    ) {
+        self.problems.push(Problem {
+            severity,
+            category,
+            item,
+            message,
+        })
+    }
+
+    pub fn errors(&self) -> Vec<&Problem> {
+        self.problems
+            .iter()
+            .filter(|problem| Severity::Error == problem.severity)
+            .collect()
+    }
+
+    pub fn is_conformant(&self) -> bool {
+        self.errors().is_empty()
+    }
+}
+
+impl<'r> Problems<'r> {
+    pub fn error(
+        &mut self,
+        category: Category,
+        item: Option<u32>,
+        message: String,
+    ) -> Result<(), Error> {
+        match self {
+            Problems::Strict => Err(format_err!("{}", message)),
+            Problems::Collect(report) => {
+                report.push(Severity::Error, category, item, message);
+                Ok(())
+            }
+        }
+    }
+
+    // strict readers don't care about these
+    pub fn warning(&mut self, category: Category, item: Option<u32>, message: String) {
+        if let Problems::Collect(report) = self {
+            report.push(Severity::Warning, category, item, message);
+        }
+    }
+}
+
+// Check the whole file, collecting every problem rather than stopping at the first. The
+// `Heif` is whatever could be salvaged, and is only missing if the 'meta' can't be read.
+pub fn validate<R: Read + Seek>(mut from: R) -> (Option<Heif>, Report) {
+    let mut report = Report::default();
+
+    let file_len = match check_boxes(&mut from, &mut report) {
+        Ok(len) => len,
+        Err(e) => {
+            report.push(
+                Severity::Error,
+                Category::Structure,
+                None,
+                format!("reading the top-level boxes failed: {}", e),
+            );
+            return (None, report);
+        }
+    };
+
+    let heif = match from
+        .seek(SeekFrom::Start(0))
+        .map_err(|e| ParseError::from(Error::from(e)))
+        .and_then(|_| Heif::new_lenient(&mut from, &mut report))
+    {
+        Ok(heif) => heif,
+        Err(e) => {
+            report.push(
+                Severity::Error,
+                Category::Structure,
+                None,
+                format!("reading the meta failed: {}", e),
+            );
+            return (None, report);
+        }
+    };
+
+    check_items(&heif, file_len, &mut report);
+
+    (Some(heif), report)
+}
+
+// the top-level layout, and the start of the meta; returns the length of the file
+fn check_boxes<R: Read + Seek>(mut from: R, report: &mut Report) -> Result<u64, Error> {
+    let len = from.seek(SeekFrom::End(0))?;
+
+    let mut types = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        from.seek(SeekFrom::Start(pos))?;
+        let header = read_header(&mut from)?;
+        let overruns = header.size > len - pos;
+        if overruns {
+            report.push(
+                Severity::Error,
+                Category::Structure,
+                None,
+                format!(
+                    "{:?} box at {} claims {} bytes, but the file ends after {}",
+                    header.box_type,
+                    pos,
+                    header.size,
+                    len - pos
+                ),
+            );
+        }
+
+        if mpeg::META == header.box_type && header.data_size() >= 12 {
+            // skip the full box header, and look at the first child
+            from.seek(SeekFrom::Current(4))?;
+            let first = read_header(&mut from)?;
+            if mpeg::HDLR != first.box_type {
+                report.push(
+                    Severity::Error,
+                    Category::Structure,
+                    None,
+                    format!(
+                        "the first box in the meta must be hdlr, not {:?}",
+                        first.box_type
+                    ),
+                );
+            }
+        }
+
+        types.push(header.box_type);
+        if overruns {
+            // the size is nonsense, so there's nowhere sensible to carry on from
+            break;
+        }
+        pos += header.size;
+    }
+
+    if Some(&mpeg::FTYP) != types.first() {
+        report.push(
+            Severity::Error,
+            Category::Structure,
+            None,
+            format!("the file must start with ftyp, not {:?}", types.first()),
+        );
+    }
+
+    for &(box_type, name) in &[(mpeg::FTYP, "ftyp"), (mpeg::META, "meta")] {
+        let count = types.iter().filter(|&&t| t == box_type).count();
+        if count > 1 {
+            report.push(
+                Severity::Error,
+                Category::Duplicate,
+                None,
+                format!("there must be one top-level {}, not {}", name, count),
+            );
+        }
+    }
+
+    Ok(len)
+}
+
+fn is_image(item_type: FourCc) -> bool {
+    [
+        mpeg::HVC1,
+        mpeg::AV01,
+        mpeg::JPEG,
+        mpeg::UNCI,
+        mpeg::GRID,
+        mpeg::IDEN,
+        mpeg::IOVL,
+    ]
+    .contains(&item_type)
+}
+
+fn check_items(heif: &Heif, file_len: u64, report: &mut Report) {
+    let primary = heif.primary_item_id();
+    let items = heif.items();
+
+    for item in &items {
+        let id = item.id;
+
+        if is_image(item.info.item_type)
+            && !item
+                .properties
+                .iter()
+                .any(|&prop| Ispe::from_property(prop).is_some())
+        {
+            report.push(
+                Severity::Error,
+                Category::Missing,
+                Some(id),
+                format!("image item {} has no ispe", id),
+            );
+        }
+
+        if id == primary && item.info.hidden {
+            report.push(
+                Severity::Error,
+                Category::Reference,
+                Some(id),
+                format!("the primary item {} is hidden", id),
+            );
+        }
+
+        let referenced = heif
+            .all_references()
+            .iter()
+            .any(|reference| reference.from_item_id == id || reference.to_item_ids.contains(&id));
+        if id != primary && !referenced && heif.groups_containing(id).is_empty() {
+            report.push(
+                Severity::Warning,
+                Category::Reference,
+                Some(id),
+                format!(
+                    "item {} isn't the primary item, and nothing refers to it",
+                    id
+                ),
+            );
+        }
+
+        if let Ok(location) = heif.item_location(id) {
+            let (limit, place) = match location.construction_method {
+                0 => (file_len, "file"),
+                1 => (u64(heif.inline_item_data().len()), "idat"),
+                _ => continue,
+            };
+
+            for extent in &location.extents {
+                let start = location.base_offset.checked_add(extent.offset);
+                let end = start.and_then(|start| start.checked_add(extent.length));
+                let outside = match (start, end) {
+                    (Some(start), Some(end)) => start > limit || end > limit,
+                    _ => true,
+                };
+                if outside {
+                    report.push(
+                        Severity::Error,
+                        Category::Location,
+                        Some(id),
+                        format!(
+                            "item {} has an extent at {}+{}, {} bytes long, outside the {} bytes of the {}",
+                            id, location.base_offset, extent.offset, extent.length, limit, place
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    for reference in heif.all_references() {
+        for &id in Some(&reference.from_item_id)
+            .into_iter()
+            .chain(reference.to_item_ids.iter())
+        {
+            let is_group = heif
+                .entity_groups()
+                .iter()
+                .any(|group| group.group_id == id);
+            if heif.item_info(id).is_err() && !is_group {
+                report.push(
+                    Severity::Error,
+                    Category::Reference,
+                    Some(id),
+                    format!(
+                        "{:?} reference from {} involves item {}, which doesn't exist",
+                        reference.reference_type, reference.from_item_id, id
+                    ),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::Read;
+use std::io::Write;
+
+use bitreader::BitReader;
+use byteorder::WriteBytesExt;
+use byteorder::BE;
+use cast::u32;
+use cast::u64;
+use failure::Error;
+
+use hevc;
+use hevc::nal;
+use hevc::nal::NalReader;
+use hevc::sps;
+use mpeg;
+use mpeg::iprp::Association;
+use mpeg::iprp::Hvcc;
+use mpeg::iprp::Ispe;
+use mpeg::iprp::ItemPropertyAssociation;
+use mpeg::iprp::Property;
+use mpeg::write;
+use mpeg::Extent;
+use mpeg::FileType;
+use mpeg::ItemInfo;
+use mpeg::ItemLoc;
+
+const NAL_AUD_NUT: u8 = 35;
+const NAL_EOS_NUT: u8 = 36;
+const NAL_EOB_NUT: u8 = 37;
+
+const ITEM_ID: u32 = 1;
+
+// Wrap a single HEVC picture, as an Annex B stream, in a HEIF file with one image item.
+// The layout follows libheif's: ftyp, meta, then an mdat holding only the item data.
+pub fn mux_hevc<R: Read, W: Write>(annex_b: R, mut into: W) -> Result<(), Error> {
+    let mut reader = NalReader::new(annex_b);
+
+    let mut vps = None;
+    let mut sps = None;
+    let mut pps = None;
+    let mut data = Vec::new();
+    let mut pictures = 0;
+
+    while let Some(mut unit) = reader.read_nal()? {
+        // the extra zero of a four-byte start code lands on the end of the previous unit
+        while Some(&0) == unit.last() {
+            unit.pop();
+        }
+
+        if unit.is_empty() {
+            continue;
+        }
+
+        ensure!(unit.len() >= 2, "NAL unit is too short: {}", unit.len());
+
+        let unit = nal::add_emulation_prevention(&unit);
+        let nal_unit_type = (unit[0] >> 1) & 0x3f;
+
+        match nal_unit_type {
+            hevc::NAL_VPS_NUT => set_once(&mut vps, unit, "vps")?,
+            hevc::NAL_SPS_NUT => set_once(&mut sps, unit, "sps")?,
+            hevc::NAL_PPS_NUT => set_once(&mut pps, unit, "pps")?,
+            NAL_AUD_NUT | NAL_EOS_NUT | NAL_EOB_NUT => continue,
+            _ => {
+                // first_slice_segment_in_pic_flag starts every vcl unit's payload
+                if nal_unit_type < 32 && unit.len() > 2 && 0 != unit[2] & 0x80 {
+                    pictures += 1;
+                }
+
+                data.write_u32::<BE>(u32(unit.len())?)?;
+                data.extend_from_slice(&unit);
+            }
+        }
+    }
+
+    ensure!(
+        1 == pictures,
+        "expected exactly one picture, not {}",
+        pictures
+    );
+
+    let vps = vps.ok_or_else(|| format_err!("stream has no vps"))?;
+    let sps = sps.ok_or_else(|| format_err!("stream has no sps"))?;
+    let pps = pps.ok_or_else(|| format_err!("stream has no pps"))?;
+
+    let hvcc = Hvcc::from_parameter_sets(&vps, &sps, &pps)?;
+    let (width, height) =
+        sps::seq_parameter_set(&mut BitReader::new(&nal::payload(&sps, hevc::NAL_SPS_NUT)?))?
+            .cropped_size();
+
+    let properties = vec![
+        Property::HvcCodecSettings(hvcc),
+        Property::Size(Ispe {
+            width: u32(width)?,
+            height: u32(height)?,
+        }),
+    ];
+
+    let mut head = Vec::new();
+    write::write_ftyp(
+        &mut head,
+        &FileType {
+            major_brand: mpeg::HEIC,
+            minor_version: 0,
+            brands: vec![mpeg::MIF1, mpeg::HEIC],
+        },
+    )?;
+
+    // the location depends on the size of the meta, so write it once to find the size;
+    // the offset is always written with the same width, so the size can't change
+    let meta = build_meta(&properties, 0, u64(data.len()))?;
+    let data_offset = u64(head.len() + meta.len()) + write::box_header_size(u64(data.len()));
+    let meta = build_meta(&properties, data_offset, u64(data.len()))?;
+    ensure!(
+        u64(head.len() + meta.len()) + write::box_header_size(u64(data.len())) == data_offset,
+        "meta changed size when the offset was filled in"
+    );
+
+    head.extend_from_slice(&meta);
+    into.write_all(&head)?;
+    write::write_box_header(&mut into, mpeg::MDAT, u64(data.len()))?;
+    into.write_all(&data)?;
+
+    Ok(())
+}
+
+fn build_meta(properties: &[Property], data_offset: u64, data_len: u64) -> Result<Vec<u8>, Error> {
+    // keep the offset wide enough for the final value, so the size doesn't change
+    let base_offset = if u32(data_len).is_ok() {
+        data_offset
+    } else {
+        data_offset.max(1 << 32)
+    };
+
+    let mut meta = Vec::new();
+    write::write_full_box(&mut meta, mpeg::META, 0, 0, |into| {
+        write::write_hdlr(into, mpeg::PICT)?;
+        write::write_pitm(into, ITEM_ID)?;
+        write::write_iloc(
+            into,
+            &[ItemLoc {
+                id: ITEM_ID,
+                construction_method: 0,
+                data_reference_index: 0,
+                base_offset,
+                extents: vec![Extent {
+                    index: 0,
+                    offset: 0,
+                    length: data_len,
+                }],
+            }],
+        )?;
+        write::write_iinf(
+            into,
+            &[ItemInfo {
+                id: ITEM_ID,
+                protection_index: 0,
+                item_type: mpeg::HVC1,
+                item_name: String::new(),
+                content_type: None,
+                content_encoding: None,
+                item_uri_type: None,
+                hidden: false,
+            }],
+        )?;
+        write::write_iprp(
+            into,
+            properties,
+            &[ItemPropertyAssociation {
+                item_id: ITEM_ID,
+                associations: vec![
+                    // the decoder configuration is essential, the size is descriptive
+                    Association {
+                        essential: true,
+                        property_index: 1,
+                    },
+                    Association {
+                        essential: false,
+                        property_index: 2,
+                    },
+                ],
+            }],
+        )
+    })?;
+
+    Ok(meta)
+}
+
+fn set_once(slot: &mut Option<Vec<u8>>, unit: Vec<u8>, name: &str) -> Result<(), Error> {
+    ensure!(slot.is_none(), "multiple {}s are not supported", name);
+    *slot = Some(unit);
+    Ok(())
+}
@@ -0,0 +1,280 @@
+#![cfg(feature = "cli")]
+
+extern crate cast;
+extern crate heifers;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::process::Command;
+use std::process::Output;
+
+use cast::u64;
+
+const ROAD: &str = "tests/data/road.heic";
+
+fn heifers(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_heifers"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("running heifers")
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("heifers-cli-{}-{}", process::id(), name))
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn info() {
+    let output = heifers(&["info", ROAD]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        "major brand: heic\n\
+         minor version: 0\n\
+         compatible brands: mif1, heic\n\
+         primary item: 1\n\
+         \n\
+         item 1: hvc1, 1048 bytes, primary\n\
+         \x20 properties: hvcC, ispe 360x190\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+#[test]
+fn info_json() {
+    let output = heifers(&["info", "--json", ROAD]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!("heic", info["file_type"]["major_brand"]);
+    assert_eq!(1, info["primary_item"]);
+    let item = &info["items"][0];
+    assert_eq!("hvc1", item["info"]["item_type"]);
+    assert_eq!(1048, item["data_size"]);
+    assert_eq!(true, item["primary"]);
+    assert_eq!(360, item["properties"][1]["Size"]["width"]);
+    assert_eq!(0, info["groups"].as_array().unwrap().len());
+}
+
+#[test]
+fn extract() {
+    let road = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/road.heic")).unwrap();
+
+    let output = heifers(&["extract", "--item", "1", ROAD, "-"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(&road[333..], output.stdout.as_slice());
+
+    let output = heifers(&["extract", "--stream", ROAD, "-"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // the vps, then the rest
+    assert_eq!(&[0, 0, 0, 1, 0x40, 0x01], &output.stdout[..6]);
+    assert!(output.stdout.len() > 1048);
+
+    // nothing is left behind when there's nothing to extract
+    let out = temp_path("exif.bin");
+    let output = heifers(&["extract", "--exif", ROAD, out.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("no Exif metadata describes item 1"));
+    assert!(!out.exists());
+
+    let output = heifers(&["extract", "--raw", ROAD, out.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(&road[333..], fs::read(&out).unwrap().as_slice());
+    fs::remove_file(&out).unwrap();
+
+    let output = heifers(&["extract", "--item", "2", ROAD, "-"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn convert() {
+    let png = temp_path("road.png");
+    let output = heifers(&["convert", ROAD, png.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains(
+        "convert only decodes uncompressed (unci) items, and this file has none; item 1 is hvc1"
+    ));
+    assert!(!png.exists());
+
+    let output = heifers(&["convert", ROAD, "road.gif"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("can only convert to .png or .ppm"));
+}
+
+// a 3x2 rgb3 picture, in the idat: red counts along each row, green down each column
+fn uncompressed() -> Vec<u8> {
+    use heifers::mpeg;
+    use heifers::mpeg::iprp::Association;
+    use heifers::mpeg::iprp::Ispe;
+    use heifers::mpeg::iprp::ItemPropertyAssociation;
+    use heifers::mpeg::iprp::Property;
+    use heifers::mpeg::unci;
+    use heifers::mpeg::unci::Uncc;
+    use heifers::mpeg::write;
+
+    let mut data = Vec::new();
+    for y in 0..2u8 {
+        for x in 0..3u8 {
+            data.extend_from_slice(&[x * 100, y * 200, 7]);
+        }
+    }
+
+    let mut file = Vec::new();
+    write::write_ftyp(
+        &mut file,
+        &mpeg::FileType {
+            major_brand: mpeg::MIF1,
+            minor_version: 0,
+            brands: vec![mpeg::MIF1],
+        },
+    )
+    .unwrap();
+    write::write_full_box(&mut file, mpeg::META, 0, 0, |into| {
+        write::write_hdlr(into, mpeg::PICT)?;
+        write::write_pitm(into, 1)?;
+        write::write_iloc(
+            into,
+            &[mpeg::ItemLoc {
+                id: 1,
+                construction_method: 1,
+                data_reference_index: 0,
+                base_offset: 0,
+                extents: vec![mpeg::Extent {
+                    index: 0,
+                    offset: 0,
+                    length: u64(data.len()),
+                }],
+            }],
+        )?;
+        write::write_iinf(
+            into,
+            &[mpeg::ItemInfo {
+                id: 1,
+                protection_index: 0,
+                item_type: mpeg::UNCI,
+                item_name: String::new(),
+                content_type: None,
+                content_encoding: None,
+                item_uri_type: None,
+                hidden: false,
+            }],
+        )?;
+        write::write_iprp(
+            into,
+            &[
+                Property::Size(Ispe {
+                    width: 3,
+                    height: 2,
+                }),
+                Property::UncompressedConfig(Uncc::from_profile(unci::RGB3)?),
+            ],
+            &[ItemPropertyAssociation {
+                item_id: 1,
+                associations: vec![
+                    Association {
+                        essential: false,
+                        property_index: 1,
+                    },
+                    Association {
+                        essential: true,
+                        property_index: 2,
+                    },
+                ],
+            }],
+        )?;
+        write::write_box(into, mpeg::IDAT, |into| {
+            into.extend_from_slice(&data);
+            Ok(())
+        })
+    })
+    .unwrap();
+    file
+}
+
+#[test]
+fn convert_uncompressed() {
+    let heif = temp_path("rgb.heif");
+    fs::write(&heif, uncompressed()).unwrap();
+    let heif = heif.to_str().unwrap();
+    let pixels: &[u8] = &[
+        0, 0, 7, 100, 0, 7, 200, 0, 7, // the first row
+        0, 200, 7, 100, 200, 7, 200, 200, 7,
+    ];
+
+    let ppm = temp_path("rgb.ppm");
+    let output = heifers(&["convert", heif, ppm.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut expected = b"P6\n3 2\n255\n".to_vec();
+    expected.extend_from_slice(pixels);
+    assert_eq!(expected, fs::read(&ppm).unwrap());
+    fs::remove_file(&ppm).unwrap();
+
+    let png = temp_path("rgb.png");
+    let output = heifers(&["convert", heif, png.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let png_bytes = fs::read(&png).unwrap();
+    fs::remove_file(&png).unwrap();
+
+    assert_eq!(b"\x89PNG\r\n\x1a\n", &png_bytes[..8]);
+    // the IHDR: 3x2, 8 bits, colour
+    assert_eq!(b"IHDR", &png_bytes[12..16]);
+    assert_eq!(&[0, 0, 0, 3, 0, 0, 0, 2, 8, 2], &png_bytes[16..26]);
+    // the IDAT is one stored deflate block: each row is a filter byte then the pixels
+    let idat = 8 + 25;
+    assert_eq!(b"IDAT", &png_bytes[idat + 4..idat + 8]);
+    let block = &png_bytes[idat + 8 + 2..];
+    assert_eq!(&[1, 20, 0, !20, 0xff], &block[..5]);
+    let mut rows = Vec::new();
+    for row in pixels.chunks(9) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+    assert_eq!(rows.as_slice(), &block[5..25]);
+
+    fs::remove_file(heif).unwrap();
+}
+
+#[test]
+fn convert_lists_decodable_items() {
+    use std::io::Cursor;
+
+    let unci = uncompressed();
+    let mut editor = heifers::Editor::new(Cursor::new(&unci)).unwrap();
+    let mut info = editor.items()[0].clone();
+    info.id = 2;
+    info.item_type = heifers::mpeg::HVC1;
+    editor.add_item(info, vec![0; 4]).unwrap();
+    let mut mixed = Vec::new();
+    editor.write(Cursor::new(&unci), &mut mixed).unwrap();
+
+    let heif = temp_path("mixed.heif");
+    fs::write(&heif, mixed).unwrap();
+    let ppm = temp_path("mixed.ppm");
+    let output = heifers(&[
+        "convert",
+        "--item",
+        "2",
+        heif.to_str().unwrap(),
+        ppm.to_str().unwrap(),
+    ]);
+    fs::remove_file(&heif).unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("item 2 is hvc1; try --item with one of: 1"));
+    assert!(!ppm.exists());
+}
+
+#[test]
+fn usage() {
+    assert!(heifers(&["--help"]).status.success());
+    assert!(!heifers(&[]).status.success());
+    assert!(!heifers(&["info", "--bogus", ROAD]).status.success());
+    assert!(!heifers(&["extract", "--raw", "--xmp", ROAD, "-"])
+        .status
+        .success());
+}
@@ -0,0 +1,85 @@
+extern crate bitreader;
+extern crate heifers;
+
+use std::io::Cursor;
+
+use bitreader::BitReader;
+use heifers::hevc::pps;
+use heifers::hevc::sps;
+use heifers::hevc::vps;
+
+// A deterministic stand-in for the cargo-fuzz targets in fuzz/: every truncation, and
+// every single bit flip, of a real file. Errors are fine; only a panic fails.
+
+const ROAD: &[u8] = include_bytes!("data/road.heic");
+
+// the same as fuzz/fuzz_targets/heif.rs
+fn heif(bytes: &[u8]) {
+    if let Ok(boxes) = heifers::mpeg::tree::read_tree(bytes, &heifers::Limits::default()) {
+        let _ = heifers::mpeg::tree::dump(&boxes);
+    }
+
+    let _ = heifers::validate(Cursor::new(bytes));
+    let _ = heifers::Heif::new_lenient(Cursor::new(bytes), &mut heifers::Report::default());
+    let _ = heifers::Heif::from_source(&mut &bytes[..]);
+    if let Ok(editor) = heifers::Editor::new(Cursor::new(bytes)) {
+        let _ = editor.write(Cursor::new(bytes), Vec::new());
+    }
+
+    let heif = match heifers::Heif::new(Cursor::new(bytes)) {
+        Ok(heif) => heif,
+        Err(_) => return,
+    };
+
+    for item in heif.items() {
+        let _ = heif.bit_stream(item.id, Cursor::new(bytes), Vec::new());
+        let _ = heif.decode_uncompressed(Cursor::new(bytes), item.id);
+        let _ = heif.read_item_data_from(&mut &bytes[..], item.id);
+        let _ = heif.find_vps(item.id);
+        let _ = heif.find_sps(item.id);
+        let _ = heif.find_pps(item.id);
+    }
+}
+
+// the same as fuzz/fuzz_targets/parameter_sets.rs
+fn parameter_sets(rbsp: &[u8]) {
+    if let Ok(parsed) = vps::video_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+    if let Ok(parsed) = sps::seq_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+    if let Ok(parsed) = pps::picture_parameter_set(&mut BitReader::new(rbsp)) {
+        let _ = parsed.to_string();
+    }
+}
+
+fn mutations(original: &[u8], mut check: impl FnMut(&[u8])) {
+    for len in 0..original.len() {
+        check(&original[..len]);
+    }
+
+    let mut bytes = original.to_vec();
+    for pos in 0..bytes.len() {
+        for bit in 0..8 {
+            bytes[pos] ^= 1 << bit;
+            check(&bytes);
+            bytes[pos] ^= 1 << bit;
+        }
+    }
+}
+
+#[test]
+fn mutated_files() {
+    mutations(ROAD, heif);
+}
+
+#[test]
+fn mutated_parameter_sets() {
+    let heif = heifers::Heif::new(Cursor::new(ROAD)).expect("parsing");
+    let item = heif.primary_item_id();
+    for (unit_type, unit) in heif.parameter_sets(item).expect("parameter sets") {
+        let rbsp = heifers::hevc::nal::payload(unit, unit_type).expect("payload");
+        mutations(&rbsp, parameter_sets);
+    }
+}
@@ -1,10 +1,16 @@
 extern crate cast;
 extern crate failure;
 extern crate heifers;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
 use std::io::Cursor;
 use std::io::Read;
 
+use cast::u64;
+use cast::usize;
 use failure::Error;
 
 #[test]
@@ -26,3 +32,1342 @@ fn road() -> Result<(), Error> {
     println!("{:?}", heifers::hevc::dump(Cursor::new(data), &pps, &sps)?);
     Ok(())
 }
+
+#[test]
+fn file_type() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+
+    let file_type = heif.file_type();
+    assert_eq!(heifers::mpeg::HEIC, file_type.major_brand);
+    assert_eq!(
+        &[heifers::mpeg::MIF1, heifers::mpeg::HEIC],
+        file_type.brands.as_slice()
+    );
+    assert_eq!(heifers::mpeg::Structure::IMAGE_ITEMS, file_type.structure());
+    Ok(())
+}
+
+#[test]
+fn items() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let items = heif.items();
+    assert_eq!(1, items.len());
+    assert_eq!(item, items[0].id);
+    assert_eq!(heifers::mpeg::HVC1, items[0].info.item_type);
+    assert_eq!("", items[0].info.item_name);
+    assert!(!items[0].info.hidden);
+    assert_eq!(1048, items[0].data_size);
+    assert_eq!(heif.properties(item)?.len(), items[0].properties.len());
+    assert_eq!(heifers::mpeg::HVC1, heif.item_info(item)?.item_type);
+    assert!(heif.references(item).is_empty());
+    assert!(heif.unknown_boxes().is_empty());
+    Ok(())
+}
+
+#[test]
+fn properties() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let props = heif.properties(item)?;
+    assert_eq!(2, props.len());
+    let ispe = heif
+        .property::<heifers::mpeg::iprp::Ispe>(item)?
+        .expect("road has an ispe");
+    assert_eq!((360, 190), (ispe.width, ispe.height));
+
+    let vps = heif.find_vps(item)?;
+    assert_eq!(60, vps.profile_tier_level.general_level_idc);
+    Ok(())
+}
+
+#[test]
+fn essential_properties() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    assert_eq!(1, heif.essential_properties(item)?.len());
+    assert!(heif.unsupported_essential_properties(item)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn bit_stream() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Hvcc;
+    use heifers::mpeg::iprp::Property;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let types: Vec<u8> = heif
+        .parameter_sets(item)?
+        .into_iter()
+        .map(|(nal_type, _)| nal_type)
+        .collect();
+    assert_eq!(vec![32, 33, 34], types);
+
+    // road's one slice, after its four-byte length
+    let slice = &bytes[333 + 4..];
+    let mut stream = Vec::new();
+    heif.bit_stream(item, Cursor::new(bytes), &mut stream)?;
+    assert_eq!(4, count_start_codes(&stream));
+    assert!(stream.ends_with(slice));
+
+    // the same picture, stored with two-byte lengths
+    let hvcc = heif.property::<Hvcc>(item)?.expect("road has an hvcC");
+    let mut record = Vec::new();
+    heifers::mpeg::iprp::write_hvcc(hvcc, &mut record)?;
+    // lengthSizeMinusOne, in the bottom two bits of the 22nd byte
+    record[21] = (record[21] & !0b11) | 1;
+    let short_hvcc = heifers::mpeg::iprp::parse_hvcc(
+        &mut Cursor::new(&record).take(u64(record.len())),
+        &mut heifers::Budget::default(),
+    )?;
+    assert_eq!(2, short_hvcc.length_size());
+
+    let mut data = cast::u16(slice.len())?.to_be_bytes().to_vec();
+    data.extend_from_slice(slice);
+    let short = single_item(
+        heifers::mpeg::HEIC,
+        heifers::mpeg::HVC1,
+        &[Property::HvcCodecSettings(short_hvcc)],
+        &data,
+    )?;
+    let mut short_stream = Vec::new();
+    heifers::Heif::new(Cursor::new(&short))?.bit_stream(
+        1,
+        Cursor::new(&short),
+        &mut short_stream,
+    )?;
+    assert_eq!(stream, short_stream);
+    Ok(())
+}
+
+#[test]
+fn hvcc() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let hvcc = bytes
+        .windows(4)
+        .position(|window| b"hvcC" == window)
+        .expect("road has an hvcC");
+    let hvcc_size = usize(u32::from_be_bytes([
+        bytes[hvcc - 4],
+        bytes[hvcc - 3],
+        bytes[hvcc - 2],
+        bytes[hvcc - 1],
+    ]));
+    let hvcc_payload = &bytes[hvcc + 4..hvcc - 4 + hvcc_size];
+    let parsed = heifers::mpeg::iprp::parse_hvcc(
+        &mut Cursor::new(hvcc_payload).take(u64(hvcc_payload.len())),
+        &mut heifers::Budget::default(),
+    )?;
+    let mut written = Vec::new();
+    heifers::mpeg::iprp::write_hvcc(&parsed, &mut written)?;
+    assert_eq!(hvcc_payload, written.as_slice());
+
+    let header = heif
+        .property::<heifers::mpeg::iprp::Hvcc>(item)?
+        .expect("road has an hvcC")
+        .header();
+    assert_eq!(4, header.general_profile_idc());
+    assert_eq!(60, header.general_level_idc());
+    assert_eq!(1, header.chroma_format());
+    assert_eq!(8, header.bit_depth_luma());
+    assert_eq!(4, header.length_size_minus_one() + 1);
+
+    let sets = heif.parameter_sets(item)?;
+    let derived = heifers::mpeg::iprp::Hvcc::from_parameter_sets(sets[0].1, sets[1].1, sets[2].1)?;
+    assert_eq!(
+        header.general_profile_idc(),
+        derived.header().general_profile_idc()
+    );
+    assert_eq!(
+        header.general_level_idc(),
+        derived.header().general_level_idc()
+    );
+    assert_eq!(header.chroma_format(), derived.header().chroma_format());
+    assert_eq!(header.bit_depth_luma(), derived.header().bit_depth_luma());
+    assert_eq!(
+        header.bit_depth_chroma(),
+        derived.header().bit_depth_chroma()
+    );
+    assert_eq!(
+        header.num_temporal_layers(),
+        derived.header().num_temporal_layers()
+    );
+    assert_eq!(
+        header.temporal_id_nested(),
+        derived.header().temporal_id_nested()
+    );
+    let units: Vec<&[u8]> = derived
+        .nals
+        .iter()
+        .map(|nal| nal.units[0].as_slice())
+        .collect();
+    assert_eq!(vec![sets[0].1, sets[1].1, sets[2].1], units);
+    Ok(())
+}
+
+#[test]
+fn hvcc_bit_depth() -> Result<(), Error> {
+    let heif = heifers::Heif::new(Cursor::new(&include_bytes!("data/road.heic")[..]))?;
+    let item = heif.primary_item_id();
+    let vps = heif.find_vps(item)?;
+    let mut sps = heif.find_sps(item)?;
+    let pps = heif.find_pps(item)?;
+    assert!(heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps).is_ok());
+
+    // 16-bit samples are a valid sps, but the record's three-bit fields can't hold them
+    sps.bit_depth_luma_minus8 = 8;
+    assert!(heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps).is_err());
+    sps.bit_depth_luma_minus8 = 7;
+    sps.bit_depth_chroma_minus8 = 8;
+    assert!(heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps).is_err());
+    Ok(())
+}
+
+#[test]
+fn hvcc_segmentation() -> Result<(), Error> {
+    let heif = heifers::Heif::new(Cursor::new(&include_bytes!("data/road.heic")[..]))?;
+    let item = heif.primary_item_id();
+    let vps = heif.find_vps(item)?;
+    let mut sps = heif.find_sps(item)?;
+    let pps = heif.find_pps(item)?;
+    let header = heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps)?;
+    assert_eq!(0, header.min_spatial_segmentation_idc());
+
+    {
+        let vui = sps.vui.as_mut().expect("road has a vui");
+        vui.bitstream_restriction_flag = true;
+        vui.min_spatial_segmentation_idc = 4095;
+    }
+    let header = heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps)?;
+    assert_eq!(4095, header.min_spatial_segmentation_idc());
+
+    sps.vui.as_mut().unwrap().min_spatial_segmentation_idc = 4096;
+    assert!(heifers::mpeg::iprp::HvccHeader::from_parameter_sets(&vps, &sps, &pps).is_err());
+    Ok(())
+}
+
+#[test]
+fn unsupported_essential() -> Result<(), Error> {
+    let mut bytes = include_bytes!("data/road.heic").to_vec();
+    // rename 'ispe' to something we don't understand, and mark it essential
+    assert_eq!(b"ispe", &bytes[288..292]);
+    bytes[291] = b'x';
+    assert_eq!(0x02, bytes[324]);
+    bytes[324] = 0x82;
+
+    let mut heif = heifers::Heif::new(Cursor::new(&bytes))?;
+    let item = heif.primary_item_id();
+    assert_eq!(2, heif.essential_properties(item)?.len());
+    assert_eq!(1, heif.unsupported_essential_properties(item)?.len());
+
+    let raw = heif.unknown_properties(item)?;
+    assert_eq!(1, raw.len());
+    assert_eq!(284, raw[0].offset);
+    assert_eq!(&bytes[292..304], raw[0].data.as_slice());
+
+    assert!(heif.open_item_data(Cursor::new(&bytes), item).is_err());
+    assert!(heif
+        .bit_stream(item, Cursor::new(&bytes), Vec::new())
+        .is_err());
+
+    heif.set_essential_policy(heifers::EssentialPolicy::Warn);
+    assert!(heif.ignored_essential_properties().is_empty());
+    heif.open_item_data(Cursor::new(&bytes), item)?;
+    heif.open_item_data(Cursor::new(&bytes), item)?;
+    let unknown = heifers::mpeg::FourCc::from(*b"ispx");
+    assert_eq!(
+        vec![(item, vec![unknown])],
+        heif.ignored_essential_properties()
+    );
+    Ok(())
+}
+
+fn count_start_codes(stream: &[u8]) -> usize {
+    stream.windows(4).filter(|w| [0, 0, 0, 1] == *w).count()
+}
+
+#[test]
+fn mux_road() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let road = heifers::Heif::new(Cursor::new(bytes))?;
+    let mut stream = Vec::new();
+    road.bit_stream(1, Cursor::new(bytes), &mut stream)?;
+
+    let mut muxed = Vec::new();
+    heifers::mux_hevc(Cursor::new(&stream), &mut muxed)?;
+
+    // the layout is libheif's, so only the hvcC header, which we fill in more, differs,
+    // and we mark the parameter set arrays as complete
+    assert_eq!(bytes.len(), muxed.len());
+    let hvcc = 0xb0;
+    let mut muxed_arrays = muxed.clone();
+    for &array in &[0xc7, 0xe3, 0x110] {
+        assert_eq!(bytes[array] | 0x80, muxed_arrays[array]);
+        muxed_arrays[array] = bytes[array];
+    }
+    assert_eq!(&bytes[..hvcc], &muxed[..hvcc]);
+    assert_eq!(&bytes[hvcc + 22..], &muxed_arrays[hvcc + 22..]);
+
+    let heif = heifers::Heif::new(Cursor::new(&muxed))?;
+    let item = heif.primary_item_id();
+    let ispe = heif
+        .property::<heifers::mpeg::iprp::Ispe>(item)?
+        .expect("ispe is written");
+    assert_eq!((360, 190), (ispe.width, ispe.height));
+
+    let mut round_trip = Vec::new();
+    heif.bit_stream(item, Cursor::new(&muxed), &mut round_trip)?;
+    assert_eq!(stream, round_trip);
+    Ok(())
+}
+
+#[test]
+fn stereo_pair() -> Result<(), Error> {
+    use heifers::mpeg::EntityGroup;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let with_groups = |groups: Vec<EntityGroup>| {
+        let limits = heifers::Limits::default();
+        let (file_type, mut raw) = heifers::mpeg::load_meta(Cursor::new(bytes), &limits)?;
+        raw.entity_groups.push(groups);
+        heifers::Heif::from_raw(file_type, raw, limits)
+    };
+    let ster = |group_id, entity_ids| EntityGroup {
+        grouping_type: heifers::mpeg::STER,
+        group_id,
+        entity_ids,
+    };
+
+    assert_eq!(None, with_groups(vec![])?.stereo_pair(1)?);
+
+    // left, then right
+    let heif = with_groups(vec![ster(10, vec![1, 2])])?;
+    assert_eq!(Some((1, 2)), heif.stereo_pair(1)?);
+    assert_eq!(Some((1, 2)), heif.stereo_pair(2)?);
+    assert_eq!(None, heif.stereo_pair(3)?);
+
+    let heif = with_groups(vec![ster(11, vec![1, 2, 3])])?;
+    let err = heif.stereo_pair(3).unwrap_err();
+    assert_eq!("stereo pair group 11 has 3 entities", err.to_string());
+    Ok(())
+}
+
+#[test]
+fn thumbnails() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Hvcc;
+    use heifers::mpeg::iprp::Ispe;
+    use heifers::mpeg::iprp::Property;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let road = heifers::Heif::new(Cursor::new(bytes))?;
+    let hvcc = road.property::<Hvcc>(1)?.expect("road has an hvcC").clone();
+
+    // road's own picture three more times, claiming to be thumbnails of it: 2 is 180x95,
+    // 3 is 90x48, and 4 doesn't say
+    let mut editor = heifers::Editor::new(Cursor::new(bytes))?;
+    for &(id, size) in &[(2, Some((180, 95))), (3, Some((90, 48))), (4, None)] {
+        editor.add_item(
+            heifers::mpeg::ItemInfo {
+                id,
+                protection_index: 0,
+                item_type: heifers::mpeg::HVC1,
+                item_name: String::new(),
+                content_type: None,
+                content_encoding: None,
+                item_uri_type: None,
+                hidden: false,
+            },
+            bytes[333..].to_vec(),
+        )?;
+        editor.add_property(id, Property::HvcCodecSettings(hvcc.clone()), true)?;
+        if let Some((width, height)) = size {
+            editor.add_property(id, Property::Size(Ispe { width, height }), false)?;
+        }
+        editor.add_reference(heifers::mpeg::ItemReference {
+            reference_type: heifers::mpeg::THMB,
+            from_item_id: id,
+            to_item_ids: vec![1],
+        })?;
+    }
+    // none, to begin with
+    assert!(road.thumbnails(1)?.is_empty());
+    assert_eq!(1, road.preview_item(1, 64, 64)?);
+
+    let mut file = Vec::new();
+    editor.write(Cursor::new(bytes), &mut file)?;
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+
+    assert_eq!(vec![4, 3, 2], heif.thumbnails(1)?);
+    assert!(heif.thumbnails(2)?.is_empty());
+
+    // the unsized one is never picked, and the item itself is the fallback
+    assert_eq!(3, heif.preview_item(1, 0, 0)?);
+    assert_eq!(3, heif.preview_item(1, 64, 32)?);
+    assert_eq!(2, heif.preview_item(1, 91, 48)?);
+    assert_eq!(2, heif.preview_item(1, 180, 95)?);
+    assert_eq!(1, heif.preview_item(1, 181, 95)?);
+
+    let mut preview = Vec::new();
+    assert_eq!(
+        2,
+        heif.preview_bit_stream(1, 100, 50, Cursor::new(&file), &mut preview)?
+    );
+    let mut full = Vec::new();
+    heif.bit_stream(1, Cursor::new(&file), &mut full)?;
+    assert_eq!(full, preview);
+    Ok(())
+}
+
+#[test]
+fn strip_metadata() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let mut editor = heifers::Editor::new(Cursor::new(bytes))?;
+
+    // spelt out, rather than `mpeg::EXIF`, so this checks what real files use
+    let exif = heifers::mpeg::ItemInfo {
+        id: 2,
+        protection_index: 0,
+        item_type: heifers::mpeg::FourCc::from(*b"Exif"),
+        item_name: String::new(),
+        content_type: None,
+        content_encoding: None,
+        item_uri_type: None,
+        hidden: true,
+    };
+    let xmp = heifers::mpeg::ItemInfo {
+        id: 3,
+        item_type: heifers::mpeg::MIME,
+        content_type: Some("application/rdf+xml".to_string()),
+        ..exif.clone()
+    };
+    editor.add_item(exif, b"\0\0\0\0MM\0*gps".to_vec())?;
+    editor.add_item(xmp, b"<x:xmpmeta/>".to_vec())?;
+    editor.add_reference(heifers::mpeg::ItemReference {
+        reference_type: heifers::mpeg::CDSC,
+        from_item_id: 2,
+        to_item_ids: vec![1],
+    })?;
+
+    let mut tagged = Vec::new();
+    editor.write(Cursor::new(bytes), &mut tagged)?;
+    let heif = heifers::Heif::new(Cursor::new(&tagged))?;
+    assert_eq!(3, heif.items().len());
+    assert_eq!(1, heif.references(2).len());
+
+    let mut xmp = Vec::new();
+    heif.open_item_data(Cursor::new(&tagged), 3)?
+        .read_to_end(&mut xmp)?;
+    assert_eq!(b"<x:xmpmeta/>", xmp.as_slice());
+
+    let mut editor = heifers::Editor::new(Cursor::new(&tagged))?;
+    assert_eq!(vec![2, 3], editor.remove_metadata()?);
+    let mut stripped = Vec::new();
+    editor.write(Cursor::new(&tagged), &mut stripped)?;
+
+    // the image data is copied untouched, and the iloc is rewritten to find it
+    let heif = heifers::Heif::new(Cursor::new(&stripped))?;
+    assert_eq!(1, heif.items().len());
+    assert!(heif.references(2).is_empty());
+    let mut data = Vec::new();
+    heif.open_item_data(Cursor::new(&stripped), 1)?
+        .read_to_end(&mut data)?;
+    assert_eq!(&bytes[333..], data.as_slice());
+
+    // with the metadata gone, we're back to the original layout; only the split
+    // between the iloc base offset and extent offset differs
+    assert_eq!(bytes.len(), stripped.len());
+    assert_eq!(&bytes[..0x5d], &stripped[..0x5d]);
+    assert_eq!(&bytes[0x75..], &stripped[0x75..]);
+    Ok(())
+}
+
+#[test]
+fn rotate_and_crop() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Clap;
+    use heifers::mpeg::iprp::Imir;
+    use heifers::mpeg::iprp::Irot;
+    use heifers::Rect;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let mut editor = heifers::Editor::new(Cursor::new(bytes))?;
+    editor.rotate(1, 1)?;
+    // the top half of the turned picture, which is the right half of the coded one
+    editor.crop(
+        1,
+        Rect {
+            left: 0,
+            top: 0,
+            width: 190,
+            height: 180,
+        },
+    )?;
+    editor.flip_horizontal(1)?;
+
+    let mut edited = Vec::new();
+    editor.write(Cursor::new(bytes), &mut edited)?;
+
+    let heif = heifers::Heif::new(Cursor::new(&edited))?;
+    assert_eq!(Some(&Irot { angle: 1 }), heif.property::<Irot>(1)?);
+    assert_eq!(Some(&Imir { axis: 0 }), heif.property::<Imir>(1)?);
+    assert_eq!(
+        Some(&Clap {
+            width_n: 180,
+            width_d: 1,
+            height_n: 190,
+            height_d: 1,
+            horiz_off_n: 90,
+            horiz_off_d: 1,
+            vert_off_n: 0,
+            vert_off_d: 1,
+        }),
+        heif.property::<Clap>(1)?
+    );
+
+    let transform = heifers::Transform::from_properties(360, 190, heif.properties(1)?)?;
+    assert_eq!((190, 180), transform.display_size());
+
+    // the coded data isn't touched
+    let mut original = Vec::new();
+    heifers::Heif::new(Cursor::new(bytes))?.bit_stream(1, Cursor::new(bytes), &mut original)?;
+    let mut rotated = Vec::new();
+    heif.bit_stream(1, Cursor::new(&edited), &mut rotated)?;
+    assert_eq!(original, rotated);
+
+    // undoing the orientation leaves only the crop
+    let mut editor = heifers::Editor::new(Cursor::new(&edited))?;
+    editor.flip_vertical(1)?;
+    editor.rotate(1, 1)?;
+    let mut edited_again = Vec::new();
+    editor.write(Cursor::new(&edited), &mut edited_again)?;
+
+    let heif = heifers::Heif::new(Cursor::new(&edited_again))?;
+    assert!(heif.property::<Irot>(1)?.is_none());
+    assert!(heif.property::<Imir>(1)?.is_none());
+    assert!(heif.property::<Clap>(1)?.is_some());
+    Ok(())
+}
+
+// a file with just one image item, with its data in the idat
+fn single_item(
+    major_brand: heifers::mpeg::FourCc,
+    item_type: heifers::mpeg::FourCc,
+    codec: &[heifers::mpeg::iprp::Property],
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let extent = heifers::mpeg::Extent {
+        index: 0,
+        offset: 0,
+        length: u64(data.len()),
+    };
+    single_item_in_extents(major_brand, item_type, codec, data, 1, vec![extent])
+}
+
+// as `single_item`, with the item made of these extents: of the idat, for construction
+// method 1, or of the file, for 0
+fn single_item_in_extents(
+    major_brand: heifers::mpeg::FourCc,
+    item_type: heifers::mpeg::FourCc,
+    codec: &[heifers::mpeg::iprp::Property],
+    data: &[u8],
+    construction_method: u8,
+    extents: Vec<heifers::mpeg::Extent>,
+) -> Result<Vec<u8>, Error> {
+    use cast::u16;
+    use heifers::mpeg;
+    use heifers::mpeg::iprp::Association;
+    use heifers::mpeg::iprp::Ispe;
+    use heifers::mpeg::iprp::ItemPropertyAssociation;
+    use heifers::mpeg::iprp::Property;
+    use heifers::mpeg::write;
+
+    let mut file = Vec::new();
+    write::write_ftyp(
+        &mut file,
+        &mpeg::FileType {
+            major_brand,
+            minor_version: 0,
+            brands: vec![mpeg::MIF1, mpeg::MIAF, major_brand],
+        },
+    )?;
+    write::write_full_box(&mut file, mpeg::META, 0, 0, |into| {
+        write::write_hdlr(into, mpeg::PICT)?;
+        write::write_pitm(into, 1)?;
+        // in the idat, so nothing depends on the size of the meta
+        write::write_iloc(
+            into,
+            &[mpeg::ItemLoc {
+                id: 1,
+                construction_method,
+                data_reference_index: 0,
+                base_offset: 0,
+                extents,
+            }],
+        )?;
+        write::write_iinf(
+            into,
+            &[mpeg::ItemInfo {
+                id: 1,
+                protection_index: 0,
+                item_type,
+                item_name: String::new(),
+                content_type: None,
+                content_encoding: None,
+                item_uri_type: None,
+                hidden: false,
+            }],
+        )?;
+        // the codec configuration, if any, is essential; the size is descriptive
+        let mut properties = vec![Property::Size(Ispe {
+            width: 64,
+            height: 48,
+        })];
+        properties.extend(codec.iter().cloned());
+        let associations = (0..properties.len())
+            .map(|i| Association {
+                essential: 0 != i,
+                property_index: u16(i + 1).unwrap(),
+            })
+            .collect();
+        write::write_iprp(
+            into,
+            &properties,
+            &[ItemPropertyAssociation {
+                item_id: 1,
+                associations,
+            }],
+        )?;
+        write::write_box(into, mpeg::IDAT, |into| {
+            into.extend_from_slice(data);
+            Ok(())
+        })
+    })?;
+    Ok(file)
+}
+
+#[test]
+fn sequence_brands() -> Result<(), Error> {
+    use heifers::mpeg::Structure;
+
+    // only the sequence brands, but with a meta holding an image item
+    let mut file = single_item(heifers::mpeg::MIF1, heifers::mpeg::UNCI, &[], b"anything")?;
+    assert_eq!(b"ftypmif1", &file[4..12]);
+    file[8..12].copy_from_slice(b"msf1");
+    file[16..28].copy_from_slice(b"msf1hevciso8");
+
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    assert_eq!(Structure::IMAGE_SEQUENCE, heif.file_type().structure());
+    assert_eq!(1, heif.items().len());
+
+    // without a meta there's nothing we can read
+    let ftyp_only = &file[..28];
+    let err = heifers::Heif::new(Cursor::new(ftyp_only)).unwrap_err();
+    assert_eq!(heifers::ErrorKind::Unsupported, err.kind);
+    Ok(())
+}
+
+#[test]
+fn avif() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Av1c;
+    use heifers::mpeg::iprp::Property;
+
+    let av1c = Av1c {
+        seq_profile: 0,
+        seq_level_idx_0: 8,
+        seq_tier_0: false,
+        high_bitdepth: true,
+        twelve_bit: false,
+        monochrome: false,
+        chroma_subsampling_x: true,
+        chroma_subsampling_y: true,
+        chroma_sample_position: 0,
+        initial_presentation_delay_minus_one: None,
+        config_obus: vec![0x0a, 0x02, 0xaa, 0xbb],
+    };
+    // a frame OBU, with the size field left off, as the last OBU of a sample may; it's framed
+    // properly, but doesn't hold a real picture
+    let file = single_item(
+        heifers::mpeg::AVIF,
+        heifers::mpeg::AV01,
+        &[Property::Av1CodecSettings(av1c.clone())],
+        &[0x30, 0x01, 0x02, 0x03],
+    )?;
+
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    assert_eq!(heifers::mpeg::AV01, heif.item_info(1)?.item_type);
+    assert_eq!(Some(&av1c), heif.property::<Av1c>(1)?);
+    assert_eq!(10, av1c.bit_depth());
+    assert_eq!(1, heif.preferred_alternative(1));
+
+    let mut stream = Vec::new();
+    heif.bit_stream(1, Cursor::new(&file), &mut stream)?;
+    assert_eq!(
+        &[0x12, 0x00, 0x0a, 0x02, 0xaa, 0xbb, 0x32, 0x03, 0x01, 0x02, 0x03][..],
+        stream.as_slice()
+    );
+
+    // the editor writes av1C back as it was read
+    let mut rewritten = Vec::new();
+    heifers::Editor::new(Cursor::new(&file))?.write(Cursor::new(&file), &mut rewritten)?;
+    let heif = heifers::Heif::new(Cursor::new(&rewritten))?;
+    assert_eq!(Some(&av1c), heif.property::<Av1c>(1)?);
+    let mut again = Vec::new();
+    heif.bit_stream(1, Cursor::new(&rewritten), &mut again)?;
+    assert_eq!(stream, again);
+    Ok(())
+}
+
+#[test]
+fn jpeg() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Jpgc;
+    use heifers::mpeg::iprp::Property;
+
+    // the start of image and a comment are shared; the item holds the end of image
+    let jpgc = Jpgc {
+        prefix: vec![0xff, 0xd8, 0xff, 0xfe, 0x00, 0x04, b'h', b'i'],
+    };
+    let file = single_item(
+        heifers::mpeg::HEIC,
+        heifers::mpeg::JPEG,
+        &[Property::JpegPrefix(jpgc.clone())],
+        &[0xff, 0xd9],
+    )?;
+
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    assert_eq!(Some(&jpgc), heif.property::<Jpgc>(1)?);
+
+    let mut jfif = Vec::new();
+    heif.open_jpeg(Cursor::new(&file), 1)?
+        .read_to_end(&mut jfif)?;
+    assert_eq!(
+        &[0xff, 0xd8, 0xff, 0xfe, 0x00, 0x04, b'h', b'i', 0xff, 0xd9][..],
+        jfif.as_slice()
+    );
+
+    let mut stream = Vec::new();
+    heif.bit_stream(1, Cursor::new(&file), &mut stream)?;
+    assert_eq!(jfif, stream);
+
+    // the item data itself is only what's stored
+    let mut stored = Vec::new();
+    heif.open_item_data(Cursor::new(&file), 1)?
+        .read_to_end(&mut stored)?;
+    assert_eq!(&[0xff, 0xd9], stored.as_slice());
+
+    // items without a jpgC are complete on their own
+    let file = single_item(heifers::mpeg::HEIC, heifers::mpeg::JPEG, &[], &jfif)?;
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    let mut alone = Vec::new();
+    heif.open_jpeg(Cursor::new(&file), 1)?
+        .read_to_end(&mut alone)?;
+    assert_eq!(jfif, alone);
+    Ok(())
+}
+
+#[test]
+fn uncompressed() -> Result<(), Error> {
+    use heifers::mpeg::iprp::Property;
+    use heifers::mpeg::unci;
+    use heifers::mpeg::unci::Uncc;
+
+    // a 64x48 rgb3 picture: red counts along each row, green down each column
+    let mut data = Vec::new();
+    for y in 0..48u8 {
+        for x in 0..64u8 {
+            data.extend_from_slice(&[x, y, 7]);
+        }
+    }
+
+    let file = single_item(
+        heifers::mpeg::MIF1,
+        heifers::mpeg::UNCI,
+        &[Property::UncompressedConfig(Uncc::from_profile(
+            unci::RGB3,
+        )?)],
+        &data,
+    )?;
+
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    let picture = heif.decode_uncompressed(Cursor::new(&file), 1)?;
+    assert_eq!((64, 48), (picture.width, picture.height));
+    assert_eq!(3, picture.planes.len());
+    let red = picture.plane(unci::COMPONENT_RED).unwrap();
+    let green = picture.plane(unci::COMPONENT_GREEN).unwrap();
+    assert_eq!((63, 10), (red.get(63, 10), green.get(63, 10)));
+    assert!(picture
+        .plane(unci::COMPONENT_BLUE)
+        .unwrap()
+        .samples
+        .iter()
+        .all(|&sample| 7 == sample));
+
+    // the editor keeps the version 1 uncC as it was
+    let mut rewritten = Vec::new();
+    heifers::Editor::new(Cursor::new(&file))?.write(Cursor::new(&file), &mut rewritten)?;
+    let heif = heifers::Heif::new(Cursor::new(&rewritten))?;
+    assert_eq!(
+        picture,
+        heif.decode_uncompressed(Cursor::new(&rewritten), 1)?
+    );
+    Ok(())
+}
+
+#[test]
+fn validate() -> Result<(), Error> {
+    use heifers::Category;
+    use heifers::Severity;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let (heif, report) = heifers::validate(Cursor::new(bytes));
+    assert!(heif.is_some());
+    assert!(report.problems.is_empty(), "{:?}", report);
+
+    // cut off the end of the mdat, and point the ispe association past the end of the ipco
+    let mut broken = bytes[..bytes.len() - 100].to_vec();
+    let ipma = broken
+        .windows(4)
+        .position(|window| b"ipma" == window)
+        .unwrap()
+        + 4;
+    assert_eq!(
+        &[0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 2, 0x81, 0x02],
+        &broken[ipma..ipma + 13]
+    );
+    broken[ipma + 12] = 0x05;
+
+    assert!(heifers::Heif::new(Cursor::new(&broken)).is_err());
+
+    let (heif, report) = heifers::validate(Cursor::new(&broken));
+    let heif = heif.expect("salvaged");
+    assert_eq!(1, heif.items().len());
+    assert!(!report.is_conformant());
+
+    let mut categories: Vec<(Severity, Category)> = report
+        .problems
+        .iter()
+        .map(|problem| (problem.severity, problem.category))
+        .collect();
+    categories.sort_by_key(|&(severity, category)| (severity, format!("{:?}", category)));
+    assert_eq!(
+        vec![
+            (Severity::Error, Category::Association),
+            (Severity::Error, Category::Location),
+            (Severity::Error, Category::Missing),
+            (Severity::Error, Category::Structure),
+        ],
+        categories,
+        "{:#?}",
+        report
+    );
+    Ok(())
+}
+
+#[test]
+fn validate_huge_box() {
+    use heifers::Category;
+
+    // an ftyp, then a free box with a largesize running far past the end of the file
+    let mut file = Vec::new();
+    file.extend_from_slice(b"\0\0\0\x18ftypmif1\0\0\0\0mif1heic");
+    file.extend_from_slice(b"\0\0\0\x01free\xff\xff\xff\xff\xff\xff\xff\xf0");
+    assert_eq!(40, file.len());
+
+    let (heif, report) = heifers::validate(Cursor::new(&file));
+    assert!(heif.is_none());
+    assert!(report
+        .problems
+        .iter()
+        .any(|problem| Category::Structure == problem.category
+            && problem
+                .message
+                .contains("claims 18446744073709551600 bytes")));
+}
+
+#[test]
+fn limits() -> Result<(), Error> {
+    use heifers::LimitExceeded;
+    use heifers::Limits;
+
+    let limit_of = |err: Error| {
+        err.iter_chain()
+            .filter_map(|cause| cause.downcast_ref::<LimitExceeded>())
+            .map(|e| e.limit)
+            .next()
+    };
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let tight = Limits {
+        max_box_size: 64,
+        ..Limits::default()
+    };
+    let err = heifers::Heif::with_limits(Cursor::new(bytes), tight).unwrap_err();
+    assert_eq!(heifers::ErrorKind::LimitExceeded, err.kind);
+    assert_eq!("meta", err.path_string());
+    assert_eq!(Some("max_box_size"), limit_of(err.into()));
+
+    let tight = Limits {
+        max_total_allocation: 64,
+        ..Limits::default()
+    };
+    let err = heifers::Heif::with_limits(Cursor::new(bytes), tight).unwrap_err();
+    assert_eq!(Some("max_total_allocation"), limit_of(err.into()));
+
+    let tight = Limits {
+        max_nal_size: 1024,
+        ..Limits::default()
+    };
+    let heif = heifers::Heif::with_limits(Cursor::new(bytes), tight)?;
+    let item = heif.primary_item_id();
+    let err = heif
+        .bit_stream(item, Cursor::new(bytes), &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(Some("max_nal_size"), limit_of(err.into()));
+
+    let tight = Limits {
+        // road is 360x190
+        max_image_pixels: 320 * 180,
+        ..Limits::default()
+    };
+    let heif = heifers::Heif::with_limits(Cursor::new(bytes), tight)?;
+    let err = heif.find_sps(item).unwrap_err();
+    assert_eq!(Some("max_image_pixels"), limit_of(err));
+    Ok(())
+}
+
+#[test]
+fn error_context() -> Result<(), Error> {
+    use heifers::ErrorKind;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let iloc = bytes
+        .windows(4)
+        .position(|window| b"iloc" == window)
+        .expect("road has an iloc");
+
+    let mut future = bytes.to_vec();
+    future[iloc + 4] = 7;
+    let err = heifers::Heif::new(Cursor::new(&future)).unwrap_err();
+    assert_eq!(ErrorKind::Unsupported, err.kind);
+    assert_eq!("meta/iloc", err.path_string());
+    // stopped just after the version and flags
+    assert_eq!(Some(u64(iloc) + 8), err.offset);
+
+    let err = heifers::Heif::new(Cursor::new(&bytes[..iloc + 10])).unwrap_err();
+    assert_eq!(ErrorKind::Truncated, err.kind);
+    assert_eq!("meta/iloc", err.path_string());
+
+    let err = match heifers::Editor::new(Cursor::new(&future)) {
+        Ok(_) => panic!("the editor read an unsupported iloc"),
+        Err(err) => err,
+    };
+    assert_eq!(ErrorKind::Unsupported, err.kind);
+    assert_eq!("meta/iloc", err.path_string());
+    Ok(())
+}
+
+#[test]
+fn item_error_kinds() -> Result<(), Error> {
+    use heifers::ErrorKind;
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let err = heif
+        .open_item_data(Cursor::new(bytes), 7)
+        .err()
+        .expect("there's no item 7");
+    assert_eq!(ErrorKind::InvalidArgument, err.kind);
+    assert_eq!("invalid argument: invalid item id: 7", err.to_string());
+    let err = heif
+        .decode_uncompressed(Cursor::new(bytes), item)
+        .unwrap_err();
+    assert_eq!(ErrorKind::InvalidArgument, err.kind);
+    let err = heif
+        .open_jpeg(Cursor::new(bytes), item)
+        .err()
+        .expect("road isn't a jpeg");
+    assert_eq!(ErrorKind::InvalidArgument, err.kind);
+
+    // the item data is cut off inside the length of its first NAL unit
+    let err = heif
+        .bit_stream(item, Cursor::new(&bytes[..335]), &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(ErrorKind::Truncated, err.kind);
+
+    let mut editor = heifers::Editor::new(Cursor::new(bytes))?;
+    assert_eq!(
+        ErrorKind::InvalidArgument,
+        editor.remove_item(item).unwrap_err().kind
+    );
+    assert_eq!(
+        ErrorKind::InvalidArgument,
+        editor.remove_item(7).unwrap_err().kind
+    );
+    let outside = heifers::Rect {
+        left: 0,
+        top: 0,
+        width: 361,
+        height: 190,
+    };
+    assert_eq!(
+        ErrorKind::InvalidArgument,
+        editor.crop(item, outside).unwrap_err().kind
+    );
+    Ok(())
+}
+
+#[test]
+fn extents() -> Result<(), Error> {
+    use heifers::mpeg::Extent;
+
+    let extent = |offset, length| Extent {
+        index: 0,
+        offset,
+        length,
+    };
+
+    let split = single_item_in_extents(
+        heifers::mpeg::MIF1,
+        heifers::mpeg::MIME,
+        &[],
+        b"helloworld!",
+        1,
+        vec![extent(5, 6), extent(0, 5)],
+    )?;
+    let heif = heifers::Heif::new(Cursor::new(&split))?;
+    let mut data = Vec::new();
+    heif.open_item_data(Cursor::new(&split), 1)?
+        .read_to_end(&mut data)?;
+    assert_eq!(b"world!hello", data.as_slice());
+
+    // a zero length after the first extent used to panic
+    let broken = single_item_in_extents(
+        heifers::mpeg::MIF1,
+        heifers::mpeg::MIME,
+        &[],
+        b"hello",
+        1,
+        vec![extent(0, 5), extent(5, 0)],
+    )?;
+    let heif = heifers::Heif::new(Cursor::new(&broken))?;
+    assert!(heif.open_item_data(Cursor::new(&broken), 1).is_err());
+    Ok(())
+}
+
+#[test]
+fn zero_length_extent() -> Result<(), Error> {
+    use heifers::mpeg::Extent;
+
+    // a zero length would mean "to the end of the file", which isn't supported
+    let file = single_item_in_extents(
+        heifers::mpeg::MIF1,
+        heifers::mpeg::JPEG,
+        &[],
+        b"hello",
+        1,
+        vec![Extent {
+            index: 0,
+            offset: 0,
+            length: 0,
+        }],
+    )?;
+    let heif = heifers::Heif::new(Cursor::new(&file))?;
+    assert_eq!(0, heif.items()[0].data_size);
+    assert!(heif.open_item_data(Cursor::new(&file), 1).is_err());
+    assert!(heif.open_jpeg(Cursor::new(&file), 1).is_err());
+    Ok(())
+}
+
+// the same file through the async API, with every other read and seek left pending
+#[cfg(feature = "tokio")]
+#[test]
+fn road_async() -> Result<(), Error> {
+    use std::pin::Pin;
+    use std::task::Context;
+    use std::task::Poll;
+
+    use tokio::io::AsyncRead;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncSeek;
+    use tokio::io::ReadBuf;
+
+    struct Stutter<R> {
+        inner: R,
+        ready: bool,
+    }
+
+    impl<R> Stutter<R> {
+        // pending, after waking, every other time
+        fn stall(&mut self, cx: &mut Context) -> bool {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+            }
+            !self.ready
+        }
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for Stutter<R> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<std::io::Result<()>> {
+            if self.stall(cx) {
+                return Poll::Pending;
+            }
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<R: AsyncSeek + Unpin> AsyncSeek for Stutter<R> {
+        fn start_seek(mut self: Pin<&mut Self>, pos: std::io::SeekFrom) -> std::io::Result<()> {
+            Pin::new(&mut self.inner).start_seek(pos)
+        }
+
+        fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<u64>> {
+            if self.stall(cx) {
+                return Poll::Pending;
+            }
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    let stutter = |bytes| Stutter {
+        inner: Cursor::new(bytes),
+        ready: false,
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+
+    let loaded = runtime.block_on(heifers::Heif::new_async(stutter(bytes)))?;
+    assert_eq!(heif.primary_item_id(), loaded.primary_item_id());
+    assert_eq!(heif.items().len(), loaded.items().len());
+
+    let item = heif.primary_item_id();
+    let mut expected = Vec::new();
+    heif.open_item_data(Cursor::new(bytes), item)?
+        .read_to_end(&mut expected)?;
+    let mut data = Vec::new();
+    runtime.block_on(
+        loaded
+            .open_item_data_async(stutter(bytes), item)?
+            .read_to_end(&mut data),
+    )?;
+    assert_eq!(expected, data);
+
+    // the whole 'meta' is read before it's parsed, so this is found before reaching the iloc
+    let iloc = bytes
+        .windows(4)
+        .position(|window| b"iloc" == window)
+        .expect("road has an iloc");
+    let err = runtime
+        .block_on(heifers::Heif::new_async(stutter(&bytes[..iloc + 10])))
+        .unwrap_err();
+    assert_eq!(heifers::ErrorKind::Truncated, err.kind);
+
+    let split = single_item_in_extents(
+        heifers::mpeg::MIF1,
+        heifers::mpeg::MIME,
+        &[],
+        b"helloworld!",
+        1,
+        vec![
+            heifers::mpeg::Extent {
+                index: 0,
+                offset: 5,
+                length: 6,
+            },
+            heifers::mpeg::Extent {
+                index: 0,
+                offset: 0,
+                length: 5,
+            },
+        ],
+    )?;
+    let heif = runtime.block_on(heifers::Heif::new_async(stutter(&split)))?;
+    let mut data = Vec::new();
+    runtime.block_on(
+        heif.open_item_data_async(stutter(&split), 1)?
+            .read_to_end(&mut data),
+    )?;
+    assert_eq!(b"world!hello", data.as_slice());
+    Ok(())
+}
+
+#[test]
+fn byte_source() -> Result<(), Error> {
+    use heifers::mpeg;
+    use heifers::mpeg::write;
+    use heifers::ByteSource;
+
+    struct Counting {
+        bytes: Vec<u8>,
+        requests: Vec<(u64, u64)>,
+    }
+
+    impl ByteSource for Counting {
+        fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+            self.requests.push((offset, len));
+            self.bytes.read_at(offset, len)
+        }
+    }
+
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let mut road = Counting {
+        bytes: bytes.to_vec(),
+        requests: Vec::new(),
+    };
+    let heif = heifers::Heif::from_source(&mut road)?;
+    assert_eq!(1, road.requests.len());
+
+    let item = heif.primary_item_id();
+    let mut expected = Vec::new();
+    heif.open_item_data(Cursor::new(bytes), item)?
+        .read_to_end(&mut expected)?;
+    assert_eq!(expected, heif.read_item_data_from(&mut road, item)?);
+    assert_eq!(
+        (2, u64(expected.len())),
+        (road.requests.len(), road.requests[1].1)
+    );
+
+    // an item in three pieces of a large 'free' box before the 'meta': two close together
+    // and one far away, listed out of order
+    let extent = |offset, length| mpeg::Extent {
+        index: 0,
+        offset,
+        length,
+    };
+    let extents_at = |start| {
+        vec![
+            extent(start + 200_000, 4),
+            extent(start, 4),
+            extent(start + 20, 4),
+        ]
+    };
+    let probe = single_item_in_extents(mpeg::MIF1, mpeg::MIME, &[], &[], 0, extents_at(0))?;
+    let ftyp_len = mpeg::read_header(&probe[..])?.size;
+    let padding_start = ftyp_len + 8;
+    let file = single_item_in_extents(
+        mpeg::MIF1,
+        mpeg::MIME,
+        &[],
+        &[],
+        0,
+        extents_at(padding_start),
+    )?;
+
+    let padding: Vec<u8> = (0..256 * 1024)
+        .map(|i: u32| cast::u8(i % 251).unwrap())
+        .collect();
+    let mut padded = file[..usize(ftyp_len)].to_vec();
+    write::write_box(&mut padded, mpeg::FREE, |into| {
+        into.extend_from_slice(&padding);
+        Ok(())
+    })?;
+    padded.extend_from_slice(&file[usize(ftyp_len)..]);
+
+    let mut source = Counting {
+        bytes: padded,
+        requests: Vec::new(),
+    };
+    let heif = heifers::Heif::from_source(&mut source)?;
+    // the start of the file, then the 'meta'; never the padding in between
+    assert_eq!(2, source.requests.len());
+    assert!(source.requests.iter().map(|&(_, len)| len).sum::<u64>() < 64 * 1024);
+
+    let data = heif.read_item_data_from(&mut source, 1)?;
+    let mut expected = padding[200_000..200_004].to_vec();
+    expected.extend_from_slice(&padding[..4]);
+    expected.extend_from_slice(&padding[20..24]);
+    assert_eq!(expected, data);
+    assert_eq!(
+        &[(padding_start, 24), (padding_start + 200_000, 4)],
+        &source.requests[2..]
+    );
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let heif = heifers::Heif::new(Cursor::new(bytes))?;
+    let item = heif.primary_item_id();
+
+    let file_type = serde_json::to_value(heif.file_type())?;
+    assert_eq!(
+        serde_json::json!({
+            "major_brand": "heic",
+            "minor_version": 0,
+            "brands": ["mif1", "heic"],
+        }),
+        file_type
+    );
+
+    let info = serde_json::to_value(heif.item_info(item)?)?;
+    assert_eq!("hvc1", info["item_type"]);
+    let location = serde_json::to_value(heif.item_location(item)?)?;
+    assert_eq!(1, location["extents"].as_array().map_or(0, Vec::len));
+
+    let properties = serde_json::to_value(heif.properties(item)?)?;
+    assert!(properties
+        .as_array()
+        .expect("a list")
+        .iter()
+        .any(|property| property.get("HvcCodecSettings").is_some()));
+    assert_eq!(
+        serde_json::json!({ "Size": { "width": 360, "height": 190 } }),
+        properties[1]
+    );
+
+    let sps = serde_json::to_value(heif.find_sps(item)?)?;
+    assert_eq!(360, sps["pic_width_in_luma_samples"]);
+    assert_eq!(4, sps["profile_tier_level"]["general_profile_idc"]);
+    let pps = serde_json::to_value(heif.find_pps(item)?)?;
+    assert!(pps["flags"].is_u64());
+    Ok(())
+}
+
+#[test]
+fn box_dump() -> Result<(), Error> {
+    let bytes = &include_bytes!("data/road.heic")[..];
+    let boxes = heifers::mpeg::tree::read_tree(bytes, &heifers::Limits::default())?;
+    assert_eq!(
+        vec![
+            heifers::mpeg::FTYP,
+            heifers::mpeg::META,
+            heifers::mpeg::MDAT
+        ],
+        boxes
+            .iter()
+            .map(|node| node.header.box_type)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(333, boxes[2].offset + 8);
+
+    let expected = include_str!("data/road.heif-info.txt");
+    let dumped = heifers::mpeg::tree::dump(&boxes)?;
+    for (line, (expected, dumped)) in expected.lines().zip(dumped.lines()).enumerate() {
+        assert_eq!(expected, dumped, "line {}", line + 1);
+    }
+    assert_eq!(expected, dumped);
+
+    assert!(heifers::mpeg::tree::read_tree(&bytes[..100], &heifers::Limits::default()).is_err());
+    Ok(())
+}